@@ -1,69 +1,50 @@
 use jsontp::client::*;
 
-/*
-format:
-    {
-        "type": "post", // or list, or comment
-        "group": "comp.lang.rust", // group name
-
-        if (type == "post") {
-            "post": {
-                "subject": "This is a subject",
-                "body": "This is a body",
-            },
-
-            "author": {
-                "username": "username",
-                "password": "password",
-                "email": "email",
-            }
-        }
+use serde::de::DeserializeOwned;
 
-        if (type == "comment") {
-            "parent": {
-                "id": "1234",
-            },
-
-            "comment": {
-                "body": "This is a comment",
-            },
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard};
 
-            "author": {
-                "username": "username",
-                "password": "password",
-                "email": "email",
-            }
-        }
-    }
+use protocol::{
+    ApproveRequest, ApproveUserRequest, Author, CancelRequest, ChangeEmailRequest,
+    ChangePasswordRequest, CommentContent, CommentRequest, ConfigureGroupRequest,
+    CreateGroupRequest, CreateInvitesRequest, Credentials, DeleteAccountRequest, EditRequest,
+    ErrorBody, GroupsResponse, HistoryRequest, HistoryResponse, InvitesRequest, InvitesResponse,
+    ListGroupsRequest, LoginRequest, LogoutRequest, MarkReadRequest, NnntpRequest, Parent,
+    PendingUsersRequest, PendingUsersResponse, PostContent, PostRequest, QueueRequest,
+    QueueResponse, RejectRequest, RoleRequest, SearchResponse, Session, SubscribeRequest,
+    SubscriptionsRequest, SubscriptionsResponse, WatchRequest, WatchResponse, BODY_KEY, ERROR_KEY,
+};
 
-    it then returns the new post id (example: 1234) or the list of posts (example: [{id: 1234, subject: "This is a subject", body: "This is a body", author: "username"}]
-*/
+// the request and response format lives in the `protocol` crate, shared with the server
+pub use protocol::{
+    Article, Comment, Group, HeldPost, Invite, ListRequest, ListResponse, NewArticle,
+    NewUserRequest, Post, Revision, Role, SearchHit, SearchRequest, Subscription, UnreadCount,
+    UserCreated,
+};
 
-#[derive(Debug, Clone)]
-pub struct Post {
-    pub id: i32,
-    pub subject: String,
-    pub body: String,
-    pub author: String,
-    pub author_email: Option<String>,
-
-    pub comments: Vec<Comment>,
-}
+mod thread;
 
-#[derive(Debug, Clone)]
-pub struct Comment {
-    pub body: String,
-    pub author: String,
-    pub author_email: Option<String>,
-}
+pub use thread::Thread;
 
 #[derive(Debug, Clone)]
 pub struct Posts {
+    /// oldest first, each tagged with the group it was posted to
     pub posts: Vec<Post>,
 
+    /// the group, or wildmat, that was listed
     pub group: String,
 }
 
+impl Posts {
+    /// the posts of one of the listed groups
+    pub fn in_group<'a>(&'a self, group: &'a str) -> impl Iterator<Item = &'a Post> {
+        self.posts
+            .iter()
+            .filter(move |post| post.group_name == group)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct User {
     pub username: String,
@@ -76,14 +57,102 @@ impl User {
     pub fn new<T: ToString>(username: T, email: Option<T>, password: T) -> User {
         User {
             username: username.to_string(),
-            email: match email {
-                Some(email) => Some(email.to_string()),
-                None => None,
-            },
+            email: email.map(|email| email.to_string()),
             password: password.to_string(),
         }
     }
+}
+
+/// an iterator over the posts of `/list`, see [`ServerConnection::pages`]
+pub struct Pages<'a> {
+    server: &'a ServerConnection,
+    // the request for the next page, none once the last page has been fetched
+    request: Option<ListRequest>,
+    posts: VecDeque<Post>,
+}
+
+impl Iterator for Pages<'_> {
+    type Item = Result<Post, String>;
+
+    fn next(&mut self) -> Option<Result<Post, String>> {
+        while self.posts.is_empty() {
+            let request = self.request.take()?;
+
+            let page = match self.server.list_page(request.clone()) {
+                Ok(page) => page,
+                Err(e) => return Some(Err(e)),
+            };
+
+            // an offset only applies to the first page, the cursor takes over after that
+            self.request = page.next.map(|after_id| ListRequest {
+                after_id: Some(after_id),
+                offset: None,
+                ..request
+            });
+            self.posts = page.posts.into();
+        }
+
+        self.posts.pop_front().map(Ok)
+    }
+}
 
+/// an endless, blocking iterator over the articles arriving in some groups, see
+/// [`ServerConnection::watch`]. it ends after the first error
+pub struct Watch<'a> {
+    server: &'a ServerConnection,
+    // the watched group or wildmat, none for the user's subscriptions
+    group: Option<String>,
+    // where the previous response left off, none before the first request
+    after: Option<i64>,
+    articles: VecDeque<NewArticle>,
+    failed: bool,
+}
+
+impl Watch<'_> {
+    fn poll(&mut self) -> Result<WatchResponse, String> {
+        let request = |reader: Option<Credentials>| {
+            NnntpRequest::Watch(WatchRequest {
+                group: self.group.clone(),
+                reader,
+                after: self.after,
+                timeout: None,
+            })
+        };
+
+        let response = match self.group {
+            Some(_) => check(self.server.send("/watch", &request(None))?)?,
+            None => self
+                .server
+                .send_as_author("/watch", |author| request(Some(author.credentials)))?,
+        };
+
+        payload(&response)
+    }
+}
+
+impl Iterator for Watch<'_> {
+    type Item = Result<NewArticle, String>;
+
+    fn next(&mut self) -> Option<Result<NewArticle, String>> {
+        while self.articles.is_empty() {
+            if self.failed {
+                return None;
+            }
+
+            match self.poll() {
+                Ok(response) => {
+                    self.after = Some(response.next);
+                    self.articles = response.articles.into();
+                }
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+
+        self.articles.pop_front().map(Ok)
+    }
 }
 
 pub struct ServerConnection {
@@ -91,314 +160,694 @@ pub struct ServerConnection {
     pub port: u16,
 
     pub user: Option<User>,
+
+    // the token from /login, used instead of the password once logged in
+    session: Mutex<Option<String>>,
 }
+
+// turns failed responses into errors, preferring the server's own message
+fn check(response: JsontpResponse) -> Result<JsontpResponse, String> {
+    match response.status.code {
+        200 => Ok(response),
+        400 => Err("Invalid request".to_string()),
+        401 => Err("Unauthorized".to_string()),
+        _ => match response.body.other.get(ERROR_KEY) {
+            Some(error) => match serde_json::from_value::<ErrorBody>(error.clone()) {
+                Ok(error) => Err(error.message),
+                Err(_) => Err("Unknown error".to_string()),
+            },
+            None => Err("Unknown error".to_string()),
+        },
+    }
+}
+
+// the `nnntp` payload of a successful response
+fn payload<T: DeserializeOwned>(response: &JsontpResponse) -> Result<T, String> {
+    match response.body.other.get(BODY_KEY) {
+        Some(payload) => {
+            serde_json::from_value(payload.clone()).map_err(|_| "Invalid response".to_string())
+        }
+        None => Err("Invalid response".to_string()),
+    }
+}
+
 impl ServerConnection {
     pub fn new<T: ToString>(host: T, port: u16, user: Option<User>) -> ServerConnection {
         ServerConnection {
             host: host.to_string(),
             port,
-            user
+            user,
+            session: Mutex::new(None),
         }
     }
 
-    pub fn post<T: ToString>(&self, group: T, subject: T, body: T) -> Result<(), String> {
-        let author = match self.user.clone() {
-            Some(author) => author,
-            None => return Err("No user provided".to_string()),
-        };
+    fn session(&self) -> MutexGuard<'_, Option<String>> {
+        self.session.lock().unwrap_or_else(|e| e.into_inner())
+    }
 
-        let client = Request::new()
-            .body_key(
-                "nnntp",
-                Value::Object(
-                    [
-                        ("type".to_string(), Value::String("post".to_string())),
-                        ("group".to_string(), Value::String(group.to_string())),
-                        (
-                            "post".to_string(),
-                            Value::Object(
-                                [
-                                    ("subject".to_string(), Value::String(subject.to_string())),
-                                    ("body".to_string(), Value::String(body.to_string())),
-                                ]
-                                .iter()
-                                .cloned()
-                                .collect(),
-                            ),
-                        ),
-                        (
-                            "author".to_string(),
-                            Value::Object(
-                                [
-                                    (
-                                        "username".to_string(),
-                                        Value::String(author.username.clone()),
-                                    ),
-                                    (
-                                        "password".to_string(),
-                                        Value::String(author.password.clone()),
-                                    ),
-                                    (
-                                        "email".to_string(),
-                                        Value::String(
-                                            author
-                                                .email
-                                                .clone()
-                                                .unwrap_or("no_email@provided.com".to_string()),
-                                        ),
-                                    ),
-                                ]
-                                .iter()
-                                .cloned()
-                                .collect(),
-                            ),
-                        ),
-                    ]
-                    .iter()
-                    .cloned()
-                    .collect(),
-                ),
-            )
-            .resource("/post");
-
-        let response = client.send(self.host.clone(), self.port).unwrap();
-
-        match response.status.code {
-            200 => Ok(()),
-            400 => Err("Invalid request".to_string()),
-            401 => Err("Unauthorized".to_string()),
-            _ => Err("Unknown error".to_string()),
-        }
+    fn send(&self, resource: &str, request: &NnntpRequest) -> Result<JsontpResponse, String> {
+        Request::new()
+            .body_key(BODY_KEY, request.to_value())
+            .resource(resource)
+            .send(self.host.clone(), self.port)
     }
 
-    pub fn comment<T: ToString>(&self, parent: i32, body: T) -> Result<(), String> {
-        let author = match self.user.clone() {
-            Some(author) => author,
+    /// logs the user in, so later posts and comments send a session token instead of the password
+    pub fn login(&self) -> Result<(), String> {
+        let user = match self.user.clone() {
+            Some(user) => user,
             None => return Err("No user provided".to_string()),
         };
 
-        let client = Request::new()
-            .body_key(
-                "nnntp",
-                Value::Object(
-                    [
-                        ("type".to_string(), Value::String("comment".to_string())),
-                        (
-                            "parent".to_string(),
-                            Value::Object(
-                                [("id".to_string(), Value::Number(parent.into()))]
-                                    .iter()
-                                    .cloned()
-                                    .collect(),
-                            ),
-                        ),
-                        (
-                            "comment".to_string(),
-                            Value::Object(
-                                [("body".to_string(), Value::String(body.to_string()))]
-                                    .iter()
-                                    .cloned()
-                                    .collect(),
-                            ),
-                        ),
-                        (
-                            "author".to_string(),
-                            Value::Object(
-                                [
-                                    (
-                                        "username".to_string(),
-                                        Value::String(author.username.clone()),
-                                    ),
-                                    (
-                                        "password".to_string(),
-                                        Value::String(author.password.clone()),
-                                    ),
-                                    (
-                                        "email".to_string(),
-                                        Value::String(
-                                            author
-                                                .email
-                                                .clone()
-                                                .unwrap_or("no_email@provided.com".to_string()),
-                                        ),
-                                    ),
-                                ]
-                                .iter()
-                                .cloned()
-                                .collect(),
-                            ),
-                        ),
-                    ]
-                    .iter()
-                    .cloned()
-                    .collect(),
-                ),
-            )
-            .resource("/comment");
-
-        let response = client.send(self.host.clone(), self.port).unwrap();
-
-        match response.status.code {
-            200 => Ok(()),
-            400 => Err("Invalid request".to_string()),
-            401 => Err("Unauthorized".to_string()),
-            _ => Err("Unknown error".to_string()),
-        }
+        let request = NnntpRequest::Login(LoginRequest {
+            username: user.username,
+            password: user.password,
+        });
+
+        let session: Session = payload(&check(self.send("/login", &request)?)?)?;
+
+        *self.session() = Some(session.token);
+
+        Ok(())
     }
 
-    pub fn list<T: ToString>(&self, group: T) -> Result<Posts, String> {
-        let client = Request::new()
-            .body_key(
-                "nnntp",
-                Value::Object(
-                    [
-                        ("type".to_string(), Value::String("list".to_string())),
-                        ("group".to_string(), Value::String(group.to_string())),
-                    ]
-                    .iter()
-                    .cloned()
-                    .collect(),
-                ),
-            )
-            .resource("/list");
-
-        let response: Result<JsontpResponse, String> = client.send(self.host.clone(), self.port);
-
-        let other = match response {
-            Ok(response) => response.body.other,
-            Err(err) => return Err(err),
+    /// ends the session on the server. with `everywhere`, every other session of the user ends too
+    pub fn logout(&self, everywhere: bool) -> Result<(), String> {
+        let token = match self.session().take() {
+            Some(token) => token,
+            None => return Ok(()),
         };
 
-        let posts = match other.get("nnntp") {
-            Some(Value::Array(posts)) => Ok(Value::Array(posts.clone())),
-            _ => Err("Invalid response".to_string()),
-        };
+        let request = NnntpRequest::Logout(LogoutRequest {
+            token,
+            all: everywhere,
+        });
 
-        let group_name = match posts.clone() {
-            Ok(Value::Array(posts)) => posts
-                .get(0)
-                .unwrap_or(&Value::String("no_posts".to_string()))
-                .get("group_name")
-                .unwrap_or(&Value::String("no_posts".to_string()))
-                .as_str()
-                .unwrap_or("no_posts")
-                .to_string(),
-            _ => return Err("Invalid response".to_string()),
-        };
+        check(self.send("/logout", &request)?)?;
+
+        Ok(())
+    }
 
-        let mut posts_instance = Posts {
-            posts: vec![],
-            group: group_name,
+    // the author of posts and comments, logging in first if there is no session yet
+    fn author(&self) -> Result<Author, String> {
+        let user = match self.user.clone() {
+            Some(user) => user,
+            None => return Err("No user provided".to_string()),
         };
 
-        for post in posts.unwrap_or(Value::Array(vec![])).as_array().unwrap() {
-            let post = match post {
-                Value::Object(post) => post,
-                _ => return Err("Invalid response".to_string()),
-            };
+        if self.session().is_none() {
+            self.login()?;
+        }
 
-            let id = match post.get("id") {
-                Some(Value::Number(id)) => id.clone(),
-                _ => return Err("Invalid response".to_string()),
-            };
+        let token = self.session().clone().unwrap_or_default();
 
-            let subject = match post.get("subject") {
-                Some(Value::String(subject)) => subject.clone(),
-                _ => return Err("Invalid response".to_string()),
-            };
+        Ok(Author {
+            credentials: Credentials::Token { token },
+            // the server uses the email stored on the account
+            email: user.email.unwrap_or_default(),
+        })
+    }
 
-            let body = match post.get("body") {
-                Some(Value::String(body)) => body.clone(),
-                _ => return Err("Invalid response".to_string()),
-            };
+    // sends a request on behalf of the user. if the session has expired or been revoked on the
+    // server, it logs in again and retries once
+    fn send_as_author<F: Fn(Author) -> NnntpRequest>(
+        &self,
+        resource: &str,
+        build: F,
+    ) -> Result<JsontpResponse, String> {
+        let response = self.send(resource, &build(self.author()?))?;
 
-            let author = match post.get("author") {
-                Some(Value::String(author)) => author.clone(),
-                _ => return Err("Invalid response".to_string()),
-            };
+        if response.status.code != 401 {
+            return check(response);
+        }
 
-            let author_email = match post.get("author_email") {
-                Some(Value::String(author_email)) => Some(author_email.clone()),
-                _ => None,
-            };
+        *self.session() = None;
 
-            let comments = match post.get("comments") {
-                Some(comments) => comments.clone(),
-                _ => return Err("Invalid response".to_string()),
-            };
+        check(self.send(resource, &build(self.author()?))?)
+    }
 
-            let mut comments_instance = vec![];
+    pub fn post<T: ToString>(&self, group: T, subject: T, body: T) -> Result<(), String> {
+        self.send_as_author("/post", |author| {
+            NnntpRequest::Post(PostRequest {
+                group: group.to_string(),
+                post: PostContent {
+                    subject: subject.to_string(),
+                    body: body.to_string(),
+                },
+                author,
+            })
+        })?;
 
-            if !comments.is_array() {
-                return Err("Invalid response".to_string());
-            }
+        Ok(())
+    }
 
-            for comment in comments.as_array().unwrap() {
-                let comment = match comment {
-                    Value::Object(comment) => comment,
-                    _ => return Err("Invalid response".to_string()),
-                };
-
-                let body = match comment.get("body") {
-                    Some(Value::String(body)) => body.clone(),
-                    _ => return Err("Invalid response".to_string()),
-                };
-
-                let author = match comment.get("author") {
-                    Some(Value::String(author)) => author.clone(),
-                    _ => return Err("Invalid response".to_string()),
-                };
-
-                let author_email = match comment.get("author_email") {
-                    Some(Value::String(author_email)) => Some(author_email.clone()),
-                    _ => None,
-                };
-
-                comments_instance.push(Comment {
-                    body,
-                    author,
-                    author_email
-                });
-            }
+    /// comments on the post `parent`, which belongs to `group`
+    pub fn comment<T: ToString>(&self, group: T, parent: i32, body: T) -> Result<(), String> {
+        self.send_comment(group, parent, None, body)
+    }
+
+    /// replies to the comment `reply_to`, which is under the post `parent` of `group`
+    pub fn reply<T: ToString>(
+        &self,
+        group: T,
+        parent: i32,
+        reply_to: i32,
+        body: T,
+    ) -> Result<(), String> {
+        self.send_comment(group, parent, Some(reply_to), body)
+    }
 
-            posts_instance.posts.push(Post {
-                id: id.to_string().parse().unwrap(),
-                subject,
-                body,
+    fn send_comment<T: ToString>(
+        &self,
+        group: T,
+        parent: i32,
+        reply_to: Option<i32>,
+        body: T,
+    ) -> Result<(), String> {
+        self.send_as_author("/comment", |author| {
+            NnntpRequest::Comment(CommentRequest {
+                group: group.to_string(),
+                parent: Parent { id: parent },
+                reply_to,
+                comment: CommentContent {
+                    body: body.to_string(),
+                },
                 author,
-                author_email,
-                comments: comments_instance,
+            })
+        })?;
+
+        Ok(())
+    }
+
+    /// replaces the subject and/or body of one of the user's posts, keeping the old version
+    pub fn edit_post<T: ToString>(
+        &self,
+        id: i32,
+        subject: Option<T>,
+        body: Option<T>,
+    ) -> Result<(), String> {
+        self.edit(
+            Article::Post(id),
+            subject.map(|subject| subject.to_string()),
+            body.map(|body| body.to_string()),
+        )
+    }
+
+    /// replaces the body of one of the user's comments, keeping the old version
+    pub fn edit_comment<T: ToString>(&self, id: i32, body: T) -> Result<(), String> {
+        self.edit(Article::Comment(id), None, Some(body.to_string()))
+    }
+
+    fn edit(
+        &self,
+        article: Article,
+        subject: Option<String>,
+        body: Option<String>,
+    ) -> Result<(), String> {
+        self.send_as_author("/edit", |author| {
+            NnntpRequest::Edit(EditRequest {
+                article,
+                subject: subject.clone(),
+                body: body.clone(),
+                author: author.credentials,
+            })
+        })?;
+
+        Ok(())
+    }
+
+    /// withdraws a post or comment of the user, or anything in a group they moderate
+    pub fn cancel(&self, article: Article) -> Result<(), String> {
+        self.send_as_author("/cancel", |author| {
+            NnntpRequest::Cancel(CancelRequest {
+                article,
+                author: author.credentials,
+            })
+        })?;
+
+        Ok(())
+    }
+
+    /// the versions of a post or comment that edits replaced, oldest first. with a user, the
+    /// history of their own held back articles, or of those in groups they moderate, is shown too
+    pub fn history(&self, article: Article) -> Result<Vec<Revision>, String> {
+        let response = if self.user.is_some() {
+            self.send_as_author("/history", |author| {
+                NnntpRequest::History(HistoryRequest {
+                    article,
+                    reader: Some(author.credentials),
+                })
+            })?
+        } else {
+            let request = NnntpRequest::History(HistoryRequest {
+                article,
+                reader: None,
             });
+
+            check(self.send("/history", &request)?)?
+        };
+
+        let revisions: HistoryResponse = payload(&response)?;
+
+        Ok(revisions)
+    }
+
+    /// every post of a group or wildmat, fetching page after page until there are none left
+    pub fn list<T: ToString>(&self, group: T) -> Result<Posts, String> {
+        let posts = self
+            .pages(ListRequest::new(group.to_string()))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Posts {
+            posts,
+            group: group.to_string(),
+        })
+    }
+
+    /// a single page of posts. the `next` of the response is the `after_id` of the next page
+    pub fn list_page(&self, request: ListRequest) -> Result<ListResponse, String> {
+        payload(&check(self.send("/list", &NnntpRequest::List(request))?)?)
+    }
+
+    /// the posts and comments matching `request`, best match first
+    pub fn search(&self, request: SearchRequest) -> Result<Vec<SearchHit>, String> {
+        let hits: SearchResponse = payload(&check(
+            self.send("/search", &NnntpRequest::Search(request))?,
+        )?)?;
+
+        Ok(hits)
+    }
+
+    /// the posts matching `request`, fetching the next page only once the last one is used up
+    pub fn pages(&self, request: ListRequest) -> Pages<'_> {
+        Pages {
+            server: self,
+            request: Some(request),
+            posts: VecDeque::new(),
         }
+    }
+
+    /// creates a new, empty group, with the user as its creator
+    pub fn create_group<T: ToString>(&self, name: T, description: T) -> Result<Group, String> {
+        let response = self.send_as_author("/groups/create", |author| {
+            NnntpRequest::CreateGroup(CreateGroupRequest {
+                name: name.to_string(),
+                description: description.to_string(),
+                restricted: false,
+                moderated: false,
+                creator: author.credentials,
+            })
+        })?;
+
+        payload(&response)
+    }
+
+    /// limits posting in a group the user moderates to its posters, or opens it back up
+    pub fn restrict_group<T: ToString>(&self, name: T, restricted: bool) -> Result<Group, String> {
+        self.configure_group(name.to_string(), Some(restricted), None)
+    }
 
-        Ok(posts_instance)
-    }
-
-    pub fn new_user<T: ToString>(&self, username: T, password: T) -> Result<(), String> {
-        let client = Request::new()
-            .body_key(
-                "nnntp",
-                Value::Object(
-                    [
-                        ("type".to_string(), Value::String("new".to_string())),
-                        ("username".to_string(), Value::String(username.to_string())),
-                        ("password".to_string(), Value::String(password.to_string())),
-                    ]
-                    .iter()
-                    .cloned()
-                    .collect(),
-                ),
-            )
-            .resource("/new");
-
-        let response = client.send(self.host.clone(), self.port).unwrap();
-
-        match response.status.code {
-            200 => Ok(()),
-            400 => Err("Invalid request".to_string()),
-            401 => Err("Unauthorized".to_string()),
-            _ => Err("Unknown error".to_string()),
+    /// holds the posts of a group the user moderates until a moderator approves them, or lets
+    /// them straight in again
+    pub fn moderate_group<T: ToString>(&self, name: T, moderated: bool) -> Result<Group, String> {
+        self.configure_group(name.to_string(), None, Some(moderated))
+    }
+
+    fn configure_group(
+        &self,
+        name: String,
+        restricted: Option<bool>,
+        moderated: Option<bool>,
+    ) -> Result<Group, String> {
+        let response = self.send_as_author("/groups/configure", |author| {
+            NnntpRequest::ConfigureGroup(ConfigureGroupRequest {
+                name: name.clone(),
+                restricted,
+                moderated,
+                author: author.credentials,
+            })
+        })?;
+
+        payload(&response)
+    }
+
+    /// subscribes to a group, or to every group a wildmat like `comp.lang.*` matches, returning
+    /// every subscription
+    pub fn subscribe<T: ToString>(&self, group: T) -> Result<Vec<Subscription>, String> {
+        let response = self.send_as_author("/subscribe", |author| {
+            NnntpRequest::Subscribe(SubscribeRequest {
+                group: group.to_string(),
+                author: author.credentials,
+            })
+        })?;
+
+        payload(&response)
+    }
+
+    pub fn unsubscribe<T: ToString>(&self, group: T) -> Result<Vec<Subscription>, String> {
+        let response = self.send_as_author("/unsubscribe", |author| {
+            NnntpRequest::Unsubscribe(SubscribeRequest {
+                group: group.to_string(),
+                author: author.credentials,
+            })
+        })?;
+
+        payload(&response)
+    }
+
+    /// the groups the user subscribed to, with how many articles of each they have not read
+    pub fn subscriptions(&self) -> Result<Vec<Subscription>, String> {
+        let response = self.send_as_author("/subscriptions", |author| {
+            NnntpRequest::Subscriptions(SubscriptionsRequest {
+                author: author.credentials,
+            })
+        })?;
+
+        let subscriptions: SubscriptionsResponse = payload(&response)?;
+
+        Ok(subscriptions)
+    }
+
+    /// the posts of `group` the user has not read, or that have comments they have not read.
+    /// each post only comes with its unread comments
+    pub fn unread<T: ToString>(&self, group: T) -> Result<Posts, String> {
+        let mut posts = vec![];
+        let mut after_id = None;
+
+        loop {
+            let response = self.send_as_author("/list", |author| {
+                NnntpRequest::List(ListRequest {
+                    after_id,
+                    reader: Some(author.credentials),
+                    only_unread: true,
+                    ..ListRequest::new(group.to_string())
+                })
+            })?;
+            let page: ListResponse = payload(&response)?;
+
+            posts.extend(page.posts);
+
+            match page.next {
+                Some(next) => after_id = Some(next),
+                None => break,
+            }
         }
+
+        Ok(Posts {
+            posts,
+            group: group.to_string(),
+        })
+    }
+
+    /// waits for new posts and comments in a group, or in every group a wildmat like
+    /// `comp.lang.*` matches. only articles listed after the first `next` count, and each call
+    /// blocks until one arrives
+    pub fn watch<T: ToString>(&self, group: T) -> Watch<'_> {
+        self.watch_group(Some(group.to_string()))
+    }
+
+    /// waits for new posts and comments in the groups the user subscribed to
+    pub fn watch_subscriptions(&self) -> Watch<'_> {
+        self.watch_group(None)
+    }
+
+    fn watch_group(&self, group: Option<String>) -> Watch<'_> {
+        Watch {
+            server: self,
+            group,
+            after: None,
+            articles: VecDeque::new(),
+            failed: false,
+        }
+    }
+
+    /// marks posts and comments of `group` as read
+    pub fn mark_read<T: ToString>(
+        &self,
+        group: T,
+        articles: &[Article],
+    ) -> Result<Subscription, String> {
+        self.send_mark_read(group, articles, false)
+    }
+
+    /// marks every article of `group` as read
+    pub fn catch_up<T: ToString>(&self, group: T) -> Result<Subscription, String> {
+        self.send_mark_read(group, &[], true)
+    }
+
+    fn send_mark_read<T: ToString>(
+        &self,
+        group: T,
+        articles: &[Article],
+        all: bool,
+    ) -> Result<Subscription, String> {
+        let response = self.send_as_author("/mark-read", |author| {
+            NnntpRequest::MarkRead(MarkReadRequest {
+                group: group.to_string(),
+                articles: articles.to_vec(),
+                all,
+                author: author.credentials,
+            })
+        })?;
+
+        payload(&response)
+    }
+
+    /// the posts waiting in the groups the user moderates, and the user's own pending and
+    /// rejected posts
+    pub fn moderation_queue(&self) -> Result<Vec<HeldPost>, String> {
+        let response = self.send_as_author("/moderation/queue", |author| {
+            NnntpRequest::ModerationQueue(QueueRequest {
+                group: None,
+                author: author.credentials,
+            })
+        })?;
+
+        let held: QueueResponse = payload(&response)?;
+
+        Ok(held)
+    }
+
+    /// lets a pending post into its group
+    pub fn approve(&self, post: i32) -> Result<(), String> {
+        self.send_as_author("/moderation/approve", |author| {
+            NnntpRequest::Approve(ApproveRequest {
+                post,
+                author: author.credentials,
+            })
+        })?;
+
+        Ok(())
+    }
+
+    /// turns a pending post down, `reason` is passed on to its author
+    pub fn reject<T: ToString>(&self, post: i32, reason: T) -> Result<(), String> {
+        self.send_as_author("/moderation/reject", |author| {
+            NnntpRequest::Reject(RejectRequest {
+                post,
+                reason: reason.to_string(),
+                author: author.credentials,
+            })
+        })?;
+
+        Ok(())
+    }
+
+    /// gives another user a role in `group`, or everywhere when it is `None`. only admins can
+    pub fn grant_role<T: ToString>(
+        &self,
+        username: T,
+        role: Role,
+        group: Option<T>,
+    ) -> Result<(), String> {
+        self.send_as_author("/roles/grant", |author| {
+            NnntpRequest::GrantRole(RoleRequest {
+                username: username.to_string(),
+                role,
+                group: group.as_ref().map(|group| group.to_string()),
+                author: author.credentials,
+            })
+        })?;
+
+        Ok(())
+    }
+
+    /// takes a role granted with [`ServerConnection::grant_role`] away again
+    pub fn revoke_role<T: ToString>(
+        &self,
+        username: T,
+        role: Role,
+        group: Option<T>,
+    ) -> Result<(), String> {
+        self.send_as_author("/roles/revoke", |author| {
+            NnntpRequest::RevokeRole(RoleRequest {
+                username: username.to_string(),
+                role,
+                group: group.as_ref().map(|group| group.to_string()),
+                author: author.credentials,
+            })
+        })?;
+
+        Ok(())
+    }
+
+    /// makes `count` single-use invite codes for registering. only admins can
+    pub fn create_invites(&self, count: u32) -> Result<Vec<Invite>, String> {
+        let response = self.send_as_author("/invites/create", |author| {
+            NnntpRequest::CreateInvites(CreateInvitesRequest {
+                count: Some(count),
+                author: author.credentials,
+            })
+        })?;
+
+        let invites: InvitesResponse = payload(&response)?;
+
+        Ok(invites)
+    }
+
+    /// every invite code, newest first, with who used it. only admins can
+    pub fn invites(&self) -> Result<Vec<Invite>, String> {
+        let response = self.send_as_author("/invites/list", |author| {
+            NnntpRequest::Invites(InvitesRequest {
+                author: author.credentials,
+            })
+        })?;
+
+        let invites: InvitesResponse = payload(&response)?;
+
+        Ok(invites)
+    }
+
+    /// the users waiting for approval, by name. only admins can
+    pub fn pending_users(&self) -> Result<Vec<String>, String> {
+        let response = self.send_as_author("/users/pending", |author| {
+            NnntpRequest::PendingUsers(PendingUsersRequest {
+                author: author.credentials,
+            })
+        })?;
+
+        let pending: PendingUsersResponse = payload(&response)?;
+
+        Ok(pending)
+    }
+
+    /// lets a user waiting for approval log in. only admins can
+    pub fn approve_user<T: ToString>(&self, username: T) -> Result<(), String> {
+        self.send_as_author("/users/approve", |author| {
+            NnntpRequest::ApproveUser(ApproveUserRequest {
+                username: username.to_string(),
+                author: author.credentials,
+            })
+        })?;
+
+        Ok(())
+    }
+
+    /// every group on the server, by name
+    pub fn groups(&self) -> Result<Vec<Group>, String> {
+        self.list_groups(None)
+    }
+
+    /// the groups matching a wildmat like `comp.*,!comp.lang.java`, by name
+    pub fn groups_matching<T: ToString>(&self, pattern: T) -> Result<Vec<Group>, String> {
+        self.list_groups(Some(pattern.to_string()))
+    }
+
+    fn list_groups(&self, pattern: Option<String>) -> Result<Vec<Group>, String> {
+        let request = NnntpRequest::ListGroups(ListGroupsRequest { pattern });
+
+        let groups: GroupsResponse = payload(&check(self.send("/groups/list", &request)?)?)?;
+
+        Ok(groups)
+    }
+
+    pub fn new_user<T: ToString>(
+        &self,
+        username: T,
+        password: T,
+        email: Option<T>,
+    ) -> Result<(), String> {
+        self.register(NewUserRequest {
+            username: username.to_string(),
+            password: password.to_string(),
+            email: email.map(|email| email.to_string()).unwrap_or_default(),
+            invite: None,
+        })?;
+
+        Ok(())
+    }
+
+    /// registers a user with everything `/new` takes, like an invite code. the response says
+    /// whether the account has to wait for an admin's approval before it can log in
+    pub fn register(&self, request: NewUserRequest) -> Result<UserCreated, String> {
+        let request = NnntpRequest::New(request);
+
+        payload(&check(self.send("/new", &request)?)?)
+    }
+
+    /// changes the password of the user. the server ends every session of the user, so the next
+    /// request logs in again with the new password
+    pub fn change_password<T: ToString>(&mut self, new_password: T) -> Result<(), String> {
+        let mut user = match self.user.clone() {
+            Some(user) => user,
+            None => return Err("No user provided".to_string()),
+        };
+
+        let request = NnntpRequest::ChangePassword(ChangePasswordRequest {
+            username: user.username.clone(),
+            password: user.password.clone(),
+            new_password: new_password.to_string(),
+        });
+
+        check(self.send("/account/password", &request)?)?;
+
+        user.password = new_password.to_string();
+        self.user = Some(user);
+        *self.session() = None;
+
+        Ok(())
+    }
+
+    /// changes the email shown on the articles the user writes from now on
+    pub fn change_email<T: ToString>(&mut self, email: T) -> Result<(), String> {
+        let mut user = match self.user.clone() {
+            Some(user) => user,
+            None => return Err("No user provided".to_string()),
+        };
+
+        let request = NnntpRequest::ChangeEmail(ChangeEmailRequest {
+            username: user.username.clone(),
+            password: user.password.clone(),
+            email: email.to_string(),
+        });
+
+        check(self.send("/account/email", &request)?)?;
+
+        user.email = Some(email.to_string());
+        self.user = Some(user);
+
+        Ok(())
+    }
+
+    /// deletes the account of the user, leaving the connection without a user. their articles stay
+    /// on the server
+    pub fn delete_account(&mut self) -> Result<(), String> {
+        let user = match self.user.clone() {
+            Some(user) => user,
+            None => return Err("No user provided".to_string()),
+        };
+
+        let request = NnntpRequest::DeleteAccount(DeleteAccountRequest {
+            username: user.username,
+            password: user.password,
+        });
+
+        check(self.send("/account/delete", &request)?)?;
+
+        self.user = None;
+        *self.session() = None;
+
+        Ok(())
     }
 }
 
@@ -419,8 +868,190 @@ mod tests {
 
         println!("Parent id: {}", parent_id);
 
-        server.comment(parent_id, "This is a comment").unwrap();
+        let groups = server.groups().unwrap();
+        assert!(groups.iter().any(|group| group.name == "comp.lang.rust"));
+        assert_eq!(
+            server.groups_matching("comp.*,!comp.lang.java").unwrap(),
+            groups
+        );
+
+        let first = server
+            .list_page(ListRequest {
+                limit: Some(1),
+                ..ListRequest::new("comp.lang.rust")
+            })
+            .unwrap();
+        assert_eq!(first.posts[0].id, parent_id);
+
+        let everything = server.list("comp.*").unwrap();
+        assert!(everything
+            .in_group("comp.lang.rust")
+            .any(|post| post.id == parent_id));
+
+        server
+            .comment("comp.lang.rust", parent_id, "This is a comment")
+            .unwrap();
+
+        let listed = server.list("comp.lang.rust").unwrap();
+        let post = listed
+            .posts
+            .iter()
+            .find(|post| post.id == parent_id)
+            .unwrap();
+        let comment = post.comments.last().unwrap();
+        assert!(comment.message_id.starts_with('<') && comment.message_id.ends_with('>'));
+        assert_ne!(comment.message_id, post.message_id);
+
+        server
+            .reply("comp.lang.rust", parent_id, comment.id, "This is a reply")
+            .unwrap();
+        let listed = server.list("comp.lang.rust").unwrap();
+        let post = listed
+            .posts
+            .iter()
+            .find(|post| post.id == parent_id)
+            .unwrap();
+        let thread = Thread::new(post);
+        assert!(thread
+            .walk()
+            .iter()
+            .any(|(depth, reply)| *depth == 2 && reply.body == "This is a reply"));
+        println!("{}", thread.render());
+
+        server
+            .edit_comment(comment.id, "This is an edited comment")
+            .unwrap();
+        let history = server.history(Article::Comment(comment.id)).unwrap();
+        assert_eq!(history[0].body, "This is a comment");
+
+        server.cancel(Article::Comment(comment.id)).unwrap();
+        let listed = server.list("comp.lang.rust").unwrap();
+        let post = listed
+            .posts
+            .iter()
+            .find(|post| post.id == parent_id)
+            .unwrap();
+        let cancelled = post.comments.iter().find(|c| c.id == comment.id).unwrap();
+        assert!(cancelled.cancelled_at.is_some());
+
+        // the user created the group, so moderates it, but is no admin
+        assert!(
+            server
+                .restrict_group("comp.lang.rust", true)
+                .unwrap()
+                .restricted
+        );
+        assert!(
+            !server
+                .restrict_group("comp.lang.rust", false)
+                .unwrap()
+                .restricted
+        );
+        assert!(
+            server
+                .moderate_group("comp.lang.rust", true)
+                .unwrap()
+                .moderated
+        );
+        // a moderator's own posts are not held back
+        server
+            .post("comp.lang.rust", "Moderated", "Straight in")
+            .unwrap();
+        assert!(server.moderation_queue().unwrap().is_empty());
+
+        let hits = server
+            .search(SearchRequest {
+                group: Some("comp.*".to_string()),
+                ..SearchRequest::new("\"straight in\"")
+            })
+            .unwrap();
+        assert_eq!(hits[0].subject, "Moderated");
+        assert!(hits[0].snippet.contains("[Straight in]"));
+        assert!(server.approve(parent_id).is_err());
+        assert!(
+            !server
+                .moderate_group("comp.lang.rust", false)
+                .unwrap()
+                .moderated
+        );
+        assert!(server.grant_role("username", Role::Admin, None).is_err());
+
+        let subscriptions = server.subscribe("comp.lang.*").unwrap();
+        assert_eq!(subscriptions[0].group, "comp.lang.rust");
+        assert!(subscriptions[0].unread > 0);
+        let unread = server.unread("comp.lang.rust").unwrap();
+        assert!(unread
+            .posts
+            .iter()
+            .all(|post| post.read == Some(false) || !post.comments.is_empty()));
+        let marked = server
+            .mark_read("comp.lang.rust", &[Article::Post(parent_id)])
+            .unwrap();
+        assert_eq!(marked.unread, subscriptions[0].unread - 1);
+        server.catch_up("comp.lang.rust").unwrap();
+        assert!(server.unread("comp.lang.rust").unwrap().posts.is_empty());
+        assert_eq!(server.subscriptions().unwrap()[0].unread, 0);
+
+        // what is posted while watching arrives, one blocking call at a time
+        let arrived = std::thread::scope(|scope| {
+            scope.spawn(|| {
+                std::thread::sleep(std::time::Duration::from_millis(500));
+
+                let poster = ServerConnection::new("localhost", 8080, server.user.clone());
+                poster
+                    .comment("comp.lang.rust", parent_id, "Watch this")
+                    .unwrap();
+            });
+
+            server.watch_subscriptions().next().unwrap().unwrap()
+        });
+        match arrived {
+            NewArticle::Comment {
+                post_id, comment, ..
+            } => {
+                assert_eq!((post_id, comment.body.as_str()), (parent_id, "Watch this"))
+            }
+            other => panic!("watched {:?}", other),
+        }
+
+        assert!(server.unsubscribe("comp.lang.rust").unwrap().is_empty());
+
+        server
+            .new_user("corro", "unsafe code", Some("corro@example.com"))
+            .unwrap();
+        let mut corro = ServerConnection::new(
+            "localhost",
+            8080,
+            Some(User::new("corro", None, "unsafe code")),
+        );
+        // only admins hand out invites, and the server is open to anyone anyway
+        assert!(corro.create_invites(1).is_err());
+        corro.change_email("corro@rust-lang.org").unwrap();
+        corro.change_password("very unsafe code").unwrap();
+        corro
+            .comment("comp.lang.rust", parent_id, "Written by corro")
+            .unwrap();
+        let listed = server.list("comp.lang.rust").unwrap();
+        let by_corro = listed
+            .posts
+            .iter()
+            .flat_map(|post| &post.comments)
+            .find(|comment| comment.author == "corro")
+            .unwrap();
+        assert_eq!(
+            by_corro.author_email.as_deref(),
+            Some("corro@rust-lang.org")
+        );
+
+        corro.delete_account().unwrap();
+        assert!(corro.user.is_none());
+        let gone = ServerConnection::new(
+            "localhost",
+            8080,
+            Some(User::new("corro", None, "very unsafe code")),
+        );
+        assert!(gone.login().is_err());
 
-        println!("Listing: {:#?}", server.list("comp.lang.rust"));
+        println!("Listing: {:#?}", listed);
     }
 }
@@ -0,0 +1,136 @@
+use protocol::{Comment, Post};
+
+/// the conversation under a post, rebuilt from the flat comment list `/list` returns
+#[derive(Debug, Clone, Copy)]
+pub struct Thread<'a> {
+    pub post: &'a Post,
+}
+
+impl<'a> Thread<'a> {
+    pub fn new(post: &'a Post) -> Thread<'a> {
+        Thread { post }
+    }
+
+    /// the direct replies to the comment `to`, or to the post itself when it is `None`, oldest
+    /// first. comments replying to something that is not in the list hang off the post
+    pub fn replies(&self, to: Option<i32>) -> Vec<&'a Comment> {
+        let comments = &self.post.comments;
+
+        comments
+            .iter()
+            .filter(|comment| {
+                let parent = comment
+                    .reply_to
+                    .filter(|id| comments.iter().any(|other| other.id == *id));
+
+                parent == to
+            })
+            .collect()
+    }
+
+    /// every comment, depth first, together with its depth: 1 for replies to the post, 2 for
+    /// replies to those, and so on
+    pub fn walk(&self) -> Vec<(usize, &'a Comment)> {
+        let mut walked = vec![];
+        // (depth, comment), the next one to visit last
+        let mut stack: Vec<(usize, &Comment)> = self
+            .replies(None)
+            .into_iter()
+            .rev()
+            .map(|c| (1, c))
+            .collect();
+
+        while let Some((depth, comment)) = stack.pop() {
+            walked.push((depth, comment));
+
+            stack.extend(
+                self.replies(Some(comment.id))
+                    .into_iter()
+                    .rev()
+                    .map(|reply| (depth + 1, reply)),
+            );
+        }
+
+        walked
+    }
+
+    /// the thread as text, each reply indented under what it answers
+    pub fn render(&self) -> String {
+        let mut rendered = format!(
+            "{} - {}\n{}\n",
+            self.post.subject, self.post.author, self.post.body
+        );
+
+        for (depth, comment) in self.walk() {
+            let indent = "  ".repeat(depth);
+
+            if comment.cancelled_at.is_some() {
+                rendered.push_str(&format!("{}> [cancelled]\n", indent));
+            } else {
+                rendered.push_str(&format!(
+                    "{}> {}: {}\n",
+                    indent, comment.author, comment.body
+                ));
+            }
+        }
+
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comment(id: i32, reply_to: Option<i32>) -> Comment {
+        Comment {
+            id,
+            reply_to,
+            references: vec![],
+            body: format!("comment {}", id),
+            author: "ferris".to_string(),
+            author_email: None,
+            created_at: 0,
+            message_id: format!("<{}@example.com>", id),
+            edited_at: None,
+            cancelled_at: None,
+            read: None,
+        }
+    }
+
+    #[test]
+    fn walks_replies_depth_first() {
+        let post = Post {
+            id: 1,
+            group_name: "comp.lang.rust".to_string(),
+            subject: "hello".to_string(),
+            body: "world".to_string(),
+            author: "ferris".to_string(),
+            author_email: None,
+            created_at: 0,
+            message_id: "<post@example.com>".to_string(),
+            edited_at: None,
+            cancelled_at: None,
+            read: None,
+            comments: vec![
+                comment(1, None),
+                comment(2, None),
+                comment(3, Some(1)),
+                comment(4, Some(3)),
+                comment(5, Some(1)),
+                // replies to a comment that is gone
+                comment(6, Some(404)),
+            ],
+        };
+        let thread = Thread::new(&post);
+
+        let walked: Vec<(usize, i32)> = thread
+            .walk()
+            .into_iter()
+            .map(|(depth, comment)| (depth, comment.id))
+            .collect();
+        assert_eq!(walked, [(1, 1), (2, 3), (3, 4), (2, 5), (1, 2), (1, 6)]);
+
+        assert!(thread.render().contains("\n      > ferris: comment 4\n"));
+    }
+}
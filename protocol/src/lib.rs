@@ -0,0 +1,919 @@
+//! the NNNTP wire format, shared by `server` and `client` so the two cannot drift apart.
+//!
+//! every request carries one of the [`NnntpRequest`] kinds in the `nnntp` key of its jsontp body,
+//! tagged by `type`. successful responses put their payload in the `nnntp` key of the response
+//! body, and failed ones an [`ErrorBody`] in the `error` key.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// the body key requests and responses carry their NNNTP payload in
+pub const BODY_KEY: &str = "nnntp";
+
+/// the body key failed responses carry their [`ErrorBody`] in
+pub const ERROR_KEY: &str = "error";
+
+/// a request, as found in the `nnntp` key of a jsontp request body
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum NnntpRequest {
+    Post(PostRequest),
+    Comment(CommentRequest),
+    List(ListRequest),
+    New(NewUserRequest),
+    Login(LoginRequest),
+    Logout(LogoutRequest),
+    #[serde(rename = "change_password")]
+    ChangePassword(ChangePasswordRequest),
+    #[serde(rename = "change_email")]
+    ChangeEmail(ChangeEmailRequest),
+    #[serde(rename = "delete_account")]
+    DeleteAccount(DeleteAccountRequest),
+    #[serde(rename = "create_group")]
+    CreateGroup(CreateGroupRequest),
+    #[serde(rename = "list_groups")]
+    ListGroups(ListGroupsRequest),
+    Edit(EditRequest),
+    History(HistoryRequest),
+    Cancel(CancelRequest),
+    #[serde(rename = "configure_group")]
+    ConfigureGroup(ConfigureGroupRequest),
+    #[serde(rename = "grant_role")]
+    GrantRole(RoleRequest),
+    #[serde(rename = "revoke_role")]
+    RevokeRole(RoleRequest),
+    #[serde(rename = "moderation_queue")]
+    ModerationQueue(QueueRequest),
+    Approve(ApproveRequest),
+    Reject(RejectRequest),
+    Search(SearchRequest),
+    #[serde(rename = "create_invites")]
+    CreateInvites(CreateInvitesRequest),
+    Invites(InvitesRequest),
+    #[serde(rename = "pending_users")]
+    PendingUsers(PendingUsersRequest),
+    #[serde(rename = "approve_user")]
+    ApproveUser(ApproveUserRequest),
+    Check(CheckRequest),
+    #[serde(rename = "takethis")]
+    TakeThis(TakeThisRequest),
+    Subscribe(SubscribeRequest),
+    Unsubscribe(SubscribeRequest),
+    Subscriptions(SubscriptionsRequest),
+    #[serde(rename = "mark_read")]
+    MarkRead(MarkReadRequest),
+    Watch(WatchRequest),
+}
+
+impl NnntpRequest {
+    /// the `type` of the request
+    pub fn kind(&self) -> &'static str {
+        match self {
+            NnntpRequest::Post(_) => "post",
+            NnntpRequest::Comment(_) => "comment",
+            NnntpRequest::List(_) => "list",
+            NnntpRequest::New(_) => "new",
+            NnntpRequest::Login(_) => "login",
+            NnntpRequest::Logout(_) => "logout",
+            NnntpRequest::ChangePassword(_) => "change_password",
+            NnntpRequest::ChangeEmail(_) => "change_email",
+            NnntpRequest::DeleteAccount(_) => "delete_account",
+            NnntpRequest::CreateGroup(_) => "create_group",
+            NnntpRequest::ListGroups(_) => "list_groups",
+            NnntpRequest::Edit(_) => "edit",
+            NnntpRequest::History(_) => "history",
+            NnntpRequest::Cancel(_) => "cancel",
+            NnntpRequest::ConfigureGroup(_) => "configure_group",
+            NnntpRequest::GrantRole(_) => "grant_role",
+            NnntpRequest::RevokeRole(_) => "revoke_role",
+            NnntpRequest::ModerationQueue(_) => "moderation_queue",
+            NnntpRequest::Approve(_) => "approve",
+            NnntpRequest::Reject(_) => "reject",
+            NnntpRequest::Search(_) => "search",
+            NnntpRequest::CreateInvites(_) => "create_invites",
+            NnntpRequest::Invites(_) => "invites",
+            NnntpRequest::PendingUsers(_) => "pending_users",
+            NnntpRequest::ApproveUser(_) => "approve_user",
+            NnntpRequest::Check(_) => "check",
+            NnntpRequest::TakeThis(_) => "takethis",
+            NnntpRequest::Subscribe(_) => "subscribe",
+            NnntpRequest::Unsubscribe(_) => "unsubscribe",
+            NnntpRequest::Subscriptions(_) => "subscriptions",
+            NnntpRequest::MarkRead(_) => "mark_read",
+            NnntpRequest::Watch(_) => "watch",
+        }
+    }
+
+    /// the user a request is made as, when it comes with their password. requests made with a
+    /// session token, by peers, or by nobody in particular do not say
+    pub fn username(&self) -> Option<&str> {
+        match self {
+            NnntpRequest::Login(LoginRequest { username, .. })
+            | NnntpRequest::ChangePassword(ChangePasswordRequest { username, .. })
+            | NnntpRequest::ChangeEmail(ChangeEmailRequest { username, .. })
+            | NnntpRequest::DeleteAccount(DeleteAccountRequest { username, .. }) => Some(username),
+            _ => match self.credentials()? {
+                Credentials::Password { username, .. } => Some(username),
+                Credentials::Token { .. } => None,
+            },
+        }
+    }
+
+    /// the session token a request is made with, if it comes with one
+    pub fn token(&self) -> Option<&str> {
+        match self {
+            NnntpRequest::Logout(LogoutRequest { token, .. }) => Some(token),
+            _ => match self.credentials()? {
+                Credentials::Token { token } => Some(token),
+                Credentials::Password { .. } => None,
+            },
+        }
+    }
+
+    /// the credentials of the author or reader of a request, for the requests that have one
+    fn credentials(&self) -> Option<&Credentials> {
+        match self {
+            NnntpRequest::Post(PostRequest { author, .. })
+            | NnntpRequest::Comment(CommentRequest { author, .. }) => Some(&author.credentials),
+            NnntpRequest::CreateGroup(CreateGroupRequest { creator, .. }) => Some(creator),
+            NnntpRequest::ConfigureGroup(ConfigureGroupRequest { author, .. })
+            | NnntpRequest::GrantRole(RoleRequest { author, .. })
+            | NnntpRequest::RevokeRole(RoleRequest { author, .. })
+            | NnntpRequest::Edit(EditRequest { author, .. })
+            | NnntpRequest::Cancel(CancelRequest { author, .. })
+            | NnntpRequest::ModerationQueue(QueueRequest { author, .. })
+            | NnntpRequest::Approve(ApproveRequest { author, .. })
+            | NnntpRequest::Reject(RejectRequest { author, .. })
+            | NnntpRequest::Subscribe(SubscribeRequest { author, .. })
+            | NnntpRequest::Unsubscribe(SubscribeRequest { author, .. })
+            | NnntpRequest::Subscriptions(SubscriptionsRequest { author })
+            | NnntpRequest::MarkRead(MarkReadRequest { author, .. })
+            | NnntpRequest::CreateInvites(CreateInvitesRequest { author, .. })
+            | NnntpRequest::Invites(InvitesRequest { author })
+            | NnntpRequest::PendingUsers(PendingUsersRequest { author })
+            | NnntpRequest::ApproveUser(ApproveUserRequest { author, .. }) => Some(author),
+            NnntpRequest::List(ListRequest { reader, .. })
+            | NnntpRequest::Watch(WatchRequest { reader, .. })
+            | NnntpRequest::History(HistoryRequest { reader, .. }) => reader.as_ref(),
+            NnntpRequest::New(_)
+            | NnntpRequest::Login(_)
+            | NnntpRequest::Logout(_)
+            | NnntpRequest::ChangePassword(_)
+            | NnntpRequest::ChangeEmail(_)
+            | NnntpRequest::DeleteAccount(_)
+            | NnntpRequest::ListGroups(_)
+            | NnntpRequest::Search(_)
+            | NnntpRequest::Check(_)
+            | NnntpRequest::TakeThis(_) => None,
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        // every field is a plain string, number or bool, so this cannot fail
+        serde_json::to_value(self).expect("requests always serialize")
+    }
+
+    pub fn from_value(value: Value) -> Result<NnntpRequest, String> {
+        serde_json::from_value(value).map_err(|e| e.to_string())
+    }
+}
+
+/// who is making a request: a session token from `/login`, or the username and password
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Credentials {
+    Token { token: String },
+    Password { username: String, password: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Author {
+    #[serde(flatten)]
+    pub credentials: Credentials,
+    /// not used anymore, articles carry the email stored on the account
+    #[serde(default)]
+    pub email: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PostContent {
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PostRequest {
+    pub group: String,
+    pub post: PostContent,
+    pub author: Author,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Parent {
+    pub id: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CommentContent {
+    pub body: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CommentRequest {
+    /// the group of the parent post
+    pub group: String,
+    pub parent: Parent,
+    /// the id of a comment under `parent` to reply to, instead of the post itself
+    #[serde(default)]
+    pub reply_to: Option<i32>,
+    pub comment: CommentContent,
+    pub author: Author,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ListRequest {
+    /// a group name, or a wildmat like `comp.lang.*` matching several groups
+    pub group: String,
+    /// the most posts to return, the server caps this and picks a default when it is left out
+    #[serde(default)]
+    pub limit: Option<u32>,
+    /// how many of the matching posts to skip
+    #[serde(default)]
+    pub offset: Option<u32>,
+    /// only posts after this one, as given by the `next` of the previous page
+    #[serde(default)]
+    pub after_id: Option<i32>,
+    /// only posts created at or after this time, in seconds since the unix epoch
+    #[serde(default)]
+    pub since: Option<i64>,
+    /// the user to report read and unread articles for
+    #[serde(default)]
+    pub reader: Option<Credentials>,
+    /// only the articles `reader` has not read yet, and the posts they are under
+    #[serde(default)]
+    pub only_unread: bool,
+}
+
+impl ListRequest {
+    /// the first page of `group`, with the server's default page size
+    pub fn new<T: ToString>(group: T) -> ListRequest {
+        ListRequest {
+            group: group.to_string(),
+            limit: None,
+            offset: None,
+            after_id: None,
+            since: None,
+            reader: None,
+            only_unread: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NewUserRequest {
+    pub username: String,
+    pub password: String,
+    /// shown on the articles of the user, it can be changed later with `/account/email`
+    #[serde(default)]
+    pub email: String,
+    /// the code from an admin, on servers that only let users in with one
+    #[serde(default)]
+    pub invite: Option<String>,
+}
+
+/// the response to `/new`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UserCreated {
+    /// the account waits for an admin to approve it, and cannot log in until then
+    pub pending: bool,
+}
+
+/// the account requests all take the current password, even from a user who has a session
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChangePasswordRequest {
+    pub username: String,
+    pub password: String,
+    pub new_password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChangeEmailRequest {
+    pub username: String,
+    pub password: String,
+    pub email: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DeleteAccountRequest {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LogoutRequest {
+    pub token: String,
+    /// end every session of the user, not just this one
+    #[serde(default)]
+    pub all: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CreateGroupRequest {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// only let posters of the group post to it, like an announcements group
+    #[serde(default)]
+    pub restricted: bool,
+    /// hold posts until a moderator approves them
+    #[serde(default)]
+    pub moderated: bool,
+    pub creator: Credentials,
+}
+
+/// changes the settings of a group, which its moderators and admins can do. settings that are
+/// left out stay as they are
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ConfigureGroupRequest {
+    pub name: String,
+    #[serde(default)]
+    pub restricted: Option<bool>,
+    #[serde(default)]
+    pub moderated: Option<bool>,
+    pub author: Credentials,
+}
+
+/// what a user may do. admins may do anything, moderators remove anything in their group and
+/// change its settings, posters post to restricted groups and readers cannot post at all
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Admin,
+    Moderator,
+    Poster,
+    Reader,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Moderator => "moderator",
+            Role::Poster => "poster",
+            Role::Reader => "reader",
+        }
+    }
+}
+
+/// grants or revokes a role, which only admins can do
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RoleRequest {
+    pub username: String,
+    pub role: Role,
+    /// the group the role holds in, leaving it out makes it hold in every group. admins are
+    /// always admins everywhere
+    #[serde(default)]
+    pub group: Option<String>,
+    pub author: Credentials,
+}
+
+/// asks for groups, like `LIST ACTIVE` and `LIST NEWSGROUPS` in NNTP
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ListGroupsRequest {
+    /// a wildmat like `comp.*,!comp.lang.java`, leaving it out lists every group
+    #[serde(default)]
+    pub pattern: Option<String>,
+}
+
+/// a post or a comment, written as `{ "post": 5 }` or `{ "comment": 12 }`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Article {
+    Post(i32),
+    Comment(i32),
+}
+
+/// replaces the subject and/or body of an article. only its author can do this, and comments
+/// have no subject
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EditRequest {
+    pub article: Article,
+    #[serde(default)]
+    pub subject: Option<String>,
+    #[serde(default)]
+    pub body: Option<String>,
+    pub author: Credentials,
+}
+
+/// asks for the versions of an article that edits replaced
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HistoryRequest {
+    pub article: Article,
+    /// who is asking, which only matters for articles that are not listed: their authors and
+    /// the moderators of the group can see the history of held back posts and comments on them
+    #[serde(default)]
+    pub reader: Option<Credentials>,
+}
+
+/// withdraws an article, like a Usenet cancel control message. authors can cancel their own
+/// articles, and moderators of the group anything in it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CancelRequest {
+    pub article: Article,
+    pub author: Credentials,
+}
+
+/// asks for the posts held back from groups: those waiting for the user to moderate them, and
+/// the user's own pending and rejected posts
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct QueueRequest {
+    /// only the posts of this group, leaving it out shows every group
+    #[serde(default)]
+    pub group: Option<String>,
+    pub author: Credentials,
+}
+
+/// lets a pending post into its moderated group
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ApproveRequest {
+    pub post: i32,
+    pub author: Credentials,
+}
+
+/// turns a pending post down, telling its author why
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RejectRequest {
+    pub post: i32,
+    pub reason: String,
+    pub author: Credentials,
+}
+
+/// makes single-use invite codes, for servers that only let users in with one
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CreateInvitesRequest {
+    /// how many codes to make, one when left out
+    #[serde(default)]
+    pub count: Option<u32>,
+    pub author: Credentials,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InvitesRequest {
+    pub author: Credentials,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PendingUsersRequest {
+    pub author: Credentials,
+}
+
+/// lets a user who registered on a server that needs approval log in
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ApproveUserRequest {
+    pub username: String,
+    pub author: Credentials,
+}
+
+/// searches the subjects and bodies of posts and comments
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SearchRequest {
+    /// the words to look for, all of them, with phrases in double quotes, like
+    /// `borrow "lifetime elision"`
+    pub query: String,
+    /// a group or a wildmat like `comp.lang.*`, leaving it out searches every group
+    #[serde(default)]
+    pub group: Option<String>,
+    #[serde(default)]
+    pub author: Option<String>,
+    /// only articles created at or after this unix timestamp
+    #[serde(default)]
+    pub since: Option<i64>,
+    /// only articles created before this unix timestamp
+    #[serde(default)]
+    pub until: Option<i64>,
+    /// at most this many hits, the server picks a default and a maximum
+    #[serde(default)]
+    pub limit: Option<u32>,
+}
+
+impl SearchRequest {
+    /// searches every group for `query`
+    pub fn new<T: ToString>(query: T) -> SearchRequest {
+        SearchRequest {
+            query: query.to_string(),
+            group: None,
+            author: None,
+            since: None,
+            until: None,
+            limit: None,
+        }
+    }
+}
+
+/// a server feeding articles to this one, named by the `--domain` it puts in Path headers and
+/// with the key both ends were configured with
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PeerCredentials {
+    pub name: String,
+    pub key: String,
+}
+
+/// offers articles, like NNTP's CHECK: the server answers with the ones it does not have yet
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CheckRequest {
+    pub message_ids: Vec<String>,
+    pub peer: PeerCredentials,
+}
+
+/// an article travelling between servers, like a Usenet article with its headers
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FeedArticle {
+    pub message_id: String,
+    /// the servers the article went through, newest first and separated by `!`, like
+    /// `news2.example.com!news1.example.com!not-for-mail`
+    pub path: String,
+    pub group: String,
+    /// empty for comments
+    pub subject: String,
+    pub body: String,
+    pub author: String,
+    pub author_email: String,
+    /// seconds since the unix epoch
+    pub created_at: i64,
+    /// empty for posts. for comments the message ids of the post and every comment down to the
+    /// one replied to, like the References header
+    pub references: Vec<String>,
+}
+
+/// sends articles, like NNTP's TAKETHIS. usually the ones a [`CheckRequest`] asked for
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TakeThisRequest {
+    pub articles: Vec<FeedArticle>,
+    pub peer: PeerCredentials,
+}
+
+/// the response to `/peer/check`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CheckResponse {
+    /// the offered message ids the server wants, in the order they were offered
+    pub wanted: Vec<String>,
+}
+
+/// an article a server would not take, and why
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RefusedArticle {
+    pub message_id: String,
+    pub reason: String,
+}
+
+/// the response to `/peer/takethis`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TakeThisResponse {
+    pub accepted: Vec<String>,
+    pub refused: Vec<RefusedArticle>,
+}
+
+/// subscribes to, or unsubscribes from, groups
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SubscribeRequest {
+    /// a group name, or a wildmat like `comp.lang.*` for every group it matches
+    pub group: String,
+    pub author: Credentials,
+}
+
+/// asks for the groups the user subscribed to
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SubscriptionsRequest {
+    pub author: Credentials,
+}
+
+/// marks articles of a group as read, whether or not the user subscribed to it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MarkReadRequest {
+    pub group: String,
+    #[serde(default)]
+    pub articles: Vec<Article>,
+    /// marks every article of the group read, like catching up in a newsreader
+    #[serde(default)]
+    pub all: bool,
+    pub author: Credentials,
+}
+
+/// a group the user subscribed to, as returned by `/subscriptions`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Subscription {
+    pub group: String,
+    /// how many articles of the group the user has not read
+    pub unread: i64,
+    /// the numbers of the articles the user has read, like the ranges of a `.newsrc`: `1-5,7`
+    pub read: String,
+}
+
+/// the response to `/subscriptions`, by group
+pub type SubscriptionsResponse = Vec<Subscription>;
+
+/// how many articles of a group a reader has not read
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UnreadCount {
+    pub group: String,
+    pub unread: i64,
+}
+
+/// waits for new articles, in some groups or in the groups a reader subscribed to
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WatchRequest {
+    /// a group or a wildmat like `comp.lang.*`, leaving it out watches the `reader`'s
+    /// subscriptions
+    #[serde(default)]
+    pub group: Option<String>,
+    #[serde(default)]
+    pub reader: Option<Credentials>,
+    /// only articles after this point, as given by the `next` of the previous response. leaving
+    /// it out only waits for articles that arrive from now on
+    #[serde(default)]
+    pub after: Option<i64>,
+    /// how many seconds to wait at most, the server picks a default and a maximum
+    #[serde(default)]
+    pub timeout: Option<u64>,
+}
+
+/// an article that arrived while watching
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum NewArticle {
+    /// a new post, without comments
+    Post(Post),
+    Comment {
+        group_name: String,
+        /// the post the comment is under
+        post_id: i32,
+        comment: Comment,
+    },
+}
+
+/// the response to `/watch`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WatchResponse {
+    /// oldest first, empty if none arrived before the timeout
+    pub articles: Vec<NewArticle>,
+    /// the `after` of the next watch request
+    pub next: i64,
+}
+
+/// the response to `/post`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PostCreated {
+    pub id: i32,
+    pub message_id: String,
+    /// whether the post waits for a moderator before it shows up in its group
+    pub pending: bool,
+}
+
+/// the response to `/comment`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CommentCreated {
+    pub id: i32,
+    pub message_id: String,
+}
+
+/// the response to `/login`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Session {
+    pub token: String,
+    /// seconds since the unix epoch
+    pub expires_at: i64,
+}
+
+/// an invite code, as returned by `/invites/create` and `/invites/list`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Invite {
+    pub code: String,
+    pub created_by: String,
+    pub created_at: i64,
+    /// who registered with the code, none while it is unused
+    pub used_by: Option<String>,
+    pub used_at: Option<i64>,
+}
+
+/// the response to `/invites/create` and `/invites/list`, newest first
+pub type InvitesResponse = Vec<Invite>;
+
+/// the response to `/users/pending`: the usernames waiting for approval, by name
+pub type PendingUsersResponse = Vec<String>;
+
+/// a group, as returned by `/groups/create` and `/groups/list`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Group {
+    pub name: String,
+    pub description: String,
+    /// seconds since the unix epoch
+    pub created_at: i64,
+    pub creator: String,
+    pub post_count: i64,
+    /// whether only posters of the group can post to it
+    pub restricted: bool,
+    /// whether posts wait for a moderator to approve them
+    pub moderated: bool,
+}
+
+/// the response to `/groups/list`
+pub type GroupsResponse = Vec<Group>;
+
+/// a post, as returned by `/list`. listing a wildmat mixes the posts of several groups, so each
+/// post carries its own `group_name`. its comments are a flat list, oldest first, and each one
+/// points at what it replies to
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Post {
+    pub id: i32,
+    pub group_name: String,
+    pub subject: String,
+    pub body: String,
+    pub author: String,
+    pub author_email: Option<String>,
+    /// seconds since the unix epoch, 0 for posts older than the server recording it
+    pub created_at: i64,
+    /// the globally unique RFC 5536 message id, like `<1700000000.5f3a9c2e1b7d4a60@example.com>`
+    pub message_id: String,
+    /// when the post was last edited, if ever
+    pub edited_at: Option<i64>,
+    /// when the post was cancelled. cancelled posts stay in their place as tombstones, with an
+    /// empty subject, body and author
+    pub cancelled_at: Option<i64>,
+    /// whether the `reader` of the list request has read the post, none without a reader
+    #[serde(default)]
+    pub read: Option<bool>,
+
+    pub comments: Vec<Comment>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub id: i32,
+    /// the comment this one replies to, none for replies to the post itself
+    pub reply_to: Option<i32>,
+    /// the message ids of the post and of every comment above this one, oldest first, like the
+    /// References header of a Usenet article
+    pub references: Vec<String>,
+    pub body: String,
+    pub author: String,
+    pub author_email: Option<String>,
+    /// seconds since the unix epoch, 0 for comments older than the server recording it
+    pub created_at: i64,
+    pub message_id: String,
+    /// when the comment was last edited, if ever
+    pub edited_at: Option<i64>,
+    /// when the comment was cancelled, leaving a tombstone like a cancelled post
+    pub cancelled_at: Option<i64>,
+    /// whether the `reader` of the list request has read the comment, none without a reader
+    #[serde(default)]
+    pub read: Option<bool>,
+}
+
+/// an earlier version of an article, as returned by `/history`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Revision {
+    /// none for comments
+    pub subject: Option<String>,
+    pub body: String,
+    /// when the edit replacing this version was made, in seconds since the unix epoch
+    pub replaced_at: i64,
+}
+
+/// the response to `/history`: the earlier versions of an article, oldest first
+pub type HistoryResponse = Vec<Revision>;
+
+/// a post held back from its group, as returned by `/moderation/queue`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HeldPost {
+    pub post: Post,
+    /// why a moderator rejected the post, none while it is still pending
+    pub rejection: Option<String>,
+}
+
+/// the response to `/moderation/queue`, oldest first
+pub type QueueResponse = Vec<HeldPost>;
+
+/// a post or comment that matched a search
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub article: Article,
+    /// the post itself, or the post a comment is under
+    pub post_id: i32,
+    pub group_name: String,
+    /// the subject of the thread, for comments too
+    pub subject: String,
+    pub author: String,
+    /// seconds since the unix epoch
+    pub created_at: i64,
+    /// the part of the article that matched best, with the matches in `[` and `]`
+    pub snippet: String,
+}
+
+/// the response to `/search`, best match first
+pub type SearchResponse = Vec<SearchHit>;
+
+/// the response to `/list`: one page of posts
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ListResponse {
+    pub posts: Vec<Post>,
+    /// the `after_id` of the next page, if there are more posts
+    pub next: Option<i32>,
+    /// the unread articles of each listed group, when the request named a `reader`
+    #[serde(default)]
+    pub unread: Vec<UnreadCount>,
+}
+
+/// what a failed request carries in the `error` key of the response body
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ErrorBody {
+    pub code: u16,
+    /// a stable, machine readable name for the error, like `unauthorized`
+    pub kind: String,
+    pub message: String,
+    /// how many seconds to wait before trying again, when the client is being throttled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn reads_the_documented_request_format() {
+        let request = NnntpRequest::from_value(json!({
+            "type": "post",
+            "group": "comp.lang.rust",
+            "post": { "subject": "This is a subject", "body": "This is a body" },
+            "author": { "username": "username", "password": "password", "email": "email" },
+        }))
+        .unwrap();
+        assert_eq!(request.username(), Some("username"));
+
+        match request {
+            NnntpRequest::Post(post) => {
+                assert_eq!(post.group, "comp.lang.rust");
+                assert_eq!(
+                    post.author.credentials,
+                    Credentials::Password {
+                        username: "username".to_string(),
+                        password: "password".to_string(),
+                    }
+                );
+            }
+            other => panic!("parsed as {}", other.kind()),
+        }
+    }
+
+    #[test]
+    fn round_trips_token_authors() {
+        let request = NnntpRequest::Comment(CommentRequest {
+            group: "comp.lang.rust".to_string(),
+            parent: Parent { id: 1234 },
+            reply_to: None,
+            comment: CommentContent {
+                body: "This is a comment".to_string(),
+            },
+            author: Author {
+                credentials: Credentials::Token {
+                    token: "abcd".to_string(),
+                },
+                email: "email".to_string(),
+            },
+        });
+
+        // there is no password to guess, so no user to hold it against
+        assert_eq!(request.username(), None);
+        assert_eq!(request.token(), Some("abcd"));
+
+        let value = request.to_value();
+        assert_eq!(value["type"], "comment");
+        assert_eq!(value["author"]["token"], "abcd");
+        assert!(value["author"].get("password").is_none());
+
+        assert_eq!(NnntpRequest::from_value(value).unwrap(), request);
+    }
+
+    #[test]
+    fn rejects_missing_fields_and_wrong_types() {
+        let missing = NnntpRequest::from_value(json!({ "type": "new", "username": "ferris" }));
+        assert!(missing.unwrap_err().contains("password"));
+
+        let wrong = NnntpRequest::from_value(json!({ "type": "list", "group": 5 }));
+        assert!(wrong.is_err());
+
+        let unknown = NnntpRequest::from_value(json!({ "type": "delete" }));
+        assert!(unknown.is_err());
+
+        let groups = NnntpRequest::from_value(json!({ "type": "list_groups" })).unwrap();
+        assert_eq!(groups.kind(), "list_groups");
+    }
+}
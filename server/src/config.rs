@@ -0,0 +1,328 @@
+//! the TOML file the server can be configured with, named by `--config`. every setting has the
+//! default of the command line flag it matches, and flags given on the command line win over
+//! the file.
+//!
+//! on SIGHUP the server reads the file again. what is in `[auth]`, `[limits]`, `[groups]` and
+//! `[logging]` holds for the requests that come in from then on, while `[server]`, `[storage]`
+//! and `[peering]` only change with a restart. relative paths are relative to the directory the
+//! server runs in, like on the command line.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::limits::{Limits, Rate};
+use crate::peering;
+use crate::policy::{self, Policy};
+use crate::settings::{Registration, Settings};
+use crate::storage;
+
+/// the bcrypt costs the bcrypt crate accepts
+const BCRYPT_COSTS: std::ops::RangeInclusive<u32> = 4..=31;
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub storage: StorageConfig,
+    pub auth: AuthConfig,
+    pub limits: LimitsConfig,
+    pub peering: PeeringConfig,
+    pub groups: GroupsConfig,
+    pub logging: LoggingConfig,
+}
+
+/// the listeners, and what the server calls itself
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// the name the jsontp server is started with
+    pub name: String,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    /// also serve newsreaders over NNTP on this port
+    pub nntp_port: Option<u16>,
+    /// the domain message ids of new posts and comments end in
+    pub domain: String,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            name: "NNNTP server".to_string(),
+            host: None,
+            port: None,
+            nntp_port: None,
+            domain: "localhost".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// the directory holding the database, unless `database` names the file itself
+    pub data_dir: PathBuf,
+    pub database: Option<PathBuf>,
+    /// keep everything in memory instead, losing it all when the server stops
+    pub memory: bool,
+}
+
+impl Default for StorageConfig {
+    fn default() -> StorageConfig {
+        StorageConfig {
+            data_dir: PathBuf::from("."),
+            database: None,
+            memory: false,
+        }
+    }
+}
+
+impl StorageConfig {
+    pub fn database_path(&self) -> PathBuf {
+        match &self.database {
+            Some(database) => database.clone(),
+            None => self.data_dir.join(storage::DATABASE_FILE),
+        }
+    }
+}
+
+/// sessions, passwords and who can register
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// how long a session token stays valid, in seconds
+    pub session_ttl: u64,
+    pub bcrypt_cost: u32,
+    pub registration: Registration,
+    /// users made admins at startup and on every reload
+    pub admins: Vec<String>,
+    pub min_username_length: usize,
+    pub max_username_length: usize,
+    pub username_punctuation: String,
+    pub min_password_length: usize,
+    /// a file of passwords to refuse instead of the built in list, read again on every reload
+    pub common_passwords: Option<PathBuf>,
+}
+
+impl Default for AuthConfig {
+    fn default() -> AuthConfig {
+        AuthConfig {
+            session_ttl: 24 * 60 * 60,
+            bcrypt_cost: bcrypt::DEFAULT_COST,
+            registration: Registration::Open,
+            admins: vec![],
+            min_username_length: 3,
+            max_username_length: 32,
+            username_punctuation: "._-".to_string(),
+            min_password_length: 8,
+            common_passwords: None,
+        }
+    }
+}
+
+/// see [`crate::limits`], a rate of 0 is no limit
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub ip_rate: u32,
+    pub user_rate: u32,
+    /// accounts registered a minute, by everyone together
+    pub signup_rate: u32,
+    pub max_failures: u32,
+    /// in seconds
+    pub lockout: u64,
+    /// how many reverse proxies in front of the server add to `X-Forwarded-For`
+    pub trusted_proxies: usize,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> LimitsConfig {
+        LimitsConfig {
+            ip_rate: 600,
+            user_rate: 60,
+            signup_rate: 10,
+            max_failures: 5,
+            lockout: 5 * 60,
+            trusted_proxies: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeeringConfig {
+    /// the file listing the peers, see [`crate::peering`]
+    pub peers: Option<PathBuf>,
+    /// how often new articles are offered to peers, in seconds
+    pub feed_interval: u64,
+}
+
+impl Default for PeeringConfig {
+    fn default() -> PeeringConfig {
+        PeeringConfig {
+            peers: None,
+            feed_interval: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GroupsConfig {
+    /// whether posting to a group that does not exist creates it
+    pub auto_create: bool,
+    /// groups created at startup and on every reload, unless they already exist
+    pub create: Vec<GroupConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GroupConfig {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub restricted: bool,
+    #[serde(default)]
+    pub moderated: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// log every jsontp request, with the client's address, its type and the response code
+    pub requests: bool,
+}
+
+/// reads a config file
+pub fn load(path: &Path) -> Result<Config, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+
+    parse(&text)
+}
+
+fn parse(text: &str) -> Result<Config, String> {
+    toml::from_str(text).map_err(|e| e.to_string())
+}
+
+impl Config {
+    /// the settings the route handlers run with, reading the files the config names
+    pub fn settings(&self) -> Result<Settings, String> {
+        let auth = &self.auth;
+
+        if !BCRYPT_COSTS.contains(&auth.bcrypt_cost) {
+            return Err(format!(
+                "the bcrypt cost has to be between {} and {}",
+                BCRYPT_COSTS.start(),
+                BCRYPT_COSTS.end()
+            ));
+        }
+
+        let peers = match &self.peering.peers {
+            Some(path) => peering::load(path)
+                .map_err(|e| format!("failed to read peers from {}: {}", path.display(), e))?,
+            None => vec![],
+        };
+
+        let mut policy = Policy {
+            min_username_length: auth.min_username_length,
+            max_username_length: auth.max_username_length,
+            username_punctuation: auth.username_punctuation.clone(),
+            min_password_length: auth.min_password_length,
+            ..Policy::default()
+        };
+        if let Some(path) = &auth.common_passwords {
+            policy.common_passwords = policy::load_common_passwords(path)
+                .map_err(|e| format!("failed to read passwords from {}: {}", path.display(), e))?;
+        }
+
+        Ok(Settings {
+            session_ttl: auth.session_ttl,
+            bcrypt_cost: auth.bcrypt_cost,
+            auto_create_groups: self.groups.auto_create,
+            domain: self.server.domain.clone(),
+            peers,
+            policy,
+            registration: auth.registration,
+            log_requests: self.logging.requests,
+            trusted_proxies: self.limits.trusted_proxies,
+        })
+    }
+
+    pub fn limits(&self) -> Limits {
+        let rate = |per_minute| Some(Rate { per_minute }).filter(|_| per_minute > 0);
+
+        Limits {
+            per_ip: rate(self.limits.ip_rate),
+            per_user: rate(self.limits.user_rate),
+            signups: rate(self.limits.signup_rate),
+            max_failures: self.limits.max_failures,
+            lockout: Duration::from_secs(self.limits.lockout),
+        }
+    }
+
+    /// the sections that differ from `other` but only change with a restart
+    pub fn restart_only_changes(&self, other: &Config) -> Vec<&'static str> {
+        let mut changed = vec![];
+
+        if self.server != other.server {
+            changed.push("server");
+        }
+        if self.storage != other.storage {
+            changed.push("storage");
+        }
+        if self.peering != other.peering {
+            changed.push("peering");
+        }
+
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_example_config_has_every_setting() {
+        let config = parse(include_str!("../nnntp.example.toml")).unwrap();
+
+        assert_eq!(config.server.port, Some(8080));
+        assert_eq!(config.auth.registration, Registration::Invite);
+        assert_eq!(config.groups.create[0].name, "comp.lang.rust");
+        assert!(config.groups.create[1].restricted);
+
+        let settings = config.settings().unwrap();
+        assert_eq!(settings.bcrypt_cost, 10);
+        assert!(settings.log_requests);
+    }
+
+    #[test]
+    fn left_out_settings_have_their_defaults() {
+        let config = parse("[server]\nport = 8080\n").unwrap();
+
+        assert_eq!(
+            config,
+            Config {
+                server: ServerConfig {
+                    port: Some(8080),
+                    ..ServerConfig::default()
+                },
+                ..Config::default()
+            }
+        );
+        assert_eq!(config.limits().per_user, Some(Rate { per_minute: 60 }));
+    }
+
+    #[test]
+    fn mistakes_are_not_ignored() {
+        // a typo would otherwise leave a setting at its default without anyone noticing
+        assert!(parse("[auth]\nsession_tll = 60\n").is_err());
+        assert!(parse("[auth]\nregistration = \"closed\"\n").is_err());
+
+        let config = parse("[auth]\nbcrypt_cost = 3\n").unwrap();
+        assert!(config.settings().is_err());
+    }
+}
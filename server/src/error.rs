@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use jsontp::server::*;
+
+use protocol::{ErrorBody, BODY_KEY, ERROR_KEY};
+use serde::Serialize;
+
+use crate::storage::StorageError;
+
+/// everything that can go wrong while handling a request, and the response code it maps to
+#[derive(Debug, Clone, PartialEq)]
+pub enum NnntpError {
+    /// the request is missing a field, or a field has the wrong type
+    BadRequest(String),
+    /// the user does not exist, the password is wrong, or the session token is not valid
+    Unauthorized,
+    /// the user is known, but not allowed to do this
+    Forbidden(String),
+    /// the thing the request refers to, like a group or a parent post, does not exist
+    NotFound(String),
+    /// the request clashes with something that already exists
+    Conflict(String),
+    /// the client sent too many requests or wrong passwords, and has to wait this many seconds
+    TooManyRequests(u64),
+    /// the storage failed, which is never the client's fault
+    Database(String),
+    /// anything else on the server's side
+    Internal(String),
+}
+
+impl NnntpError {
+    pub fn code(&self) -> u16 {
+        match self {
+            NnntpError::BadRequest(_) => 400,
+            NnntpError::Unauthorized => 401,
+            NnntpError::Forbidden(_) => 403,
+            NnntpError::NotFound(_) => 404,
+            NnntpError::Conflict(_) => 409,
+            NnntpError::TooManyRequests(_) => 429,
+            NnntpError::Database(_) | NnntpError::Internal(_) => 500,
+        }
+    }
+
+    /// a stable, machine readable name for the error
+    pub fn kind(&self) -> &'static str {
+        match self {
+            NnntpError::BadRequest(_) => "bad_request",
+            NnntpError::Unauthorized => "unauthorized",
+            NnntpError::Forbidden(_) => "forbidden",
+            NnntpError::NotFound(_) => "not_found",
+            NnntpError::Conflict(_) => "conflict",
+            NnntpError::TooManyRequests(_) => "too_many_requests",
+            NnntpError::Database(_) => "database",
+            NnntpError::Internal(_) => "internal",
+        }
+    }
+
+    /// the message shown to the client. server side failures are logged, not sent back
+    pub fn message(&self) -> String {
+        match self {
+            NnntpError::BadRequest(message)
+            | NnntpError::Forbidden(message)
+            | NnntpError::NotFound(message)
+            | NnntpError::Conflict(message) => message.clone(),
+            NnntpError::Unauthorized => "Invalid user".to_string(),
+            NnntpError::TooManyRequests(seconds) => {
+                format!("Too many requests, try again in {} seconds", seconds)
+            }
+            NnntpError::Database(_) | NnntpError::Internal(_) => {
+                "Internal server error".to_string()
+            }
+        }
+    }
+}
+
+impl fmt::Display for NnntpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NnntpError::Database(e) => write!(f, "database error: {}", e),
+            NnntpError::Internal(e) => write!(f, "internal error: {}", e),
+            _ => write!(f, "{}", self.message()),
+        }
+    }
+}
+
+impl From<StorageError> for NnntpError {
+    fn from(e: StorageError) -> NnntpError {
+        NnntpError::Database(e.to_string())
+    }
+}
+
+/// a successful response: a human readable message, and any extra body keys
+pub struct Reply {
+    pub message: String,
+    pub other: Option<HashMap<String, Value>>,
+}
+
+impl Reply {
+    pub fn new<T: ToString>(message: T) -> Reply {
+        Reply {
+            message: message.to_string(),
+            other: None,
+        }
+    }
+
+    /// adds `key` to the response body
+    pub fn with<T: ToString>(mut self, key: T, value: Value) -> Reply {
+        self.other
+            .get_or_insert_with(HashMap::new)
+            .insert(key.to_string(), value);
+        self
+    }
+
+    /// sets the `nnntp` payload of the response body
+    pub fn payload<T: Serialize>(self, payload: &T) -> Reply {
+        // payloads are plain data, so they always serialize
+        let value = serde_json::to_value(payload).unwrap_or(Value::Null);
+
+        self.with(BODY_KEY, value)
+    }
+}
+
+/// turns the outcome of a handler into a response. errors become their response code, with
+/// an `error` object in the body carrying the code, kind and message, and how long to wait
+/// when the client is throttled
+pub fn respond(req: &JsontpRequest, result: Result<Reply, NnntpError>) -> Response {
+    match result {
+        Ok(reply) => req.to_response(
+            Body::new(reply.message, "identity", reply.other),
+            200,
+            None,
+            Language::default(),
+            None,
+        ),
+        Err(e) => {
+            if e.code() >= 500 {
+                eprintln!("request failed: {}", e);
+            }
+
+            let error = ErrorBody {
+                code: e.code(),
+                kind: e.kind().to_string(),
+                message: e.message(),
+                retry_after: match e {
+                    NnntpError::TooManyRequests(seconds) => Some(seconds),
+                    _ => None,
+                },
+            };
+
+            let mut other = HashMap::new();
+            other.insert(
+                ERROR_KEY.to_string(),
+                serde_json::to_value(&error).unwrap_or(Value::Null),
+            );
+
+            req.to_response(
+                Body::new(e.message(), "identity", Some(other)),
+                e.code(),
+                None,
+                Language::default(),
+                None,
+            )
+        }
+    }
+}
@@ -0,0 +1,615 @@
+//! throttles clients before their requests reach the route handlers: every client gets a token
+//! bucket per IP and per username, and is locked out for a while after too many wrong passwords.
+//!
+//! wrong passwords lock out the IP and username they came with together after `max_failures`,
+//! which is what holds back a guesser at one account. a whole IP, whatever usernames it tries,
+//! and a whole username, wherever it is tried from, are only locked out after
+//! [`WIDE_LOCKOUT_FACTOR`] times as many. that leaves a guesser spread over many addresses more
+//! guesses before it is stopped, and that is the price of not letting anyone who knows a
+//! username, an admin's say, lock its owner out with a few wrong passwords: it takes many more,
+//! and a lockout only turns away passwords, never the sessions the owner already has, nor those
+//! of anyone else behind a locked out IP. for the same reason requests with a password take from
+//! a bucket of their own, apart from the one of the user's sessions, since anyone can send them
+//! for any username. jsontp requests without an IP, see below, only have the limits of their
+//! username.
+//!
+//! jsontp does not tell handlers where a connection comes from, so over jsontp the IP is taken
+//! from the `X-Forwarded-For` header the reverse proxies in front of the server add to. a client
+//! can put anything in the header before it reaches them, so only the entry the first of the
+//! `--trusted-proxies` added counts, which is that many from the end. without trusted proxies the
+//! header is ignored and jsontp requests are not limited by IP at all. the per-username limits
+//! and lockouts hold either way, since they are what stands between a password guesser and the
+//! passwords.
+//!
+//! time is always passed in as an [`Instant`], so the tests can make it pass as they please.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::error::NnntpError;
+
+/// how many requests a client may send: up to `per_minute` at once, and then `per_minute` a minute
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    pub per_minute: u32,
+}
+
+impl Rate {
+    fn per_second(&self) -> f64 {
+        f64::from(self.per_minute) / 60.0
+    }
+}
+
+/// the limits to enforce. the default enforces none
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    /// for every IP, none for no limit
+    pub per_ip: Option<Rate>,
+    /// for every username requests are made as, once for those with its password and once for
+    /// those with its sessions, none for no limit
+    pub per_user: Option<Rate>,
+    /// for registering accounts, shared by everyone since a new user has nothing to go by
+    pub signups: Option<Rate>,
+    /// how many wrong passwords in a row lock a username out from an IP, 0 to never lock anyone
+    /// out. see the module docs for what it takes to lock out a whole IP or username
+    pub max_failures: u32,
+    /// how long a lockout lasts, which is also how long a wrong password is remembered
+    pub lockout: Duration,
+}
+
+/// who a request comes from, as far as the server can tell
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Client<'a> {
+    pub ip: Option<&'a str>,
+    /// the user the request is made as, with their password or a session of theirs
+    pub username: Option<&'a str>,
+    /// whether the request comes with the user's password, which is what a lockout holds back
+    pub password: bool,
+    /// whether the request registers an account
+    pub signup: bool,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// adds the tokens that dripped in since the last update
+    fn refill(&mut self, rate: Rate, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * rate.per_second()).min(f64::from(rate.per_minute));
+        self.updated = now;
+    }
+}
+
+struct Failures {
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// the buckets and failures of every IP, or of every username
+#[derive(Default)]
+struct Tracker {
+    buckets: HashMap<String, Bucket>,
+    failures: HashMap<String, Failures>,
+}
+
+/// once a tracker knows this many clients, it forgets the ones it has nothing against
+const PRUNE_AT: usize = 10_000;
+
+/// how many times `max_failures` wrong passwords lock out a whole IP or username
+pub const WIDE_LOCKOUT_FACTOR: u32 = 10;
+
+impl Tracker {
+    /// how much longer `key` is locked out for, if it is
+    fn locked(&self, key: &str, now: Instant) -> Option<Duration> {
+        let locked_until = self.failures.get(key)?.locked_until?;
+
+        locked_until
+            .checked_duration_since(now)
+            .filter(|wait| !wait.is_zero())
+    }
+
+    /// how long `key` has to wait for a token from its bucket, if at all
+    fn wait(&mut self, key: &str, rate: Option<Rate>, now: Instant) -> Option<Duration> {
+        let rate = rate?;
+        let bucket = self.buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: f64::from(rate.per_minute),
+            updated: now,
+        });
+        bucket.refill(rate, now);
+
+        if bucket.tokens >= 1.0 {
+            return None;
+        }
+
+        Some(Duration::from_secs_f64(
+            (1.0 - bucket.tokens) / rate.per_second(),
+        ))
+    }
+
+    fn take(&mut self, key: &str) {
+        if let Some(bucket) = self.buckets.get_mut(key) {
+            bucket.tokens -= 1.0;
+        }
+    }
+
+    /// counts a wrong password against `key`, locking it out at the `max_failures`th in a row
+    fn fail(&mut self, key: &str, max_failures: u32, limits: &Limits, now: Instant) {
+        let failures = self.failures.entry(key.to_string()).or_insert(Failures {
+            count: 0,
+            last: now,
+            locked_until: None,
+        });
+
+        // wrong passwords are forgotten after a lockout's time, and so are lockouts that ended
+        if now.saturating_duration_since(failures.last) >= limits.lockout
+            || failures.locked_until.is_some_and(|until| until <= now)
+        {
+            failures.count = 0;
+            failures.locked_until = None;
+        }
+
+        failures.count += 1;
+        failures.last = now;
+
+        if max_failures > 0 && failures.count >= max_failures {
+            failures.locked_until = Some(now + limits.lockout);
+        }
+    }
+
+    fn succeed(&mut self, key: &str) {
+        self.failures.remove(key);
+    }
+
+    /// forgets full buckets and failures that are over, which would not hold anyone back anymore
+    fn prune(&mut self, limits: &Limits, rate: Option<Rate>, now: Instant) {
+        if self.buckets.len() + self.failures.len() < PRUNE_AT {
+            return;
+        }
+
+        if let Some(rate) = rate {
+            self.buckets.retain(|_, bucket| {
+                bucket.refill(rate, now);
+                bucket.tokens < f64::from(rate.per_minute)
+            });
+        }
+
+        self.failures.retain(|_, failures| {
+            failures.locked_until.is_some_and(|until| until > now)
+                || now.saturating_duration_since(failures.last) < limits.lockout
+        });
+    }
+}
+
+#[derive(Default)]
+struct State {
+    ips: Tracker,
+    /// the sessions of usernames, and the failures of their passwords
+    users: Tracker,
+    /// the requests that come with the password of a username
+    passwords: Tracker,
+    /// the failures of usernames from IPs, keyed by [`pair`]
+    pairs: Tracker,
+    /// the one bucket of [`SIGNUPS`]
+    signups: Tracker,
+}
+
+/// the key of the bucket every signup takes from
+const SIGNUPS: &str = "signups";
+
+impl Client<'_> {
+    /// the key of the username, which like the username itself ignores case
+    fn user(&self) -> Option<String> {
+        self.username.map(str::to_ascii_lowercase)
+    }
+
+    /// the key of the IP and the username together, when there are both
+    fn pair(&self) -> Option<String> {
+        // neither addresses nor usernames can have spaces
+        Some(format!("{} {}", self.ip?, self.user()?))
+    }
+}
+
+/// enforces [`Limits`], remembering what every client did
+pub struct Limiter {
+    limits: RwLock<Limits>,
+    state: Mutex<State>,
+}
+
+impl Limiter {
+    pub fn new(limits: Limits) -> Limiter {
+        Limiter {
+            limits: RwLock::new(limits),
+            state: Mutex::default(),
+        }
+    }
+
+    fn limits(&self) -> Limits {
+        self.limits
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// enforces other limits from now on. what clients did so far still counts against them
+    pub fn set_limits(&self, limits: Limits) {
+        *self.limits.write().unwrap_or_else(|e| e.into_inner()) = limits;
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// lets a request of `client` through, taking a token from each of its buckets, or fails
+    /// with how long it has to wait when it is locked out or any of its buckets is empty
+    pub fn admit(&self, client: &Client, now: Instant) -> Result<(), NnntpError> {
+        let limits = self.limits();
+        let mut state = self.state();
+        let state = &mut *state;
+
+        state.ips.prune(&limits, limits.per_ip, now);
+        state.users.prune(&limits, limits.per_user, now);
+        state.passwords.prune(&limits, limits.per_user, now);
+        state.pairs.prune(&limits, None, now);
+
+        // lockouts only hold back passwords, so sessions keep working through them
+        let mut waits = vec![];
+        if let Some(ip) = client.ip {
+            waits.push(state.ips.wait(ip, limits.per_ip, now));
+            if client.password {
+                waits.push(state.ips.locked(ip, now));
+            }
+        }
+        if let Some(user) = client.user() {
+            if client.password {
+                waits.push(state.passwords.wait(&user, limits.per_user, now));
+                waits.push(state.users.locked(&user, now));
+                if let Some(pair) = client.pair() {
+                    waits.push(state.pairs.locked(&pair, now));
+                }
+            } else {
+                waits.push(state.users.wait(&user, limits.per_user, now));
+            }
+        }
+        if client.signup {
+            waits.push(state.signups.wait(SIGNUPS, limits.signups, now));
+        }
+
+        if let Some(wait) = waits.into_iter().flatten().max() {
+            // round up, so a client that waits as long as it is told always gets through
+            let seconds = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+            return Err(NnntpError::TooManyRequests(seconds.max(1)));
+        }
+
+        if let Some(ip) = client.ip {
+            state.ips.take(ip);
+        }
+        if let Some(user) = client.user() {
+            match client.password {
+                true => state.passwords.take(&user),
+                false => state.users.take(&user),
+            }
+        }
+        if client.signup {
+            state.signups.take(SIGNUPS);
+        }
+
+        Ok(())
+    }
+
+    /// records whether `client` got the password of its user right. a wrong one counts against
+    /// its IP, its username and the two together, a right one clears the username and the pair
+    /// but not the IP, so that a guesser cannot reset its own count by logging in to an account
+    /// of its own in between
+    pub fn record(&self, client: &Client, succeeded: bool, now: Instant) {
+        let limits = self.limits();
+        let mut state = self.state();
+        let pair = client.pair();
+
+        if succeeded {
+            if let Some(user) = client.user() {
+                state.users.succeed(&user);
+            }
+            if let Some(pair) = &pair {
+                state.pairs.succeed(pair);
+            }
+
+            return;
+        }
+
+        let wide = limits.max_failures.saturating_mul(WIDE_LOCKOUT_FACTOR);
+        if let Some(ip) = client.ip {
+            state.ips.fail(ip, wide, &limits, now);
+        }
+        if let Some(user) = client.user() {
+            state.users.fail(&user, wide, &limits, now);
+        }
+        if let Some(pair) = &pair {
+            state.pairs.fail(pair, limits.max_failures, &limits, now);
+        }
+    }
+}
+
+static LIMITER: OnceLock<Limiter> = OnceLock::new();
+
+/// installs the limiter every request goes through, this can only be done once
+pub fn init(limiter: Limiter) {
+    if LIMITER.set(limiter).is_err() {
+        panic!("the limiter has already been initialised");
+    }
+}
+
+/// the limiter shared by every route handler and the nntp listener
+pub fn limiter() -> &'static Limiter {
+    LIMITER.get().expect("the limiter has not been initialised")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter() -> Limiter {
+        Limiter::new(Limits {
+            per_ip: Some(Rate { per_minute: 6 }),
+            per_user: Some(Rate { per_minute: 3 }),
+            signups: Some(Rate { per_minute: 2 }),
+            max_failures: 2,
+            lockout: Duration::from_secs(60),
+        })
+    }
+
+    const FERRIS: Client = Client {
+        ip: Some("192.0.2.1"),
+        username: Some("ferris"),
+        password: true,
+        signup: false,
+    };
+
+    #[test]
+    fn buckets_empty_and_refill_over_time() {
+        let limiter = limiter();
+        let start = Instant::now();
+        let anonymous = Client {
+            ip: FERRIS.ip,
+            ..Client::default()
+        };
+
+        for _ in 0..3 {
+            limiter.admit(&FERRIS, start).unwrap();
+        }
+        // ferris spent all of their tokens, but the IP has some left for other requests
+        assert_eq!(
+            limiter.admit(&FERRIS, start),
+            Err(NnntpError::TooManyRequests(20))
+        );
+        for _ in 0..3 {
+            limiter.admit(&anonymous, start).unwrap();
+        }
+        assert_eq!(
+            limiter.admit(&anonymous, start),
+            Err(NnntpError::TooManyRequests(10))
+        );
+
+        // the user bucket gets a token every 20 seconds, and the IP bucket one every 10
+        limiter
+            .admit(&FERRIS, start + Duration::from_secs(20))
+            .unwrap();
+        assert!(limiter
+            .admit(&FERRIS, start + Duration::from_secs(21))
+            .is_err());
+
+        // clients the server cannot tell apart are not limited
+        for _ in 0..10 {
+            limiter.admit(&Client::default(), start).unwrap();
+        }
+    }
+
+    #[test]
+    fn signups_share_a_bucket_whoever_sends_them() {
+        let limiter = limiter();
+        let start = Instant::now();
+        let signup = |ip| Client {
+            ip,
+            signup: true,
+            ..Client::default()
+        };
+
+        limiter.admit(&signup(Some("192.0.2.1")), start).unwrap();
+        limiter.admit(&signup(None), start).unwrap();
+        assert_eq!(
+            limiter.admit(&signup(Some("192.0.2.2")), start),
+            Err(NnntpError::TooManyRequests(30))
+        );
+        limiter.admit(&Client::default(), start).unwrap();
+        limiter
+            .admit(&signup(None), start + Duration::from_secs(30))
+            .unwrap();
+    }
+
+    #[test]
+    fn wrong_passwords_lock_clients_out_for_a_while() {
+        let limiter = limiter();
+        let start = Instant::now();
+        let other_ip = Client {
+            ip: Some("192.0.2.2"),
+            ..FERRIS
+        };
+        let other_user = Client {
+            username: Some("corro"),
+            ..FERRIS
+        };
+        let session = Client {
+            password: false,
+            ..FERRIS
+        };
+
+        limiter.record(&FERRIS, false, start);
+        limiter.record(&FERRIS, true, start);
+        // the right password cleared the count
+        limiter.record(&FERRIS, false, start);
+        limiter.admit(&FERRIS, start).unwrap();
+        limiter.record(&FERRIS, false, start);
+
+        // two wrong passwords in a row lock out ferris, but only from that IP
+        assert_eq!(
+            limiter.admit(&FERRIS, start),
+            Err(NnntpError::TooManyRequests(60))
+        );
+        limiter.admit(&other_ip, start).unwrap();
+        limiter.admit(&other_user, start).unwrap();
+        // and the sessions of ferris keep working
+        limiter.admit(&session, start).unwrap();
+        // however they spell their name
+        assert!(limiter
+            .admit(
+                &Client {
+                    username: Some("Ferris"),
+                    ..FERRIS
+                },
+                start
+            )
+            .is_err());
+
+        assert!(limiter
+            .admit(&FERRIS, start + Duration::from_secs(59))
+            .is_err());
+        limiter
+            .admit(&FERRIS, start + Duration::from_secs(60))
+            .unwrap();
+    }
+
+    #[test]
+    fn only_many_wrong_passwords_lock_out_a_whole_ip_or_username() {
+        let limiter = limiter();
+        let start = Instant::now();
+        let ips: Vec<String> = (0..20).map(|i| format!("198.51.100.{}", i)).collect();
+        let usernames: Vec<String> = (0..20).map(|i| format!("user{}", i)).collect();
+
+        // a guesser spread over many addresses, each staying under the lockout of its own
+        for ip in &ips[..19] {
+            let client = Client {
+                ip: Some(ip),
+                ..FERRIS
+            };
+            limiter.record(&client, false, start);
+        }
+        limiter.admit(&FERRIS, start).unwrap();
+        let client = Client {
+            ip: Some(&ips[19]),
+            ..FERRIS
+        };
+        limiter.record(&client, false, start);
+        assert!(limiter.admit(&FERRIS, start).is_err());
+        limiter
+            .admit(
+                &Client {
+                    password: false,
+                    ..FERRIS
+                },
+                start,
+            )
+            .unwrap();
+
+        // and one trying many usernames, which locks out the passwords of everyone behind its IP
+        let guesser = Client {
+            ip: Some("192.0.2.9"),
+            password: true,
+            ..Client::default()
+        };
+        for username in &usernames {
+            let client = Client {
+                username: Some(username),
+                ..guesser
+            };
+            limiter.record(&client, false, start);
+        }
+        assert!(limiter
+            .admit(
+                &Client {
+                    username: Some("corro"),
+                    ..guesser
+                },
+                start
+            )
+            .is_err());
+        // but not their sessions
+        limiter
+            .admit(
+                &Client {
+                    username: Some("corro"),
+                    password: false,
+                    ..guesser
+                },
+                start,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn passwords_sent_by_anyone_do_not_use_up_the_tokens_of_sessions() {
+        let limiter = limiter();
+        let start = Instant::now();
+        let guesser = Client { ip: None, ..FERRIS };
+        let session = Client {
+            password: false,
+            ..FERRIS
+        };
+
+        for _ in 0..3 {
+            limiter.admit(&guesser, start).unwrap();
+        }
+        assert!(limiter.admit(&guesser, start).is_err());
+        assert!(limiter.admit(&FERRIS, start).is_err());
+        for _ in 0..3 {
+            limiter.admit(&session, start).unwrap();
+        }
+        assert!(limiter.admit(&session, start).is_err());
+    }
+
+    #[test]
+    fn limits_can_change_while_clients_are_counted() {
+        let limiter = Limiter::new(Limits::default());
+        let start = Instant::now();
+
+        limiter.admit(&FERRIS, start).unwrap();
+        limiter.record(&FERRIS, false, start);
+        limiter.set_limits(Limits {
+            per_user: Some(Rate { per_minute: 1 }),
+            max_failures: 2,
+            lockout: Duration::from_secs(60),
+            ..Limits::default()
+        });
+
+        // the wrong password from before the change counts towards the lockout
+        limiter.record(&FERRIS, false, start);
+        assert_eq!(
+            limiter.admit(&FERRIS, start),
+            Err(NnntpError::TooManyRequests(60))
+        );
+
+        // and a lockout that started runs its course, even without limits
+        limiter.set_limits(Limits::default());
+        assert!(limiter.admit(&FERRIS, start).is_err());
+        for _ in 0..10 {
+            limiter
+                .admit(&FERRIS, start + Duration::from_secs(60))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn no_limits_let_everything_through() {
+        let limiter = Limiter::new(Limits::default());
+        let now = Instant::now();
+
+        for _ in 0..100 {
+            limiter.record(&FERRIS, false, now);
+            limiter.admit(&FERRIS, now).unwrap();
+        }
+    }
+}
@@ -1,577 +1,567 @@
-use std::collections::HashMap;
-
-use jsontp::client::*;
 use jsontp::server::*;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use console::style;
+use signal_hook::consts::SIGHUP;
+use signal_hook::iterator::Signals;
+
+mod config;
+mod error;
+mod limits;
+mod message_id;
+mod migrations;
+mod newsrc;
+mod nntp;
+mod peering;
+mod permissions;
+mod policy;
+mod routes;
+mod session;
+mod settings;
+mod storage;
+mod watch;
+mod wildmat;
+
+use config::{Config, GroupConfig, PeeringConfig};
+use limits::Limiter;
+use protocol::Role;
+use settings::Registration;
+use storage::{MemoryStorage, SqliteStorage, Storage};
 
-use bcrypt::{hash, verify, DEFAULT_COST};
+#[derive(Parser)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
 
-use rusqlite::{Connection, Result};
+    /// a TOML file to read the settings from, which the flags below override. the server reads
+    /// it again on SIGHUP
+    #[clap(long, global = true)]
+    config: Option<PathBuf>,
 
-pub struct NnntpRequest {
-    inner: JsontpRequest,
-}
+    #[clap(long)]
+    host: Option<String>,
 
-impl NnntpRequest {
-    fn new(inner: JsontpRequest) -> NnntpRequest {
-        NnntpRequest { inner }
-    }
+    #[clap(long)]
+    port: Option<u16>,
 
-    fn validate(&self) -> Result<(), String> {
-        /*
-        format:
-            {
-                "type": "post", // or list, or comment
-                "group": "comp.lang.rust", // group name
-
-                if (type == "post") {
-                    "post": {
-                        "subject": "This is a subject",
-                        "body": "This is a body",
-                    },
-
-                    "author": {
-                        "username": "username",
-                        "password": "password",
-                        "email": "email",
-                    }
-                }
-
-                if (type == "comment") {
-                    "parent": {
-                        "id": "1234",
-                    },
-
-                    "comment": {
-                        "body": "This is a comment",
-                    },
-
-                    "author": {
-                        "username": "username",
-                        "password": "password",
-                        "email": "email",
-                    }
-                }
-            }
+    /// directory holding the server's database [default: .]
+    #[clap(long, global = true)]
+    data_dir: Option<PathBuf>,
 
-            it then returns the new post id (example: 1234) or the list of posts (example: [{id: 1234, subject: "This is a subject", body: "This is a body", author: "username"}]
-        */
-
-        let request = &self.inner;
-
-        match request.body.other.get("nnntp") {
-            Some(nnntp) => {
-                match nnntp.get("type") {
-                    Some(t) => {
-                        match t.as_str() {
-                            Some("post") => {
-                                if nnntp.get("post").is_none() {
-                                    return Err("post is required".to_string());
-                                }
-
-                                let post = nnntp.get("post").unwrap();
-
-                                if post.get("subject").is_none() {
-                                    return Err("subject is required".to_string());
-                                }
-
-                                if post.get("body").is_none() {
-                                    return Err("body is required".to_string());
-                                }
-
-                                if nnntp.get("author").is_none() {
-                                    return Err("author is required".to_string());
-                                }
-
-                                let author = nnntp.get("author").unwrap();
-
-                                if author.get("username").is_none() {
-                                    return Err("username is required".to_string());
-                                }
-
-                                if author.get("password").is_none() {
-                                    return Err("password is required".to_string());
-                                }
-
-                                if author.get("email").is_none() {
-                                    return Err("email is required".to_string());
-                                }
-                            },
-                            Some("list") => {
-                                // nothing to validate
-                            },
-
-                            Some("new") => {
-                                if nnntp.get("username").is_none() {
-                                    return Err("username is required".to_string());
-                                }
-                                if nnntp.get("password").is_none() {
-                                    return Err("password is required".to_string());
-                                }
-                            },
-
-                            Some("comment") => {
-                                if nnntp.get("parent").is_none() {
-                                    return Err("parent is required".to_string());
-                                }
-                                if nnntp.get("comment").is_none() {
-                                    return Err("comment is required".to_string());
-                                }
-                                if nnntp.get("comment").unwrap().get("body").is_none() {
-                                    return Err("body is required".to_string());
-                                }
-
-                                if nnntp.get("author").is_none() {
-                                    return Err("author is required".to_string());
-                                }
-                                
-                                let author = nnntp.get("author").unwrap();
-
-                                if author.get("username").is_none() {
-                                    return Err("username is required".to_string());
-                                }
-
-                                if author.get("password").is_none() {
-                                    return Err("password is required".to_string());
-                                }
-
-                                if author.get("email").is_none() {
-                                    return Err("email is required".to_string());
-                                }
-                            }
-                            _ => {
-                                return Err("valid type is required".to_string());
-                            }
-                        }
-                    }
-                    None => {
-                        return Err("type is required".to_string());
-                    }
-                }
-            }
-            None => {
-                return Err("nnntp is required".to_string());
-            }
-        }
+    /// path of the database file, overriding `--data-dir`
+    #[clap(long, global = true)]
+    database: Option<PathBuf>,
 
-        Ok(())
-    }
-}
+    /// keep everything in memory instead of a database, losing it all when the server stops
+    #[clap(long, conflicts_with_all = ["data_dir", "database"])]
+    memory: bool,
 
-#[derive(Parser)]
-struct Args {
+    /// how long a session token from /login stays valid, in seconds [default: 86400]
+    #[clap(long)]
+    session_ttl: Option<u64>,
+
+    /// how much work bcrypt puts into hashing new passwords, from 4 to 31 [default: 12]
+    #[clap(long)]
+    bcrypt_cost: Option<u32>,
+
+    /// create groups on their first post, instead of rejecting posts to unknown groups
+    #[clap(long)]
+    auto_create_groups: bool,
+
+    /// the domain message ids of new posts and comments end in, like `<...@news.example.com>`
+    /// [default: localhost]
+    #[clap(long)]
+    domain: Option<String>,
+
+    /// a user to make an admin at startup, can be given more than once. adds to the admins of
+    /// the config file
+    #[clap(long = "admin")]
+    admins: Vec<String>,
+
+    /// also serve newsreaders over NNTP (RFC 3977) on this port
+    #[clap(long)]
+    nntp_port: Option<u16>,
+
+    /// a file listing the servers to exchange articles with, one a line:
+    /// `<domain> <host>:<port> <wildmat of groups> <key> [<wildmat of restricted groups>]`
+    #[clap(long)]
+    peers: Option<PathBuf>,
+
+    /// how often new articles are offered to peers, in seconds [default: 30]
+    #[clap(long)]
+    feed_interval: Option<u64>,
+
+    /// how many requests a minute every IP may send, 0 for no limit. the IP comes from the
+    /// `X-Forwarded-For` header, so this only works with `--trusted-proxies` [default: 600]
+    #[clap(long)]
+    ip_rate: Option<u32>,
+
+    /// how many reverse proxies in front of the server add the address they got a request from
+    /// to `X-Forwarded-For`. the client's address is taken that many entries from the end of the
+    /// header, which is ignored when this is 0 [default: 0]
+    #[clap(long)]
+    trusted_proxies: Option<usize>,
+
+    /// how many requests a minute may be made as every user, with their password or a session, 0
+    /// for no limit [default: 60]
     #[clap(long)]
-    host: String,
+    user_rate: Option<u32>,
 
+    /// how many accounts may be registered a minute, by all clients together, 0 for no limit
+    /// [default: 10]
     #[clap(long)]
-    port: u16,
+    signup_rate: Option<u32>,
+
+    /// how many wrong passwords in a row lock a user out from an IP, 0 to never lock anyone out.
+    /// ten times as many lock out the whole IP, or the user from everywhere [default: 5]
+    #[clap(long)]
+    max_failures: Option<u32>,
+
+    /// how long a lockout lasts, in seconds [default: 300]
+    #[clap(long)]
+    lockout: Option<u64>,
+
+    /// the fewest characters usernames can have [default: 3]
+    #[clap(long)]
+    min_username_length: Option<usize>,
+
+    /// the most characters usernames can have [default: 32]
+    #[clap(long)]
+    max_username_length: Option<usize>,
+
+    /// the characters usernames can have besides letters and digits. `@` is never allowed
+    /// [default: ._-]
+    #[clap(long)]
+    username_punctuation: Option<String>,
+
+    /// the fewest characters passwords can have [default: 8]
+    #[clap(long)]
+    min_password_length: Option<usize>,
+
+    /// a file of passwords to refuse, one a line, instead of the built in list of common ones
+    #[clap(long)]
+    common_passwords: Option<PathBuf>,
+
+    /// who can register: anyone, only users with an invite code from an admin, or anyone, but
+    /// only able to log in once an admin approved them [default: open]
+    #[clap(long, value_enum)]
+    registration: Option<Registration>,
+
+    /// log every request, with the client's address, its type and the response code
+    #[clap(long)]
+    log_requests: bool,
 }
 
-fn comment_on(
-    parent_id: i32,
-    body: &str,
-    author: &str,
-    password: &str,
-    email: &str,
+#[derive(Subcommand)]
+enum Command {
+    /// upgrade the database schema, without starting the server
+    Migrate {
+        /// only show the current schema version and the pending migrations
+        #[clap(long, conflicts_with = "to")]
+        status: bool,
+
+        /// migrate up to this version instead of the latest one
+        #[clap(long)]
+        to: Option<u32>,
+    },
+
+    /// erase the content of every cancelled post and comment, leaving only their tombstones
+    Purge,
+}
+
+/// replaces `setting` with `flag`, when the flag was given
+fn set<T: Clone>(setting: &mut T, flag: &Option<T>) {
+    if let Some(value) = flag {
+        *setting = value.clone();
+    }
+}
+
+/// the config file, if there is one, with the flags given on the command line on top
+fn configure(args: &Args) -> Result<Config, String> {
+    let mut config = match &args.config {
+        Some(path) => config::load(path)
+            .map_err(|e| format!("failed to read the config from {}: {}", path.display(), e))?,
+        None => Config::default(),
+    };
+
+    let server = &mut config.server;
+    server.host = args.host.clone().or(server.host.take());
+    server.port = args.port.or(server.port);
+    server.nntp_port = args.nntp_port.or(server.nntp_port);
+    set(&mut server.domain, &args.domain);
+
+    let storage = &mut config.storage;
+    if args.memory {
+        storage.memory = true;
+    }
+    if args.data_dir.is_some() || args.database.is_some() {
+        storage.memory = false;
+    }
+    set(&mut storage.data_dir, &args.data_dir);
+    storage.database = args.database.clone().or(storage.database.take());
+
+    let auth = &mut config.auth;
+    set(&mut auth.session_ttl, &args.session_ttl);
+    set(&mut auth.bcrypt_cost, &args.bcrypt_cost);
+    set(&mut auth.registration, &args.registration);
+    auth.admins.extend(args.admins.iter().cloned());
+    set(&mut auth.min_username_length, &args.min_username_length);
+    set(&mut auth.max_username_length, &args.max_username_length);
+    set(&mut auth.username_punctuation, &args.username_punctuation);
+    set(&mut auth.min_password_length, &args.min_password_length);
+    auth.common_passwords = args
+        .common_passwords
+        .clone()
+        .or(auth.common_passwords.take());
+
+    let limits = &mut config.limits;
+    set(&mut limits.ip_rate, &args.ip_rate);
+    set(&mut limits.user_rate, &args.user_rate);
+    set(&mut limits.signup_rate, &args.signup_rate);
+    set(&mut limits.max_failures, &args.max_failures);
+    set(&mut limits.lockout, &args.lockout);
+    set(&mut limits.trusted_proxies, &args.trusted_proxies);
+
+    config.peering.peers = args.peers.clone().or(config.peering.peers.take());
+    set(&mut config.peering.feed_interval, &args.feed_interval);
+
+    if args.auto_create_groups {
+        config.groups.auto_create = true;
+    }
+    if args.log_requests {
+        config.logging.requests = true;
+    }
+
+    Ok(config)
+}
+
+fn migrate_command(
+    database: &Path,
+    data_dir: &Path,
+    status: bool,
+    to: Option<u32>,
 ) -> Result<(), String> {
-    if !verify_user(author, password).unwrap() {
-        return Err("Invalid user".to_string());
+    let mut conn = rusqlite::Connection::open(database).map_err(|e| e.to_string())?;
+    let current = migrations::current_version(&conn)?;
+
+    if status {
+        println!(
+            "{} is at schema version {} (latest is {})",
+            database.display(),
+            current,
+            migrations::latest_version()
+        );
+
+        for migration in migrations::MIGRATIONS {
+            let state = if migration.version <= current {
+                style("applied").green()
+            } else {
+                style("pending").yellow()
+            };
+
+            println!(
+                "  {:>3} {} {}",
+                migration.version, state, migration.description
+            );
+        }
+
+        return Ok(());
     }
 
-    let conn = Connection::open("posts.db").unwrap();
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS comments (
-            parent_id INTEGER NOT NULL,
-            body TEXT NOT NULL,
-            author TEXT NOT NULL,
-            author_email TEXT NOT NULL
-        )",
-        [],
-    ).unwrap();
-
-    conn.execute(
-        "INSERT INTO comments (parent_id, body, author, author_email) VALUES (?1, ?2, ?3, ?4)",
-        rusqlite::params![parent_id, body, author, email],
-    ).unwrap();
+    let target = to.unwrap_or_else(migrations::latest_version);
+    let applied = migrations::upgrade(&mut conn, Some(data_dir), target)?;
+
+    if applied.is_empty() {
+        println!(
+            "{} is already at schema version {}",
+            database.display(),
+            migrations::current_version(&conn)?
+        );
+    }
 
     Ok(())
 }
 
-fn save_new_user(username: &str, password: &str) -> Result<(), String> {
-    let conn = Connection::open("users.db").unwrap();
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS users (
-            id INTEGER PRIMARY KEY,
-            username TEXT NOT NULL,
-            password TEXT NOT NULL
-        )",
-        [],
-    )
-    .unwrap();
-
-    // first, check if the user already exists
-    let mut stmt = conn
-        .prepare("SELECT username FROM users WHERE username = ?1")
-        .unwrap();
-    let mut rows = stmt.query([username]).unwrap();
-
-    if rows.next().unwrap().is_some() {
-        return Err("User already exists".to_string());
-    }
+fn purge_command(database: &Path, data_dir: &Path) -> Result<(), String> {
+    let storage = SqliteStorage::open(database, Some(data_dir))?;
+    let purged = storage.purge_cancelled().map_err(|e| e.to_string())?;
 
-    let hashed = hash(password, DEFAULT_COST).unwrap();
-    conn.execute(
-        "INSERT INTO users (username, password) VALUES (?1, ?2)",
-        [username, &hashed],
-    )
-    .unwrap();
+    println!("erased {} cancelled articles", purged);
 
     Ok(())
 }
 
-fn verify_user(username: &str, password: &str) -> Result<bool, String> {
-    let conn = Connection::open("users.db").unwrap();
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS users (
-            id INTEGER PRIMARY KEY,
-            username TEXT NOT NULL,
-            password TEXT NOT NULL
-        )",
-        [],
-    )
-    .unwrap();
-    let mut stmt = conn
-        .prepare("SELECT password FROM users WHERE username = ?1")
-        .unwrap();
-    let mut rows = stmt.query([username]).unwrap();
-
-    let hashed: String = rows.next().unwrap().unwrap().get(0).unwrap();
-    let result = verify(password, &hashed).unwrap();
-
-    Ok(result)
+/// gives every user named with `--admin` or in the config the admin role, which then stays in the
+/// database. they are approved too, so that the first admin of a server that needs approval can
+/// log in
+fn make_admins(admins: &[String]) -> Result<(), String> {
+    let storage = storage::storage();
+
+    for admin in admins {
+        let admin = match storage
+            .registered_username(admin)
+            .map_err(|e| e.to_string())?
+        {
+            Some(admin) => admin,
+            None => {
+                eprintln!("{} is not a user, so it cannot be an admin", admin);
+                continue;
+            }
+        };
+        // deleted accounts keep their username, but nobody can log in as them anymore
+        if storage
+            .password_hash(&admin)
+            .map_err(|e| e.to_string())?
+            .is_none()
+        {
+            eprintln!("{} deleted their account, so it cannot be an admin", admin);
+            continue;
+        }
+
+        if storage.approve_user(&admin).map_err(|e| e.to_string())? {
+            println!("approved {}", admin);
+        }
+        if storage
+            .grant_role(&admin, Role::Admin.as_str(), "")
+            .map_err(|e| e.to_string())?
+        {
+            println!("made {} an admin", admin);
+        }
+    }
+
+    Ok(())
 }
 
-fn post_to_group(
-    group: &str,
-    subject: &str,
-    body: &str,
-    author: &str,
-    password: &str,
-    email: &str,
-) -> Result<i32, String> {
-    if !verify_user(author, password).unwrap() {
-        return Err("Invalid user".to_string());
+/// creates the groups the config lists, unless they already exist. they have no creator, and so
+/// no moderator until an admin grants the role
+fn create_groups(groups: &[GroupConfig]) -> Result<(), String> {
+    let storage = storage::storage();
+
+    for group in groups {
+        if !routes::valid_group_name(&group.name) {
+            return Err(format!("{} is not a valid group name", group.name));
+        }
+
+        if !storage
+            .insert_group(&group.name, &group.description, session::now(), "")
+            .map_err(|e| e.to_string())?
+        {
+            continue;
+        }
+
+        if group.restricted {
+            storage
+                .set_group_restricted(&group.name, true)
+                .map_err(|e| e.to_string())?;
+        }
+        if group.moderated {
+            storage
+                .set_group_moderated(&group.name, true)
+                .map_err(|e| e.to_string())?;
+        }
+
+        println!("created group {}", group.name);
     }
 
-    let conn = Connection::open("posts.db").unwrap();
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS posts (
-            id INTEGER PRIMARY KEY,
-            group_name TEXT NOT NULL,
-            subject TEXT NOT NULL,
-            body TEXT NOT NULL,
-            author TEXT NOT NULL,
-            author_email TEXT NOT NULL
-        )",
-        [],
-    )
-    .unwrap();
-
-    conn.execute(
-        "INSERT INTO posts (group_name, subject, body, author, author_email) VALUES (?1, ?2, ?3, ?4, ?5)",
-        [group, subject, body, author, email],
-    )
-    .unwrap();
-
-    // now return the new post id
-    let mut stmt = conn.prepare("SELECT id FROM posts WHERE group_name = ?1 AND subject = ?2 AND body = ?3 AND author = ?4").unwrap();
-    let mut rows = stmt.query([group, subject, body, author]).unwrap();
-
-    let id: i32 = rows.next().unwrap().unwrap().get(0).unwrap();
-
-    Ok(id)
+    Ok(())
 }
 
-fn main() {
-    let args: Args = Args::parse();
+/// reads the config again and applies everything that can change without a restart. the
+/// server keeps running as it was when anything is wrong with it
+fn reload(args: &Args, running: &Config) -> Result<(), String> {
+    let config = configure(args)?;
 
-    let mut server = Server::new("NNNTP server", args.host, args.port);
-
-    server.route("/comment",
-        |req: JsontpRequest| {
-            let nnntp_req = NnntpRequest::new(req);
-
-            match nnntp_req.validate() {
-                Ok(_) => {
-                    let request = &nnntp_req.inner;
-                    let nnntp = &request.body.other.get("nnntp").unwrap();
-                    let parent_id = nnntp.get("parent").unwrap().get("id").unwrap().as_i64().unwrap() as i32;
-                    let body = nnntp.get("comment").unwrap().get("body").unwrap().as_str().unwrap();
-                    let author_obj = nnntp.get("author").unwrap();
-                    let author = author_obj.get("username").unwrap().as_str().unwrap();
-                    let password = author_obj.get("password").unwrap().as_str().unwrap();
-                    let email = author_obj.get("email").unwrap().as_str().unwrap();
-
-                    match comment_on(parent_id, body, author, password, email) {
-                        Ok(_) => nnntp_req.inner.to_response(
-                            Body::new("Commented OK", "identity", None),
-                            200,
-                            None,
-                            Language::default(),
-                            None,
-                        ),
-                        Err(e) => {
-                            match e.as_str() {
-                                "Invalid user" => nnntp_req.inner.to_response(
-                                    Body::new("Invalid user", "identity", None),
-                                    401,
-                                    None,
-                                    Language::default(),
-                                    None,
-                                ),
-                                _ => nnntp_req.inner.to_response(
-                                    Body::new("Failed to comment", "identity", None),
-                                    400,
-                                    None,
-                                    Language::default(),
-                                    None,
-                                ),
-                            }
-                        }
-                    }
-                }
-                Err(e) => nnntp_req.inner.to_response(
-                    Body::new(format!("bad request - {}", e), "identity", None),
-                    400,
-                    None,
-                    Language::default(),
-                    None,
-                ),
-            }
+    for section in config.restart_only_changes(running) {
+        eprintln!(
+            "[{}] changed, which only takes effect after a restart",
+            section
+        );
+    }
+
+    // the peers are not even read again, their threads keep feeding the ones they started with
+    let config = Config {
+        server: running.server.clone(),
+        storage: running.storage.clone(),
+        peering: PeeringConfig {
+            peers: None,
+            ..running.peering.clone()
         },
-    );
+        ..config
+    };
+    let mut settings = config.settings()?;
+    settings.peers = settings::settings().peers.clone();
 
-    server.route("/post", |req: JsontpRequest| {
-        let nnntp_req = NnntpRequest::new(req);
-        match nnntp_req.validate() {
-            Ok(_) => {
-                let request = &nnntp_req.inner;
-                let nnntp = match request.body.other.get("nnntp") {
-                    Some(nnntp) => nnntp,
-                    None => {
-                        return nnntp_req.inner.to_response(
-                            Body::new("", "identity", None),
-                            400,
-                            None,
-                            Language::default(),
-                            None,
-                        )
-                    }
-                };
-                
-                let post = match nnntp.get("post") {
-                    Some(post) => post,
-                    None => {
-                        return nnntp_req.inner.to_response(
-                            Body::new("", "identity", None),
-                            400,
-                            None,
-                            Language::default(),
-                            None,
-                        )
-                    }
-                };
-
-                let subject = post.get("subject").unwrap().as_str().unwrap();
-                let body = post.get("body").unwrap().as_str().unwrap();
-                
-                let author_obj = match nnntp.get("author") {
-                    Some(author) => author,
-                    None => {
-                        return nnntp_req.inner.to_response(
-                            Body::new("", "identity", None),
-                            400,
-                            None,
-                            Language::default(),
-                            None,
-                        )
-                    }
-                };
-
-                let author = author_obj.get("username").unwrap().as_str().unwrap();
-                let password = author_obj.get("password").unwrap().as_str().unwrap();
-                let email = author_obj.get("email").unwrap().as_str().unwrap();
-
-                
-
-                let group = request
-                    .body
-                    .other
-                    .get("nnntp")
-                    .unwrap()
-                    .get("group")
-                    .unwrap()
-                    .as_str()
-                    .unwrap();
-
-                match post_to_group(group, subject, body, author, password, email) {
-                    Ok(id) => {
-                        let mut hs = HashMap::new();
-                        hs.insert("id".to_string(), Value::Number(id.into()));
-                        nnntp_req.inner.to_response(
-                            Body::new("Posted OK", "identity", Some(hs)),
-                            200,
-                            None,
-                            Language::default(),
-                            None,
-                        )
-                    }
-                    Err(e) => {
-                        match e.as_str() {
-                            "Invalid user" => nnntp_req.inner.to_response(
-                                Body::new("Invalid user", "identity", None),
-                                401,
-                                None,
-                                Language::default(),
-                                None,
-                            ),
-                            _ => nnntp_req.inner.to_response(
-                                Body::new("Failed to post", "identity", None),
-                                400,
-                                None,
-                                Language::default(),
-                                None,
-                            ),
-                        }
-                    }
-                }
-            }
-            Err(e) => nnntp_req.inner.to_response(
-                Body::new(format!("bad request - {}", e), "identity", None),
-                400,
-                None,
-                Language::default(),
-                None,
-            ),
-        }
-    });
+    settings::reload(settings);
+    limits::limiter().set_limits(config.limits());
+    make_admins(&config.auth.admins)?;
+    create_groups(&config.groups.create)?;
+
+    println!("reloaded the config");
+
+    Ok(())
+}
+
+/// reloads the config whenever the server gets a SIGHUP
+fn reload_on_hangup(args: Args, running: Config) -> Result<(), String> {
+    let mut signals = Signals::new([SIGHUP]).map_err(|e| e.to_string())?;
 
-    server.route("/new", |req| {
-        let nnntp_req = NnntpRequest::new(req);
-
-        match nnntp_req.validate() {
-            Ok(_) => {
-                let request = &nnntp_req.inner;
-                let nnntp = &request.body.other.get("nnntp").unwrap();
-                let username = nnntp.get("username").unwrap().as_str().unwrap();
-                let password = nnntp.get("password").unwrap().as_str().unwrap();
-
-                match save_new_user(username, password) {
-                    Ok(_) => nnntp_req.inner.to_response(
-                        Body::new("User created", "identity", None),
-                        200,
-                        None,
-                        Language::default(),
-                        None,
-                    ),
-                    Err(e) => nnntp_req.inner.to_response(
-                        Body::new(e, "identity", None),
-                        400,
-                        None,
-                        Language::default(),
-                        None,
-                    ),
-                }
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            if let Err(e) = reload(&args, &running) {
+                eprintln!("failed to reload the config: {}", e);
             }
-            Err(e) => nnntp_req.inner.to_response(
-                Body::new(format!("bad request - {}", e), "identity", None),
-                400,
-                None,
-                Language::default(),
-                None,
-            ),
         }
     });
 
-    server.route("/list", |req| {
-        let conn = Connection::open("posts.db").unwrap();
-        let mut stmt = conn.prepare("SELECT * FROM posts").unwrap();
+    Ok(())
+}
 
-        // do not use query_map because it returns a Result
-        let rows = stmt.query([]).unwrap();
+fn main() {
+    let args: Args = Args::parse();
 
+    let config = match configure(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let database = config.storage.database_path();
 
-        let mut posts = vec![];
+    if let Some(command) = &args.command {
+        let result = match *command {
+            Command::Migrate { status, to } => {
+                migrate_command(&database, &config.storage.data_dir, status, to)
+            }
+            Command::Purge => purge_command(&database, &config.storage.data_dir),
+        };
 
-        for post in rows.mapped(
-            |row| -> Result<(i32, String, String, String, String, String), rusqlite::Error> {
-                Ok((
-                    row.get(0)?,
-                    row.get(1)?,
-                    row.get(2)?,
-                    row.get(3)?,
-                    row.get(4)?,
-                    row.get(5)?,
-                ))
-            },
-        ) {
-            let (id, group, subject, body, author, email): (i32, String, String, String, String, String) =
-                post.unwrap();
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
 
-            if group != req.body.other.get("nnntp").unwrap().get("group").unwrap().as_str().unwrap() {
-                continue;
+        return;
+    }
+
+    let (host, port) = match (config.server.host.clone(), config.server.port) {
+        (Some(host), Some(port)) => (host, port),
+        _ => {
+            eprintln!("the server needs a host and a port, from --host and --port or the config");
+            std::process::exit(1);
+        }
+    };
+
+    if config.storage.memory {
+        storage::init(Box::new(MemoryStorage::new()));
+    } else {
+        if let Some(dir) = database.parent() {
+            if let Err(e) = std::fs::create_dir_all(dir) {
+                eprintln!("failed to create {}: {}", dir.display(), e);
+                std::process::exit(1);
             }
+        }
 
-            let mut post = serde_json::map::Map::new();
-            post.insert("id".to_string(), Value::Number(id.into()));
-            post.insert("group_name".to_string(), Value::String(group));
-            post.insert("subject".to_string(), Value::String(subject));
-            post.insert("body".to_string(), Value::String(body));
-            post.insert("author".to_string(), Value::String(author));
-            post.insert("author_email".to_string(), Value::String(email));
-
-            // now add the comments
-            let mut stmt = conn.prepare("SELECT * FROM comments WHERE parent_id = ?1").unwrap();
-            let rows = stmt.query([id]).unwrap();
-
-            let mut comments = vec![];
-
-            for comment in rows.mapped(
-                |row| -> Result<(i32, String, String, String), rusqlite::Error> {
-                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
-                },
-            ) {
-                let (_, body, author, email): (i32, String, String, String) = comment.unwrap();
-
-                let mut comment = serde_json::map::Map::new();
-                comment.insert("body".to_string(), Value::String(body));
-                comment.insert("author".to_string(), Value::String(author));
-                comment.insert("author_email".to_string(), Value::String(email));
-
-                comments.push(Value::Object(comment));
+        match SqliteStorage::open(&database, Some(&config.storage.data_dir)) {
+            Ok(opened) => storage::init(Box::new(opened)),
+            Err(e) => {
+                eprintln!("failed to open database {}: {}", database.display(), e);
+                std::process::exit(1);
             }
+        }
+    }
 
-            post.insert("comments".to_string(), Value::Array(comments));
+    match config.settings() {
+        Ok(settings) => settings::init(settings),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    limits::init(Limiter::new(config.limits()));
+
+    if let Err(e) = make_admins(&config.auth.admins) {
+        eprintln!("failed to grant the admin role: {}", e);
+        std::process::exit(1);
+    }
 
-            posts.push(post);
+    if let Err(e) = create_groups(&config.groups.create) {
+        eprintln!("failed to create the groups of the config: {}", e);
+        std::process::exit(1);
+    }
+
+    if let Some(nntp_port) = config.server.nntp_port {
+        if let Err(e) = nntp::listen(&host, nntp_port) {
+            eprintln!("failed to listen for nntp on port {}: {}", nntp_port, e);
+            std::process::exit(1);
         }
 
-        let mut prepared_other: HashMap<String, Value> = HashMap::new();
+        println!("serving nntp on {}:{}", host, nntp_port);
+    }
 
-        prepared_other.insert(
-            "nnntp".to_string(),
-            Value::Array(posts.iter().map(|x| Value::Object(x.clone())).collect()),
-        );
+    peering::start(
+        storage::storage(),
+        settings::settings(),
+        Duration::from_secs(config.peering.feed_interval),
+    );
+
+    let mut server = Server::new(&config.server.name, host, port);
 
-        req.to_response(
-            Body::new("processed OK", "identity", Some(prepared_other)),
-            200,
-            None,
-            Language::default(),
-            None,
-        )
+    server.route("/post", |req| routes::serve(req, "post"));
+    server.route("/comment", |req| routes::serve(req, "comment"));
+    server.route("/list", |req| routes::serve(req, "list"));
+    server.route("/new", |req| routes::serve(req, "new"));
+    server.route("/login", |req| routes::serve(req, "login"));
+    server.route("/logout", |req| routes::serve(req, "logout"));
+    server.route("/account/password", |req| {
+        routes::serve(req, "change_password")
+    });
+    server.route("/account/email", |req| routes::serve(req, "change_email"));
+    server.route("/account/delete", |req| {
+        routes::serve(req, "delete_account")
+    });
+    server.route("/groups/create", |req| routes::serve(req, "create_group"));
+    server.route("/groups/list", |req| routes::serve(req, "list_groups"));
+    server.route("/edit", |req| routes::serve(req, "edit"));
+    server.route("/history", |req| routes::serve(req, "history"));
+    server.route("/cancel", |req| routes::serve(req, "cancel"));
+    server.route("/groups/configure", |req| {
+        routes::serve(req, "configure_group")
     });
+    server.route("/roles/grant", |req| routes::serve(req, "grant_role"));
+    server.route("/roles/revoke", |req| routes::serve(req, "revoke_role"));
+    server.route("/moderation/queue", |req| {
+        routes::serve(req, "moderation_queue")
+    });
+    server.route("/moderation/approve", |req| routes::serve(req, "approve"));
+    server.route("/moderation/reject", |req| routes::serve(req, "reject"));
+    server.route("/search", |req| routes::serve(req, "search"));
+    server.route("/invites/create", |req| {
+        routes::serve(req, "create_invites")
+    });
+    server.route("/invites/list", |req| routes::serve(req, "invites"));
+    server.route("/users/pending", |req| routes::serve(req, "pending_users"));
+    server.route("/users/approve", |req| routes::serve(req, "approve_user"));
+    server.route("/peer/check", |req| routes::serve(req, "check"));
+    server.route("/peer/takethis", |req| routes::serve(req, "takethis"));
+    server.route("/subscribe", |req| routes::serve(req, "subscribe"));
+    server.route("/unsubscribe", |req| routes::serve(req, "unsubscribe"));
+    server.route("/subscriptions", |req| routes::serve(req, "subscriptions"));
+    server.route("/mark-read", |req| routes::serve(req, "mark_read"));
+    server.route("/watch", |req| routes::serve(req, "watch"));
+
+    if let Err(e) = reload_on_hangup(args, config) {
+        eprintln!(
+            "failed to listen for SIGHUP, the config cannot be reloaded: {}",
+            e
+        );
+    }
 
     server.start();
 }
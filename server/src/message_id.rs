@@ -0,0 +1,67 @@
+//! RFC 5536 message ids, the globally unique names of articles, like
+//! `<1700000000.5f3a9c2e1b7d4a60@news.example.com>`.
+
+use crate::error::NnntpError;
+
+/// a fresh message id on `domain`: the creation time and 8 random bytes, so ids from different
+/// servers, or from the same server in the same second, never clash
+pub fn generate(domain: &str, created_at: i64) -> Result<String, NnntpError> {
+    let mut bytes = [0u8; 8];
+    getrandom::getrandom(&mut bytes).map_err(|e| NnntpError::Internal(e.to_string()))?;
+
+    let random: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+
+    Ok(format!("<{}.{}@{}>", created_at, random, domain))
+}
+
+/// whether `message_id` looks like a message id: `<` and `>` around a left and a right hand side
+/// split by a single `@`, at most 250 characters of printable ascii (RFC 5536, section 3.1.3)
+pub fn is_valid(message_id: &str) -> bool {
+    let inner = match message_id
+        .strip_prefix('<')
+        .and_then(|rest| rest.strip_suffix('>'))
+    {
+        Some(inner) => inner,
+        None => return false,
+    };
+
+    let sides_ok = match inner.split_once('@') {
+        Some((left, right)) => !left.is_empty() && !right.is_empty() && !right.contains('@'),
+        None => false,
+    };
+
+    sides_ok
+        && message_id.len() <= 250
+        && inner
+            .chars()
+            .all(|c| c.is_ascii_graphic() && c != '<' && c != '>')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_ids_are_unique() {
+        let first = generate("news.example.com", 1700000000).unwrap();
+        let second = generate("news.example.com", 1700000000).unwrap();
+
+        assert!(first.starts_with("<1700000000."));
+        assert!(first.ends_with("@news.example.com>"));
+        assert_ne!(first, second);
+        assert!(is_valid(&first));
+    }
+
+    #[test]
+    fn checks_the_shape_of_message_ids() {
+        assert!(is_valid("<1700000000.5f3a@news.example.com>"));
+
+        assert!(!is_valid("1700000000.5f3a@news.example.com"));
+        assert!(!is_valid("<1700000000.5f3a>"));
+        assert!(!is_valid("<@news.example.com>"));
+        assert!(!is_valid("<a@b@c>"));
+        assert!(!is_valid("<a b@news.example.com>"));
+        assert!(!is_valid("<a<b@news.example.com>"));
+        assert!(!is_valid(&format!("<{}@example.com>", "a".repeat(250))));
+    }
+}
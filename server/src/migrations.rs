@@ -0,0 +1,827 @@
+use std::path::{Path, PathBuf};
+
+use rusqlite::Connection;
+
+/// a single, ordered change to the database schema
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+/// every migration the server knows about, in the order they are applied.
+/// the version of a database is stored in `PRAGMA user_version`, so never edit or reorder
+/// a migration that has been released, add a new one to the end instead
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create the users, posts and comments tables",
+        // `IF NOT EXISTS` so databases created before migrations existed are adopted as version 1
+        sql: "CREATE TABLE IF NOT EXISTS users (
+            id INTEGER PRIMARY KEY,
+            username TEXT NOT NULL,
+            password TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS posts (
+            id INTEGER PRIMARY KEY,
+            group_name TEXT NOT NULL,
+            subject TEXT NOT NULL,
+            body TEXT NOT NULL,
+            author TEXT NOT NULL,
+            author_email TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS comments (
+            parent_id INTEGER NOT NULL,
+            body TEXT NOT NULL,
+            author TEXT NOT NULL,
+            author_email TEXT NOT NULL
+        );",
+    },
+    Migration {
+        version: 2,
+        description: "create the sessions table",
+        sql: "CREATE TABLE sessions (
+            token TEXT PRIMARY KEY,
+            username TEXT NOT NULL,
+            expires_at INTEGER NOT NULL
+        );
+
+        CREATE INDEX sessions_username ON sessions (username);",
+    },
+    Migration {
+        version: 3,
+        description: "create the groups table, from the groups posts already use",
+        sql: "CREATE TABLE groups (
+            name TEXT PRIMARY KEY,
+            description TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            creator TEXT NOT NULL
+        );
+
+        -- groups used to spring into existence with their first post, so that post's author
+        -- is the closest thing they have to a creator
+        INSERT INTO groups (name, description, created_at, creator)
+            SELECT group_name, '', 0, author FROM posts
+                WHERE id IN (SELECT MIN(id) FROM posts GROUP BY group_name);",
+    },
+    Migration {
+        version: 4,
+        description: "add a creation time to posts, and indexes for paging through groups",
+        sql: "ALTER TABLE posts ADD COLUMN created_at INTEGER NOT NULL DEFAULT 0;
+
+        CREATE INDEX posts_group_name ON posts (group_name, id);
+        CREATE INDEX comments_parent_id ON comments (parent_id);",
+    },
+    Migration {
+        version: 5,
+        description: "give comments ids, and every article a creation time and a message id",
+        sql: "CREATE TABLE comments_with_ids (
+            id INTEGER PRIMARY KEY,
+            parent_id INTEGER NOT NULL,
+            body TEXT NOT NULL,
+            author TEXT NOT NULL,
+            author_email TEXT NOT NULL,
+            created_at INTEGER NOT NULL DEFAULT 0,
+            message_id TEXT NOT NULL
+        );
+
+        -- the migration cannot know the server's domain, so older articles get a random id
+        -- on a reserved one
+        INSERT INTO comments_with_ids (id, parent_id, body, author, author_email, message_id)
+            SELECT rowid, parent_id, body, author, author_email,
+                '<' || lower(hex(randomblob(16))) || '@legacy.invalid>'
+            FROM comments ORDER BY rowid;
+
+        DROP TABLE comments;
+        ALTER TABLE comments_with_ids RENAME TO comments;
+        CREATE INDEX comments_parent_id ON comments (parent_id);
+
+        ALTER TABLE posts ADD COLUMN message_id TEXT;
+        UPDATE posts SET message_id = '<' || lower(hex(randomblob(16))) || '@legacy.invalid>';
+
+        CREATE UNIQUE INDEX posts_message_id ON posts (message_id);
+        CREATE UNIQUE INDEX comments_message_id ON comments (message_id);",
+    },
+    Migration {
+        version: 6,
+        description: "let comments reply to other comments, with a References chain",
+        sql: "ALTER TABLE comments ADD COLUMN reply_to INTEGER;
+        ALTER TABLE comments ADD COLUMN refs TEXT NOT NULL DEFAULT '';
+
+        -- every existing comment replies straight to its post
+        UPDATE comments SET refs = (SELECT message_id FROM posts WHERE posts.id = comments.parent_id);",
+    },
+    Migration {
+        version: 7,
+        description: "allow editing posts and comments, keeping every earlier revision",
+        sql: "ALTER TABLE posts ADD COLUMN edited_at INTEGER;
+        ALTER TABLE comments ADD COLUMN edited_at INTEGER;
+
+        -- the versions edits replaced. `kind` is 'post' or 'comment', and comments have no subject
+        CREATE TABLE revisions (
+            id INTEGER PRIMARY KEY,
+            kind TEXT NOT NULL,
+            article_id INTEGER NOT NULL,
+            subject TEXT,
+            body TEXT NOT NULL,
+            replaced_at INTEGER NOT NULL
+        );
+
+        CREATE INDEX revisions_article ON revisions (kind, article_id);",
+    },
+    Migration {
+        version: 8,
+        description: "allow cancelling posts and comments, keeping a tombstone",
+        sql: "ALTER TABLE posts ADD COLUMN cancelled_at INTEGER;
+        ALTER TABLE posts ADD COLUMN cancelled_by TEXT;
+        ALTER TABLE comments ADD COLUMN cancelled_at INTEGER;
+        ALTER TABLE comments ADD COLUMN cancelled_by TEXT;",
+    },
+    Migration {
+        version: 9,
+        description: "create the roles table, and let groups restrict who can post",
+        sql: "CREATE TABLE roles (
+            username TEXT NOT NULL,
+            role TEXT NOT NULL,
+            -- empty for roles that hold in every group
+            group_name TEXT NOT NULL DEFAULT '',
+            PRIMARY KEY (username, role, group_name)
+        );
+
+        ALTER TABLE groups ADD COLUMN restricted INTEGER NOT NULL DEFAULT 0;
+
+        -- creating a group used to be what made someone its moderator
+        INSERT INTO roles (username, role, group_name)
+            SELECT creator, 'moderator', name FROM groups WHERE creator != '';",
+    },
+    Migration {
+        version: 10,
+        description: "hold the posts of moderated groups until a moderator approves them",
+        sql: "ALTER TABLE groups ADD COLUMN moderated INTEGER NOT NULL DEFAULT 0;
+
+        ALTER TABLE posts ADD COLUMN pending INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE posts ADD COLUMN rejection TEXT;
+
+        CREATE INDEX posts_pending ON posts (pending) WHERE pending = 1;",
+    },
+    Migration {
+        version: 11,
+        description: "index listed posts and comments for full text search",
+        sql: "CREATE VIRTUAL TABLE search_index USING fts5(
+            subject,
+            body,
+            -- 'post' or 'comment', and the id of the article and of the post it is under
+            kind UNINDEXED,
+            article_id UNINDEXED,
+            post_id UNINDEXED,
+            group_name UNINDEXED,
+            author UNINDEXED,
+            created_at UNINDEXED
+        );
+
+        INSERT INTO search_index
+            (subject, body, kind, article_id, post_id, group_name, author, created_at)
+            SELECT subject, body, 'post', id, id, group_name, author, created_at FROM posts
+                WHERE pending = 0 AND rejection IS NULL AND cancelled_at IS NULL;
+
+        INSERT INTO search_index
+            (subject, body, kind, article_id, post_id, group_name, author, created_at)
+            SELECT '', comments.body, 'comment', comments.id, posts.id, posts.group_name,
+                comments.author, comments.created_at
+                FROM comments JOIN posts ON posts.id = comments.parent_id
+                WHERE comments.cancelled_at IS NULL;",
+    },
+    Migration {
+        version: 12,
+        description: "number the articles of each group, for NNTP",
+        sql: "CREATE TABLE article_numbers (
+            group_name TEXT NOT NULL,
+            number INTEGER NOT NULL,
+            -- 'post' or 'comment'
+            kind TEXT NOT NULL,
+            article_id INTEGER NOT NULL,
+            PRIMARY KEY (group_name, number),
+            UNIQUE (kind, article_id)
+        );
+
+        INSERT INTO article_numbers (group_name, number, kind, article_id)
+            SELECT group_name,
+                ROW_NUMBER() OVER (PARTITION BY group_name ORDER BY created_at, kind DESC, id),
+                kind, id
+                FROM (
+                    SELECT group_name, created_at, 'post' AS kind, id FROM posts
+                        WHERE pending = 0 AND rejection IS NULL
+                    UNION ALL
+                    SELECT posts.group_name, comments.created_at, 'comment', comments.id
+                        FROM comments JOIN posts ON posts.id = comments.parent_id
+                );",
+    },
+    Migration {
+        version: 13,
+        description: "record the path of articles and feed them to peers",
+        sql: "ALTER TABLE posts ADD COLUMN path TEXT NOT NULL DEFAULT 'not-for-mail';
+        ALTER TABLE comments ADD COLUMN path TEXT NOT NULL DEFAULT 'not-for-mail';
+
+        -- every article, in the order it was listed, for peers to catch up on
+        CREATE TABLE feed (
+            seq INTEGER PRIMARY KEY AUTOINCREMENT,
+            -- 'post' or 'comment'
+            kind TEXT NOT NULL,
+            article_id INTEGER NOT NULL
+        );
+        INSERT INTO feed (kind, article_id)
+            SELECT kind, article_id FROM article_numbers ORDER BY rowid;
+
+        CREATE TABLE peer_progress (
+            peer TEXT PRIMARY KEY,
+            seq INTEGER NOT NULL
+        );",
+    },
+    Migration {
+        version: 14,
+        description: "keep a newsrc for every user",
+        sql: "CREATE TABLE newsrc (
+            username TEXT NOT NULL,
+            group_name TEXT NOT NULL,
+            subscribed INTEGER NOT NULL DEFAULT 0,
+            -- article numbers as ranges, like '1-5,7'
+            read TEXT NOT NULL DEFAULT '',
+            PRIMARY KEY (username, group_name)
+        );",
+    },
+    Migration {
+        version: 15,
+        description: "store the email of users and allow deleting them",
+        sql: "ALTER TABLE users ADD COLUMN email TEXT NOT NULL DEFAULT '';
+        -- until now every post came with an email, so the latest one the user gave is kept. they
+        -- were never checked, so only those `routes::check_email` would take are, anything else
+        -- could break out of the From header
+        UPDATE users SET email = COALESCE(
+            (SELECT author_email FROM posts WHERE posts.author = users.username
+                AND instr(author_email, '@') > 1
+                AND instr(author_email, '@') < length(author_email)
+                AND instr(substr(author_email, instr(author_email, '@') + 1), '@') = 0
+                AND length(author_email) <= 254
+                AND author_email NOT GLOB '*[^!-~]*'
+                AND author_email NOT GLOB '*[][<>(),;:\\\"]*'
+                ORDER BY created_at DESC, id DESC LIMIT 1),
+            '');
+
+        -- deleted users keep their row, so their username cannot be registered again
+        ALTER TABLE users ADD COLUMN deleted_at INTEGER;",
+    },
+    Migration {
+        version: 16,
+        description: "make usernames unique, whatever their case",
+        sql: "-- the same username twice could only come from two registrations racing, and only the
+        -- first of them could ever log in
+        DELETE FROM users WHERE EXISTS
+            (SELECT 1 FROM users AS first
+                WHERE first.username = users.username AND first.id < users.id);
+
+        -- usernames differing only in case belonged to different users until now. all but the
+        -- first get their id appended, everywhere the username is used, so they keep working.
+        -- should that name be taken too, the id is appended again until it is not, and since
+        -- every new name ends in the id of its user no two of them can be the same. the renames
+        -- are kept so the server can say who has to be told
+        CREATE TABLE renamed_users (
+            old TEXT NOT NULL,
+            new TEXT NOT NULL
+        );
+
+        WITH RECURSIVE candidates (id, old, new) AS (
+            SELECT id, username, username || '-' || id FROM users WHERE EXISTS
+                (SELECT 1 FROM users AS first
+                    WHERE first.username = users.username COLLATE NOCASE AND first.id < users.id)
+            UNION ALL
+            SELECT id, old, new || '-' || id FROM candidates WHERE EXISTS
+                (SELECT 1 FROM users WHERE username = candidates.new COLLATE NOCASE)
+        )
+        INSERT INTO renamed_users (old, new)
+            SELECT old, new FROM candidates WHERE NOT EXISTS
+                (SELECT 1 FROM users WHERE username = candidates.new COLLATE NOCASE)
+            ORDER BY id;
+
+        UPDATE users SET username = (SELECT new FROM renamed_users WHERE old = username)
+            WHERE username IN (SELECT old FROM renamed_users);
+        UPDATE sessions SET username = (SELECT new FROM renamed_users WHERE old = username)
+            WHERE username IN (SELECT old FROM renamed_users);
+        UPDATE roles SET username = (SELECT new FROM renamed_users WHERE old = username)
+            WHERE username IN (SELECT old FROM renamed_users);
+        UPDATE newsrc SET username = (SELECT new FROM renamed_users WHERE old = username)
+            WHERE username IN (SELECT old FROM renamed_users);
+        UPDATE groups SET creator = (SELECT new FROM renamed_users WHERE old = creator)
+            WHERE creator IN (SELECT old FROM renamed_users);
+        UPDATE posts SET author = (SELECT new FROM renamed_users WHERE old = author)
+            WHERE author IN (SELECT old FROM renamed_users);
+        UPDATE posts SET cancelled_by = (SELECT new FROM renamed_users WHERE old = cancelled_by)
+            WHERE cancelled_by IN (SELECT old FROM renamed_users);
+        UPDATE comments SET author = (SELECT new FROM renamed_users WHERE old = author)
+            WHERE author IN (SELECT old FROM renamed_users);
+        UPDATE comments SET cancelled_by = (SELECT new FROM renamed_users WHERE old = cancelled_by)
+            WHERE cancelled_by IN (SELECT old FROM renamed_users);
+        UPDATE search_index SET author = (SELECT new FROM renamed_users WHERE old = author)
+            WHERE author IN (SELECT old FROM renamed_users);
+
+        CREATE UNIQUE INDEX users_username ON users (username COLLATE NOCASE);",
+    },
+    Migration {
+        version: 17,
+        description: "hold new users for approval and let them in with invites",
+        sql: "ALTER TABLE users ADD COLUMN pending INTEGER NOT NULL DEFAULT 0;
+
+        CREATE TABLE invites (
+            code TEXT PRIMARY KEY,
+            created_by TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            -- both NULL while the code is unused
+            used_by TEXT,
+            used_at INTEGER
+        );",
+    },
+];
+
+/// the version a fully migrated database is at
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+/// the schema version the database is currently at
+pub fn current_version(conn: &Connection) -> Result<u32, String> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+        .map_err(|e| e.to_string())
+}
+
+/// applies every pending migration up to and including `target`, returning the versions applied.
+/// each migration runs in its own transaction, together with the version bump, so a failing
+/// migration leaves the database at the last version that succeeded
+pub fn migrate_to(conn: &mut Connection, target: u32) -> Result<Vec<u32>, String> {
+    let current = current_version(conn)?;
+
+    if target > latest_version() {
+        return Err(format!(
+            "there is no migration {}, the latest is {}",
+            target,
+            latest_version()
+        ));
+    }
+
+    if target < current {
+        return Err(format!(
+            "the database is at version {}, downgrading to {} is not supported",
+            current, target
+        ));
+    }
+
+    let mut applied = vec![];
+
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|m| m.version > current && m.version <= target)
+    {
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+        tx.execute_batch(migration.sql)
+            .and_then(|_| tx.pragma_update(None, "user_version", migration.version))
+            .and_then(|_| tx.commit())
+            .map_err(|e| format!("migration {} failed: {}", migration.version, e))?;
+
+        applied.push(migration.version);
+    }
+
+    Ok(applied)
+}
+
+/// what every way of opening the database goes through: imports the databases of old servers in
+/// `legacy_dir` into a database that is new, then applies the migrations up to `target`, saying
+/// what it did. returns the versions applied
+pub fn upgrade(
+    conn: &mut Connection,
+    legacy_dir: Option<&Path>,
+    target: u32,
+) -> Result<Vec<u32>, String> {
+    if let Some(dir) = legacy_dir {
+        for imported in import_legacy(conn, dir)? {
+            println!("imported {}", imported.display());
+        }
+    }
+
+    let applied = migrate_to(conn, target)?;
+
+    for &version in &applied {
+        println!("applied database migration {}", version);
+
+        if version == 16 {
+            for (old, new) in renamed_users(conn)? {
+                println!(
+                    "renamed user {} to {}, tell them to log in as that",
+                    old, new
+                );
+            }
+        }
+    }
+
+    Ok(applied)
+}
+
+/// the users migration 16 renamed because their names only differed in case from an older
+/// one's, as their old and new names. they log in with the new name from then on
+pub fn renamed_users(conn: &Connection) -> Result<Vec<(String, String)>, String> {
+    if current_version(conn)? < 16 {
+        return Ok(vec![]);
+    }
+
+    conn.prepare("SELECT old, new FROM renamed_users ORDER BY rowid")
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect()
+        })
+        .map_err(|e| e.to_string())
+}
+
+/// the databases servers kept their users and their articles in before everything moved into a
+/// single file, with the tables that were in each
+const LEGACY_DATABASES: &[(&str, &[&str])] = &[
+    ("users.db", &["users"]),
+    ("posts.db", &["posts", "comments"]),
+];
+
+/// copies what is in the databases of old servers in `dir` into a database that has not been
+/// created yet, returning the files imported. the old files are left as they were
+pub fn import_legacy(conn: &mut Connection, dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let legacy: Vec<(PathBuf, &[&str])> = LEGACY_DATABASES
+        .iter()
+        .map(|(file, tables)| (dir.join(file), *tables))
+        .filter(|(path, _)| path.is_file())
+        .collect();
+
+    if legacy.is_empty() || current_version(conn)? != 0 {
+        return Ok(vec![]);
+    }
+
+    // sqlite cannot attach or detach databases in the middle of a transaction
+    for (index, (path, _)) in legacy.iter().enumerate() {
+        let file = path
+            .to_str()
+            .ok_or("the path of a database is not valid UTF-8")?;
+        conn.execute(&format!("ATTACH DATABASE ?1 AS legacy{}", index), [file])
+            .map_err(|e| format!("failed to open {}: {}", path.display(), e))?;
+    }
+
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+    // the old tables are those of the first migration, which the others then build on. it is
+    // applied along with the import, so a failed import can be tried again on the next start
+    tx.execute_batch(MIGRATIONS[0].sql)
+        .and_then(|_| tx.pragma_update(None, "user_version", MIGRATIONS[0].version))
+        .map_err(|e| format!("migration 1 failed: {}", e))?;
+
+    for (index, (path, tables)) in legacy.iter().enumerate() {
+        for table in tables.iter() {
+            let exists: bool = tx
+                .query_row(
+                    &format!(
+                        "SELECT COUNT(*) > 0 FROM legacy{}.sqlite_master
+                            WHERE type = 'table' AND name = ?1",
+                        index
+                    ),
+                    [table],
+                    |row| row.get(0),
+                )
+                .map_err(|e| e.to_string())?;
+
+            if exists {
+                tx.execute(
+                    &format!(
+                        "INSERT INTO main.{0} SELECT * FROM legacy{1}.{0}",
+                        table, index
+                    ),
+                    [],
+                )
+                .map_err(|e| format!("failed to import {}: {}", path.display(), e))?;
+            }
+        }
+    }
+
+    tx.commit().map_err(|e| e.to_string())?;
+
+    for index in 0..legacy.len() {
+        conn.execute(&format!("DETACH DATABASE legacy{}", index), [])
+            .map_err(|e| e.to_string())?;
+    }
+
+    let imported = legacy.into_iter().map(|(path, _)| path).collect();
+
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_a_fresh_database_to_the_latest_version() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        let applied = migrate_to(&mut conn, latest_version()).unwrap();
+
+        assert_eq!(applied.len(), MIGRATIONS.len());
+        assert_eq!(current_version(&conn).unwrap(), latest_version());
+        assert!(migrate_to(&mut conn, latest_version()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn imports_the_databases_of_old_servers() {
+        let dir = std::env::temp_dir().join(format!("nnntp-legacy-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let users = Connection::open(dir.join("users.db")).unwrap();
+        users
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS users (
+                    id INTEGER PRIMARY KEY,
+                    username TEXT NOT NULL,
+                    password TEXT NOT NULL
+                );
+                INSERT INTO users (username, password) VALUES ('ferris', 'hash');",
+            )
+            .unwrap();
+        let posts = Connection::open(dir.join("posts.db")).unwrap();
+        posts
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS posts (
+                    id INTEGER PRIMARY KEY,
+                    group_name TEXT NOT NULL,
+                    subject TEXT NOT NULL,
+                    body TEXT NOT NULL,
+                    author TEXT NOT NULL,
+                    author_email TEXT NOT NULL
+                );
+                INSERT INTO posts (group_name, subject, body, author, author_email)
+                    VALUES ('comp.lang.rust', 'hello', 'world', 'ferris', 'ferris@example.com');",
+            )
+            .unwrap();
+
+        let mut conn = Connection::open_in_memory().unwrap();
+        let imported = import_legacy(&mut conn, &dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(imported, [dir.join("users.db"), dir.join("posts.db")]);
+        migrate_to(&mut conn, latest_version()).unwrap();
+
+        let count = |sql: &str| -> i64 { conn.query_row(sql, [], |row| row.get(0)).unwrap() };
+        assert_eq!(
+            count("SELECT COUNT(*) FROM users WHERE username = 'ferris'"),
+            1
+        );
+        assert_eq!(
+            count("SELECT COUNT(*) FROM posts WHERE subject = 'hello'"),
+            1
+        );
+        assert_eq!(count("SELECT COUNT(*) FROM groups"), 1);
+
+        // a database that already exists is never imported into
+        assert!(import_legacy(&mut conn, &dir).unwrap().is_empty());
+    }
+
+    #[test]
+    fn upgrading_imports_old_databases_whatever_version_it_stops_at() {
+        let dir = std::env::temp_dir().join(format!("nnntp-upgrade-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Connection::open(dir.join("users.db"))
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE users (
+                    id INTEGER PRIMARY KEY,
+                    username TEXT NOT NULL,
+                    password TEXT NOT NULL
+                );
+                INSERT INTO users (username, password) VALUES ('ferris', 'hash');",
+            )
+            .unwrap();
+        let users = |conn: &Connection| -> i64 {
+            conn.query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0))
+                .unwrap()
+        };
+
+        // `nnntp migrate --to` leaves the database short of the latest version, which the next
+        // start must not take for a database that was there before
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert_eq!(upgrade(&mut conn, Some(&dir), 2).unwrap(), [2]);
+        assert_eq!(users(&conn), 1);
+        assert_eq!(
+            upgrade(&mut conn, Some(&dir), latest_version())
+                .unwrap()
+                .len(),
+            MIGRATIONS.len() - 2
+        );
+        assert_eq!(users(&conn), 1);
+
+        let mut elsewhere = Connection::open_in_memory().unwrap();
+        upgrade(&mut elsewhere, None, latest_version()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(users(&elsewhere), 0);
+    }
+
+    #[test]
+    fn adopts_a_database_created_before_migrations() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE users (
+                id INTEGER PRIMARY KEY,
+                username TEXT NOT NULL,
+                password TEXT NOT NULL
+            );
+            INSERT INTO users (username, password) VALUES ('ferris', 'hash');
+            CREATE TABLE posts (
+                id INTEGER PRIMARY KEY,
+                group_name TEXT NOT NULL,
+                subject TEXT NOT NULL,
+                body TEXT NOT NULL,
+                author TEXT NOT NULL,
+                author_email TEXT NOT NULL
+            );
+            INSERT INTO posts (group_name, subject, body, author, author_email)
+                VALUES ('comp.lang.rust', 'hello', 'world', 'ferris', 'ferris@example.com');
+            CREATE TABLE comments (
+                parent_id INTEGER NOT NULL,
+                body TEXT NOT NULL,
+                author TEXT NOT NULL,
+                author_email TEXT NOT NULL
+            );
+            INSERT INTO comments (parent_id, body, author, author_email)
+                VALUES (1, 'hi', 'ferris', 'ferris@example.com');",
+        )
+        .unwrap();
+
+        migrate_to(&mut conn, latest_version()).unwrap();
+
+        let (id, message_id): (i64, String) = conn
+            .query_row("SELECT id, message_id FROM comments", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(id, 1);
+        assert!(message_id.ends_with("@legacy.invalid>"));
+
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM posts", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+
+        let creator: String = conn
+            .query_row(
+                "SELECT creator FROM groups WHERE name = 'comp.lang.rust'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(creator, "ferris");
+
+        let indexed: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM search_index WHERE search_index MATCH 'hi OR world'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(indexed, 2);
+
+        let numbered: Vec<(i64, String)> = conn
+            .prepare("SELECT number, kind FROM article_numbers ORDER BY number")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            numbered,
+            [(1, "post".to_string()), (2, "comment".to_string())]
+        );
+
+        let fed: i64 = conn
+            .query_row("SELECT COUNT(*) FROM feed", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(fed, 2);
+
+        let email: String = conn
+            .query_row(
+                "SELECT email FROM users WHERE username = 'ferris'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(email, "ferris@example.com");
+    }
+
+    #[test]
+    fn keeps_only_the_emails_of_users_that_are_valid() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate_to(&mut conn, 14).unwrap();
+        conn.execute_batch(
+            "INSERT INTO users (username, password) VALUES ('ferris', 'hash'), ('corro', 'hash');
+            INSERT INTO posts (group_name, subject, body, author, author_email, created_at)
+                VALUES ('comp.lang.rust', 'hi', 'hello', 'ferris', 'ferris@example.com', 1),
+                    ('comp.lang.rust', 'hi', 'hello', 'ferris', 'x@y.com>\r\nApproved: yes', 2),
+                    ('comp.lang.rust', 'hi', 'hello', 'corro', 'corro@[example.com]', 3),
+                    ('comp.lang.rust', 'hi', 'hello', 'corro', 'corro\\@example.com', 4);",
+        )
+        .unwrap();
+
+        migrate_to(&mut conn, 15).unwrap();
+
+        let emails: Vec<(String, String)> = conn
+            .prepare("SELECT username, email FROM users ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            emails,
+            [
+                ("ferris".to_string(), "ferris@example.com".to_string()),
+                ("corro".to_string(), String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn makes_usernames_unique_whatever_their_case() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate_to(&mut conn, 15).unwrap();
+        conn.execute_batch(
+            "INSERT INTO users (id, username, password) VALUES
+                (1, 'ferris', 'first'), (2, 'ferris', 'raced'), (3, 'Ferris', 'other'),
+                (4, 'FERRIS-3', 'taken'), (5, 'FERRIS', 'third');
+            INSERT INTO groups (name, description, created_at, creator)
+                VALUES ('comp.lang.rust', '', 0, 'Ferris');
+            INSERT INTO posts (group_name, subject, body, author, author_email, cancelled_by)
+                VALUES ('comp.lang.rust', 'hi', 'hello', 'FERRIS', 'f@example.com', 'Ferris');
+            INSERT INTO roles (username, role, group_name)
+                VALUES ('Ferris', 'moderator', 'comp.lang.rust');",
+        )
+        .unwrap();
+
+        migrate_to(&mut conn, latest_version()).unwrap();
+
+        let users: Vec<(String, String)> = conn
+            .prepare("SELECT username, password FROM users ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            users,
+            [
+                ("ferris".to_string(), "first".to_string()),
+                ("Ferris-3-3".to_string(), "other".to_string()),
+                ("FERRIS-3".to_string(), "taken".to_string()),
+                ("FERRIS-5".to_string(), "third".to_string()),
+            ]
+        );
+        assert_eq!(
+            renamed_users(&conn).unwrap(),
+            [
+                ("Ferris".to_string(), "Ferris-3-3".to_string()),
+                ("FERRIS".to_string(), "FERRIS-5".to_string()),
+            ]
+        );
+
+        let moderator: String = conn
+            .query_row("SELECT username FROM roles", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(moderator, "Ferris-3-3");
+
+        let (author, cancelled_by): (String, String) = conn
+            .query_row("SELECT author, cancelled_by FROM posts", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(author, "FERRIS-5");
+        assert_eq!(cancelled_by, "Ferris-3-3");
+
+        assert!(conn
+            .execute(
+                "INSERT INTO users (username, password) VALUES ('FERRIS', 'again')",
+                []
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn refuses_unknown_and_older_versions() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        assert!(migrate_to(&mut conn, latest_version() + 1).is_err());
+
+        migrate_to(&mut conn, latest_version()).unwrap();
+        conn.pragma_update(None, "user_version", latest_version() + 1)
+            .unwrap();
+
+        assert!(migrate_to(&mut conn, latest_version()).is_err());
+    }
+}
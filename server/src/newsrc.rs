@@ -0,0 +1,109 @@
+//! what each user has read, kept on the server like the `.newsrc` file of a Usenet newsreader:
+//! for every group, whether the user subscribed to it and the article numbers they have read, as
+//! ranges like `1-5,7,10-12`.
+
+use std::fmt;
+
+/// a set of article numbers, as sorted ranges that neither overlap nor touch
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Ranges {
+    ranges: Vec<(i64, i64)>,
+}
+
+impl Ranges {
+    /// reads ranges like `1-5,7`, skipping anything that is not a number or a range of them
+    pub fn parse(ranges: &str) -> Ranges {
+        let mut parsed = Ranges::default();
+
+        for range in ranges.split(',').map(str::trim) {
+            let bounds = match range.split_once('-') {
+                Some((first, last)) => first.parse().ok().zip(last.parse().ok()),
+                None => range.parse().ok().map(|number| (number, number)),
+            };
+
+            if let Some((first, last)) = bounds {
+                parsed.insert(first, last);
+            }
+        }
+
+        parsed
+    }
+
+    /// adds the numbers `first` to `last`, both included
+    pub fn insert(&mut self, first: i64, last: i64) {
+        if first > last {
+            return;
+        }
+
+        let (mut first, mut last) = (first, last);
+
+        // every range that overlaps or touches the new one is merged into it
+        self.ranges.retain(|&(start, end)| {
+            if end + 1 < first || last + 1 < start {
+                return true;
+            }
+
+            first = first.min(start);
+            last = last.max(end);
+            false
+        });
+
+        let at = self
+            .ranges
+            .iter()
+            .position(|&(start, _)| start > first)
+            .unwrap_or(self.ranges.len());
+        self.ranges.insert(at, (first, last));
+    }
+
+    /// the ranges, lowest first, each as its first and last number
+    pub fn iter(&self) -> impl Iterator<Item = (i64, i64)> + '_ {
+        self.ranges.iter().copied()
+    }
+
+    pub fn contains(&self, number: i64) -> bool {
+        self.ranges
+            .iter()
+            .any(|&(first, last)| (first..=last).contains(&number))
+    }
+}
+
+impl fmt::Display for Ranges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ranges: Vec<String> = self
+            .ranges
+            .iter()
+            .map(|&(first, last)| {
+                if first == last {
+                    first.to_string()
+                } else {
+                    format!("{}-{}", first, last)
+                }
+            })
+            .collect();
+
+        write!(f, "{}", ranges.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_ranges_that_touch() {
+        let mut read = Ranges::parse("1-3,7,junk,9-8");
+        assert_eq!(read.to_string(), "1-3,7");
+
+        read.insert(5, 5);
+        assert_eq!(read.to_string(), "1-3,5,7");
+        read.insert(4, 4);
+        assert_eq!(read.to_string(), "1-5,7");
+        read.insert(6, 10);
+        assert_eq!(read.to_string(), "1-10");
+
+        assert!(read.contains(1) && read.contains(10));
+        assert!(!read.contains(0) && !read.contains(11));
+        assert_eq!(Ranges::parse("").to_string(), "");
+    }
+}
@@ -0,0 +1,1077 @@
+//! a gateway speaking a subset of NNTP (RFC 3977), so newsreaders like Thunderbird, slrn and tin
+//! can read and post next to jsontp clients. it serves the same storage as the routes, and knows
+//! CAPABILITIES, MODE READER, LIST (ACTIVE, NEWSGROUPS and OVERVIEW.FMT), GROUP, ARTICLE, HEAD,
+//! BODY, OVER, POST, AUTHINFO USER/PASS and QUIT.
+//!
+//! posts and comments are both articles to NNTP, numbered in their group as they are listed. a
+//! comment is an article whose References lead back to its post.
+
+use std::fmt::Display;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use protocol::{Author, Credentials};
+
+use crate::error::NnntpError;
+use crate::limits::{limiter, Client, Limiter};
+use crate::routes;
+use crate::session;
+use crate::settings::{settings, Settings};
+use crate::storage::{storage, ArticleId, Storage, StorageError};
+use crate::wildmat::Wildmat;
+
+/// the longest command line taken, with its CRLF, as RFC 3977 allows
+const MAX_COMMAND_LINE: usize = 512;
+
+/// the largest article POST takes, in bytes on the wire
+const MAX_ARTICLE_BYTES: usize = 1024 * 1024;
+
+/// how long a connection may sit idle before it is closed. RFC 3977 asks for at least 3 minutes
+const READ_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// the overview fields OVER returns after the article number, as LIST OVERVIEW.FMT names them
+const OVERVIEW_FORMAT: [&str; 7] = [
+    "Subject:",
+    "From:",
+    "Date:",
+    "Message-ID:",
+    "References:",
+    ":bytes",
+    ":lines",
+];
+
+/// starts accepting NNTP connections on `host`:`port` in the background, each served on a
+/// thread of its own
+pub fn listen(host: &str, port: u16) -> Result<(), String> {
+    let listener = TcpListener::bind((host, port)).map_err(|e| e.to_string())?;
+
+    thread::spawn(move || accept(listener, storage(), settings, limiter()));
+
+    Ok(())
+}
+
+/// serves every connection with the `settings` of the moment it was accepted, which it keeps
+/// until it is closed
+fn accept(
+    listener: TcpListener,
+    storage: &'static dyn Storage,
+    settings: impl Fn() -> Arc<Settings>,
+    limiter: &'static Limiter,
+) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let settings = settings();
+
+                thread::spawn(move || {
+                    if let Err(e) = serve(stream, storage, &settings, limiter) {
+                        eprintln!("nntp connection failed: {}", e);
+                    }
+                });
+            }
+            Err(e) => eprintln!("failed to accept an nntp connection: {}", e),
+        }
+    }
+}
+
+/// a status line, and the lines of a multi-line response if there are any
+struct Response {
+    status: String,
+    lines: Option<Vec<String>>,
+}
+
+fn reply<T: Display>(code: u16, text: T) -> Response {
+    Response {
+        status: format!("{} {}", code, text),
+        lines: None,
+    }
+}
+
+fn multi_line<T: Display>(code: u16, text: T, lines: Vec<String>) -> Response {
+    Response {
+        status: format!("{} {}", code, text),
+        lines: Some(lines),
+    }
+}
+
+/// why a command did not succeed: an NNTP error to send back, or a failure on the server's side
+enum Failure {
+    Reply(Response),
+    Error(NnntpError),
+}
+
+impl From<NnntpError> for Failure {
+    fn from(e: NnntpError) -> Failure {
+        Failure::Error(e)
+    }
+}
+
+impl From<StorageError> for Failure {
+    fn from(e: StorageError) -> Failure {
+        Failure::Error(e.into())
+    }
+}
+
+fn fail<T: Display>(code: u16, text: T) -> Failure {
+    Failure::Reply(reply(code, text))
+}
+
+fn serve(
+    stream: TcpStream,
+    storage: &dyn Storage,
+    settings: &Settings,
+    limiter: &Limiter,
+) -> io::Result<()> {
+    let ip = stream.peer_addr()?.ip().to_string();
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    let mut session = Session {
+        storage,
+        settings,
+        limiter,
+        ip,
+        group: None,
+        current: None,
+        username: None,
+        token: None,
+    };
+
+    let greeting = format!("{} nnntp ready, posting allowed", settings.domain);
+    send(&mut writer, &reply(200, greeting))?;
+
+    loop {
+        let line = match read_line(&mut reader, MAX_COMMAND_LINE) {
+            Ok(Some(line)) => line,
+            Ok(None) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                send(&mut writer, &reply(501, e))?;
+                continue;
+            }
+            Err(e) if timed_out(&e) => {
+                return send(&mut writer, &reply(400, "idle for too long, closing"));
+            }
+            Err(e) => return Err(e),
+        };
+
+        let (command, rest) = match line.trim().split_once(' ') {
+            Some((command, rest)) => (command.to_ascii_uppercase(), rest.trim()),
+            None => (line.trim().to_ascii_uppercase(), ""),
+        };
+
+        let response = match command.as_str() {
+            "QUIT" => {
+                send(&mut writer, &reply(205, "bye"))?;
+                return Ok(());
+            }
+            "POST" if session.token.is_none() => reply(480, "authentication required"),
+            "POST" => {
+                send(
+                    &mut writer,
+                    &reply(340, "send the article, ending with a lone ."),
+                )?;
+                let article = match read_block(&mut reader, MAX_ARTICLE_BYTES) {
+                    Ok(article) => article,
+                    // the rest of the article is still on its way, so there is no telling
+                    // where the next command starts
+                    Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                        return send(&mut writer, &reply(441, e));
+                    }
+                    Err(e) => return Err(e),
+                };
+                session.respond(|session| session.post(article))
+            }
+            _ => session.respond(|session| session.command(&command, rest)),
+        };
+
+        send(&mut writer, &response)?;
+    }
+}
+
+fn timed_out(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// the next line from the client without its line ending, or none once it hangs up. a line of
+/// more than `max` bytes is skipped, failing with [`io::ErrorKind::InvalidData`]
+fn read_line(reader: &mut impl BufRead, max: usize) -> io::Result<Option<String>> {
+    let mut line = vec![];
+
+    if reader
+        .by_ref()
+        .take(max as u64)
+        .read_until(b'\n', &mut line)?
+        == 0
+    {
+        return Ok(None);
+    }
+
+    if !line.ends_with(b"\n") && line.len() == max {
+        skip_line(reader)?;
+
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("lines can be at most {} bytes long", max),
+        ));
+    }
+
+    let line = String::from_utf8_lossy(&line);
+
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+/// throws away what is left of a line, without keeping any of it
+fn skip_line(reader: &mut impl BufRead) -> io::Result<()> {
+    loop {
+        let buffer = reader.fill_buf()?;
+
+        if buffer.is_empty() {
+            return Ok(());
+        }
+
+        match buffer.iter().position(|&byte| byte == b'\n') {
+            Some(end) => {
+                reader.consume(end + 1);
+                return Ok(());
+            }
+            None => {
+                let length = buffer.len();
+                reader.consume(length);
+            }
+        }
+    }
+}
+
+/// the lines of a multi-line block up to the lone `.` ending it, undoing the dot stuffing. a
+/// block of more than `max` bytes fails with [`io::ErrorKind::InvalidData`]
+fn read_block(reader: &mut impl BufRead, max: usize) -> io::Result<Vec<String>> {
+    let too_large = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("articles can be at most {} bytes long", max),
+        )
+    };
+    let mut lines = vec![];
+    let mut left = max;
+
+    loop {
+        if left == 0 {
+            return Err(too_large());
+        }
+
+        let line = match read_line(reader, left) {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => return Err(too_large()),
+            Err(e) => return Err(e),
+        };
+        // the line ending counts too, whichever the client used
+        left = left.saturating_sub(line.len() + 2);
+
+        if line == "." {
+            return Ok(lines);
+        }
+
+        lines.push(match line.strip_prefix('.') {
+            Some(unstuffed) => unstuffed.to_string(),
+            None => line,
+        });
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "the client hung up in the middle of a block",
+    ))
+}
+
+fn send(writer: &mut impl Write, response: &Response) -> io::Result<()> {
+    let mut out = format!("{}\r\n", response.status);
+
+    if let Some(lines) = &response.lines {
+        for line in lines {
+            // lines starting with a dot get another one, so they are not taken for the end
+            if line.starts_with('.') {
+                out.push('.');
+            }
+            out.push_str(line);
+            out.push_str("\r\n");
+        }
+        out.push_str(".\r\n");
+    }
+
+    writer.write_all(out.as_bytes())?;
+    writer.flush()
+}
+
+/// an article as NNTP shows it
+struct Article {
+    message_id: String,
+    headers: Vec<(&'static str, String)>,
+    body: String,
+}
+
+impl Article {
+    fn header(&self, name: &str) -> &str {
+        self.headers
+            .iter()
+            .find(|(header, _)| *header == name)
+            .map_or("", |(_, value)| value.as_str())
+    }
+
+    fn header_lines(&self) -> Vec<String> {
+        // articles from before subjects were checked could still have line breaks, which would
+        // start headers of their own
+        self.headers
+            .iter()
+            .map(|(name, value)| format!("{}: {}", name, value.replace(['\r', '\n'], " ")))
+            .collect()
+    }
+
+    fn body_lines(&self) -> Vec<String> {
+        self.body.lines().map(str::to_string).collect()
+    }
+
+    /// the line OVER shows for the article
+    fn overview(&self, number: i64) -> String {
+        let lines = self.body_lines().len();
+        // the size of the article on the wire, with its CRLF line endings
+        let bytes: usize = self
+            .header_lines()
+            .iter()
+            .chain(self.body_lines().iter())
+            .map(|line| line.len() + 2)
+            .sum::<usize>()
+            + 2;
+
+        let mut fields = vec![number.to_string()];
+        for name in ["Subject", "From", "Date", "Message-ID", "References"] {
+            // tabs separate the fields, so they cannot appear inside them
+            fields.push(self.header(name).replace(['\t', '\r', '\n'], " "));
+        }
+        fields.push(bytes.to_string());
+        fields.push(lines.to_string());
+
+        fields.join("\t")
+    }
+}
+
+/// the state of one connection
+struct Session<'a> {
+    storage: &'a dyn Storage,
+    settings: &'a Settings,
+    limiter: &'a Limiter,
+    /// the address of the newsreader, which unlike over jsontp is known
+    ip: String,
+    /// the group picked with GROUP
+    group: Option<String>,
+    /// the current article number in that group
+    current: Option<i64>,
+    /// the name given with AUTHINFO USER, waiting for its password
+    username: Option<String>,
+    /// the session of the user once AUTHINFO PASS succeeded
+    token: Option<String>,
+}
+
+impl Session<'_> {
+    /// runs a command, turning failures on the server's side into a 403
+    fn respond<F>(&mut self, command: F) -> Response
+    where
+        F: FnOnce(&mut Self) -> Result<Response, Failure>,
+    {
+        match command(self) {
+            Ok(response) => response,
+            Err(Failure::Reply(response)) => response,
+            Err(Failure::Error(e)) => {
+                eprintln!("nntp command failed: {}", e);
+                reply(403, "internal fault")
+            }
+        }
+    }
+
+    fn command(&mut self, command: &str, rest: &str) -> Result<Response, Failure> {
+        let args: Vec<&str> = rest.split_whitespace().collect();
+
+        match command {
+            "CAPABILITIES" => Ok(self.capabilities()),
+            "MODE" if rest.eq_ignore_ascii_case("READER") => Ok(reply(200, "posting allowed")),
+            "LIST" => self.list(&args),
+            "GROUP" => self.select_group(&args),
+            "ARTICLE" | "HEAD" | "BODY" => self.article(command, &args),
+            "OVER" | "XOVER" => self.over(&args),
+            "AUTHINFO" => self.authinfo(rest),
+            "MODE" => Err(fail(501, "only MODE READER is supported")),
+            _ => Err(fail(500, "unknown command")),
+        }
+    }
+
+    fn capabilities(&self) -> Response {
+        let mut capabilities = vec![
+            "VERSION 2".to_string(),
+            "READER".to_string(),
+            "POST".to_string(),
+            "LIST ACTIVE NEWSGROUPS OVERVIEW.FMT".to_string(),
+            "OVER".to_string(),
+        ];
+        // RFC 4643 has AUTHINFO vanish from the list once the user is authenticated
+        if self.token.is_none() {
+            capabilities.push("AUTHINFO USER".to_string());
+        }
+        capabilities.push("IMPLEMENTATION nnntp".to_string());
+
+        multi_line(101, "capability list follows", capabilities)
+    }
+
+    /// the number of articles in `group`, and its lowest and highest article numbers. an empty
+    /// group has a high water mark below its low one, as RFC 3977 asks
+    fn water_marks(&self, group: &str) -> Result<(i64, i64, i64), NnntpError> {
+        Ok(match self.storage.count_articles(group, 1, i64::MAX)? {
+            (count, Some((low, high))) => (count, low, high),
+            (_, None) => (0, 1, 0),
+        })
+    }
+
+    fn list(&self, args: &[&str]) -> Result<Response, Failure> {
+        let keyword = args.first().map(|keyword| keyword.to_ascii_uppercase());
+        let wildmat = match args.get(1) {
+            Some(pattern) => match Wildmat::parse(pattern) {
+                Ok(wildmat) => Some(wildmat),
+                Err(e) => return Err(fail(501, e)),
+            },
+            None => None,
+        };
+
+        let groups: Vec<_> = self
+            .storage
+            .groups()?
+            .into_iter()
+            .filter(|group| wildmat.as_ref().is_none_or(|w| w.matches(&group.name)))
+            .collect();
+
+        let lines = match keyword.as_deref() {
+            None | Some("ACTIVE") => {
+                let mut lines = vec![];
+                for group in groups {
+                    let (_, low, high) = self.water_marks(&group.name)?;
+                    let status = if group.moderated { "m" } else { "y" };
+                    lines.push(format!("{} {} {} {}", group.name, high, low, status));
+                }
+                lines
+            }
+            Some("NEWSGROUPS") => groups
+                .into_iter()
+                .map(|group| format!("{}\t{}", group.name, group.description))
+                .collect(),
+            Some("OVERVIEW.FMT") => OVERVIEW_FORMAT.iter().map(|f| f.to_string()).collect(),
+            Some(_) => return Err(fail(501, "unknown list keyword")),
+        };
+
+        Ok(multi_line(215, "information follows", lines))
+    }
+
+    fn select_group(&mut self, args: &[&str]) -> Result<Response, Failure> {
+        let name = match args {
+            [name] => *name,
+            _ => return Err(fail(501, "GROUP takes a group name")),
+        };
+
+        if self.storage.group(name)?.is_none() {
+            return Err(fail(411, "no such newsgroup"));
+        }
+
+        let (count, low, high) = self.water_marks(name)?;
+        self.group = Some(name.to_string());
+        self.current = Some(low).filter(|_| count > 0);
+
+        Ok(reply(211, format!("{} {} {} {}", count, low, high, name)))
+    }
+
+    fn current_group(&self) -> Result<&str, Failure> {
+        match &self.group {
+            Some(group) => Ok(group),
+            None => Err(fail(412, "no newsgroup selected")),
+        }
+    }
+
+    /// the article numbered `number` in the current group
+    fn numbered(&self, number: i64) -> Result<Option<Article>, Failure> {
+        let group = self.current_group()?;
+
+        match self.storage.article_numbers(group, number, number)?.first() {
+            Some((_, article)) => Ok(self.load(*article)?),
+            None => Ok(None),
+        }
+    }
+
+    /// the article a command names: by message id, by number, or the current one when it names
+    /// none. returns its number too, 0 for articles named by message id
+    fn find(&mut self, args: &[&str]) -> Result<(i64, Article), Failure> {
+        match args {
+            [] => {
+                self.current_group()?;
+                let number = match self.current {
+                    Some(number) => number,
+                    None => return Err(fail(420, "current article number is invalid")),
+                };
+
+                match self.numbered(number)? {
+                    Some(article) => Ok((number, article)),
+                    None => Err(fail(423, "no article with that number")),
+                }
+            }
+            [message_id] if message_id.starts_with('<') => {
+                let article = match self.storage.article_by_message_id(message_id)? {
+                    Some(article) => self.load(article)?,
+                    None => None,
+                };
+
+                match article {
+                    Some(article) => Ok((0, article)),
+                    None => Err(fail(430, "no article with that message-id")),
+                }
+            }
+            [number] => {
+                let number: i64 = match number.parse() {
+                    Ok(number) => number,
+                    Err(_) => return Err(fail(501, "not an article number")),
+                };
+
+                match self.numbered(number)? {
+                    Some(article) => {
+                        self.current = Some(number);
+                        Ok((number, article))
+                    }
+                    None => Err(fail(423, "no article with that number")),
+                }
+            }
+            _ => Err(fail(501, "too many arguments")),
+        }
+    }
+
+    fn article(&mut self, command: &str, args: &[&str]) -> Result<Response, Failure> {
+        let (number, article) = self.find(args)?;
+        let named = format!("{} {}", number, article.message_id);
+
+        Ok(match command {
+            "HEAD" => multi_line(221, named, article.header_lines()),
+            "BODY" => multi_line(222, named, article.body_lines()),
+            _ => {
+                let mut lines = article.header_lines();
+                lines.push(String::new());
+                lines.extend(article.body_lines());
+                multi_line(220, named, lines)
+            }
+        })
+    }
+
+    fn over(&mut self, args: &[&str]) -> Result<Response, Failure> {
+        let range = match args {
+            [range] if !range.starts_with('<') => *range,
+            _ => {
+                let (number, article) = self.find(args)?;
+                return Ok(multi_line(
+                    224,
+                    "overview information follows",
+                    vec![article.overview(number)],
+                ));
+            }
+        };
+
+        let parse = |number: &str| number.parse::<i64>().map_err(|_| fail(501, "bad range"));
+        let (from, to) = match range.split_once('-') {
+            Some((from, "")) => (parse(from)?, i64::MAX),
+            Some((from, to)) => (parse(from)?, parse(to)?),
+            None => (parse(range)?, parse(range)?),
+        };
+
+        let group = self.current_group()?.to_string();
+        let mut lines = vec![];
+        for (number, article) in self.storage.article_numbers(&group, from, to)? {
+            if let Some(article) = self.load(article)? {
+                lines.push(article.overview(number));
+            }
+        }
+
+        if lines.is_empty() {
+            return Err(fail(423, "no articles in that range"));
+        }
+
+        Ok(multi_line(224, "overview information follows", lines))
+    }
+
+    fn authinfo(&mut self, rest: &str) -> Result<Response, Failure> {
+        if self.token.is_some() {
+            return Err(fail(502, "already authenticated"));
+        }
+
+        let (keyword, value) = match rest.split_once(' ') {
+            Some((keyword, value)) => (keyword.to_ascii_uppercase(), value.trim_start()),
+            None => return Err(fail(501, "AUTHINFO takes USER or PASS and a value")),
+        };
+
+        match keyword.as_str() {
+            "USER" => {
+                self.username = Some(value.to_string());
+                Ok(reply(381, "password required"))
+            }
+            "PASS" => {
+                let username = match self.username.take() {
+                    Some(username) => username,
+                    None => return Err(fail(482, "send AUTHINFO USER first")),
+                };
+
+                let client = Client {
+                    ip: Some(&self.ip),
+                    username: Some(&username),
+                    password: true,
+                    signup: false,
+                };
+                if let Err(NnntpError::TooManyRequests(seconds)) =
+                    self.limiter.admit(&client, Instant::now())
+                {
+                    return Err(fail(
+                        481,
+                        format!("too many attempts, try again in {} seconds", seconds),
+                    ));
+                }
+
+                let verified = match routes::verify_user(self.storage, &username, value) {
+                    // the password was right, but the account still waits for an admin
+                    Err(NnntpError::Forbidden(message)) => {
+                        self.limiter.record(&client, true, Instant::now());
+                        return Err(fail(481, message));
+                    }
+                    verified => verified?,
+                };
+                self.limiter.record(&client, verified, Instant::now());
+
+                if !verified {
+                    return Err(fail(481, "authentication failed"));
+                }
+
+                let username = routes::registered_username(self.storage, &username)?;
+                let (token, _) =
+                    session::start(self.storage, &username, self.settings.session_ttl)?;
+                self.token = Some(token);
+
+                Ok(reply(281, "authentication accepted"))
+            }
+            _ => Err(fail(501, "AUTHINFO takes USER or PASS and a value")),
+        }
+    }
+
+    /// posts an article a newsreader sent. one with References is a comment on the thread the
+    /// last of them belongs to
+    fn post(&mut self, lines: Vec<String>) -> Result<Response, Failure> {
+        let (headers, body) = parse_article(&lines);
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(header, _)| header.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.trim())
+        };
+
+        let groups: Vec<&str> = header("Newsgroups")
+            .unwrap_or("")
+            .split(',')
+            .map(str::trim)
+            .filter(|group| !group.is_empty())
+            .collect();
+        let group = match groups.as_slice() {
+            [group] => *group,
+            [] => return Err(fail(441, "the article has no Newsgroups header")),
+            _ => return Err(fail(441, "posting to more than one group is not supported")),
+        };
+
+        let author = Author {
+            credentials: Credentials::Token {
+                token: self.token.clone().unwrap_or_default(),
+            },
+            // the From header is not trusted, articles carry the email of the account
+            email: String::new(),
+        };
+
+        let replied_to = header("References").and_then(|refs| refs.split_whitespace().last());
+
+        let created = match replied_to {
+            None => {
+                let subject = header("Subject").unwrap_or("");
+                routes::post_to_group(self.storage, self.settings, group, subject, &body, &author)
+                    .map(|created| (created.message_id, created.pending))
+            }
+            Some(message_id) => {
+                let (parent, reply_to) = match self.storage.article_by_message_id(message_id)? {
+                    Some(ArticleId::Post(id)) => (id, None),
+                    Some(ArticleId::Comment(id)) => match self.storage.comment(id)? {
+                        Some(comment) => (comment.parent_id, Some(id)),
+                        None => return Err(fail(441, "the article replied to does not exist")),
+                    },
+                    None => return Err(fail(441, "the article replied to does not exist")),
+                };
+
+                routes::comment_on(
+                    self.storage,
+                    self.settings,
+                    group,
+                    parent,
+                    reply_to,
+                    &body,
+                    &author,
+                )
+                .map(|(_, message_id)| (message_id, false))
+            }
+        };
+
+        match created {
+            Ok((message_id, false)) => Ok(reply(240, format!("{} article received", message_id))),
+            Ok((message_id, true)) => Ok(reply(
+                240,
+                format!("{} article received, waiting for a moderator", message_id),
+            )),
+            Err(e @ (NnntpError::Database(_) | NnntpError::Internal(_))) => Err(e.into()),
+            Err(e) => Err(fail(441, e.message())),
+        }
+    }
+
+    /// an article by id, unless it is not listed or has been cancelled
+    fn load(&self, article: ArticleId) -> Result<Option<Article>, NnntpError> {
+        let domain = &self.settings.domain;
+
+        match article {
+            ArticleId::Post(id) => {
+                let post = match self.storage.post(id)? {
+                    Some(post) if post.is_listed() && post.cancelled_at.is_none() => post,
+                    _ => return Ok(None),
+                };
+
+                Ok(Some(Article {
+                    headers: vec![
+                        ("Path", format!("{}!{}", domain, post.path)),
+                        ("From", from(&post.author, &post.author_email)),
+                        ("Newsgroups", post.group_name),
+                        ("Subject", post.subject),
+                        ("Date", format_date(post.created_at)),
+                        ("Message-ID", post.message_id.clone()),
+                    ],
+                    message_id: post.message_id,
+                    body: post.body,
+                }))
+            }
+            ArticleId::Comment(id) => {
+                let comment = match self.storage.comment(id)? {
+                    Some(comment) if comment.cancelled_at.is_none() => comment,
+                    _ => return Ok(None),
+                };
+                let post = match self.storage.post(comment.parent_id)? {
+                    Some(post) if post.is_listed() => post,
+                    _ => return Ok(None),
+                };
+                // like on `/list`, a cancelled post keeps its comments but not its subject
+                let subject = if post.cancelled_at.is_some() {
+                    "Re:".to_string()
+                } else {
+                    format!("Re: {}", post.subject)
+                };
+
+                Ok(Some(Article {
+                    headers: vec![
+                        ("Path", format!("{}!{}", domain, comment.path)),
+                        ("From", from(&comment.author, &comment.author_email)),
+                        ("Newsgroups", post.group_name),
+                        ("Subject", subject),
+                        ("Date", format_date(comment.created_at)),
+                        ("Message-ID", comment.message_id.clone()),
+                        ("References", comment.references.join(" ")),
+                    ],
+                    message_id: comment.message_id,
+                    body: comment.body,
+                }))
+            }
+        }
+    }
+}
+
+/// splits an article into its headers, unfolding any continuation lines, and its body
+fn parse_article(lines: &[String]) -> (Vec<(String, String)>, String) {
+    let mut headers: Vec<(String, String)> = vec![];
+    let mut rest = lines.iter();
+
+    for line in rest.by_ref() {
+        if line.is_empty() {
+            break;
+        }
+
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    let body: Vec<&str> = rest.map(String::as_str).collect();
+
+    (headers, body.join("\n"))
+}
+
+/// the From header of an article
+fn from(author: &str, email: &str) -> String {
+    if email.is_empty() {
+        author.to_string()
+    } else {
+        format!("{} <{}>", author, email)
+    }
+}
+
+/// a unix timestamp as an RFC 5322 date, like `Tue, 14 Nov 2023 22:13:20 +0000`
+fn format_date(timestamp: i64) -> String {
+    const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let days = timestamp.div_euclid(86400);
+    let seconds = timestamp.rem_euclid(86400);
+
+    // the civil date of a day count, from Howard Hinnant's date algorithms
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{}, {} {} {} {:02}:{:02}:{:02} +0000",
+        // the epoch was a thursday
+        DAYS[(days + 4).rem_euclid(7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bcrypt::hash;
+
+    use crate::limits::Limits;
+    use crate::policy::Policy;
+    use crate::settings::{Registration, TEST_BCRYPT_COST};
+    use crate::storage::{MemoryStorage, NewPost, NewUser, LOCAL_PATH};
+
+    #[test]
+    fn formats_dates_like_rfc_5322() {
+        assert_eq!(format_date(0), "Thu, 1 Jan 1970 00:00:00 +0000");
+        assert_eq!(format_date(1700000000), "Tue, 14 Nov 2023 22:13:20 +0000");
+        assert_eq!(format_date(951782400), "Tue, 29 Feb 2000 00:00:00 +0000");
+    }
+
+    /// a newsreader, as far as a test needs one
+    struct Client {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+    }
+
+    impl Client {
+        fn status(&mut self) -> String {
+            read_line(&mut self.reader, usize::MAX).unwrap().unwrap()
+        }
+
+        fn send(&mut self, line: &str) -> String {
+            write!(self.writer, "{}\r\n", line).unwrap();
+            self.status()
+        }
+
+        fn block(&mut self) -> Vec<String> {
+            read_block(&mut self.reader, usize::MAX).unwrap()
+        }
+    }
+
+    fn start_server() -> (Client, &'static MemoryStorage) {
+        let storage = MemoryStorage::new();
+        storage
+            .insert_user(&NewUser {
+                username: "ferris",
+                hashed_password: &hash("crab", TEST_BCRYPT_COST).unwrap(),
+                ..NewUser::default()
+            })
+            .unwrap();
+        storage
+            .insert_group("comp.lang.rust", "rust talk", 0, "ferris")
+            .unwrap();
+        storage
+            .insert_post(&NewPost {
+                group: "comp.lang.rust",
+                subject: "hi",
+                body: "there\n.with a leading dot",
+                author: "ferris",
+                author_email: "ferris@example.com",
+                created_at: 1700000000,
+                message_id: "<1@news.example.com>",
+                path: LOCAL_PATH,
+                pending: false,
+            })
+            .unwrap();
+
+        // the server threads outlive the test, so what they serve has to as well
+        let storage: &'static MemoryStorage = Box::leak(Box::new(storage));
+        let settings = Arc::new(Settings {
+            session_ttl: 60,
+            bcrypt_cost: TEST_BCRYPT_COST,
+            auto_create_groups: false,
+            domain: "news.example.com".to_string(),
+            peers: vec![],
+            policy: Policy::default(),
+            registration: Registration::Open,
+            log_requests: false,
+            trusted_proxies: 0,
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let limiter: &'static Limiter = Box::leak(Box::new(Limiter::new(Limits::default())));
+        thread::spawn(move || accept(listener, storage, move || settings.clone(), limiter));
+
+        let stream = TcpStream::connect(address).unwrap();
+        let mut client = Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+        };
+        assert!(client.status().starts_with("200 "));
+
+        (client, storage)
+    }
+
+    #[test]
+    fn lines_and_articles_are_limited_in_size() {
+        let long = format!("{}\r\nQUIT\r\n", "A".repeat(600));
+        let mut reader = io::Cursor::new(long.into_bytes());
+
+        let e = read_line(&mut reader, MAX_COMMAND_LINE).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        // the rest of the long line was skipped, so the next command reads as usual
+        assert_eq!(
+            read_line(&mut reader, MAX_COMMAND_LINE).unwrap().unwrap(),
+            "QUIT"
+        );
+
+        let article = "Subject: hi\r\n\r\n..dotted\r\n.\r\n";
+        let mut reader = io::Cursor::new(article.as_bytes());
+        assert_eq!(
+            read_block(&mut reader, 64).unwrap(),
+            ["Subject: hi", "", ".dotted"]
+        );
+
+        let mut reader = io::Cursor::new(article.as_bytes());
+        let e = read_block(&mut reader, 16).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn newsreaders_can_read_and_post() {
+        let (mut client, storage) = start_server();
+
+        assert!(client.send("CAPABILITIES").starts_with("101 "));
+        let capabilities = client.block();
+        assert!(capabilities.contains(&"OVER".to_string()));
+        assert!(capabilities.contains(&"AUTHINFO USER".to_string()));
+        assert!(client.send("mode reader").starts_with("200 "));
+
+        assert!(client.send("LIST").starts_with("215 "));
+        assert_eq!(client.block(), ["comp.lang.rust 1 1 y"]);
+        assert!(client.send("LIST NEWSGROUPS comp.*").starts_with("215 "));
+        assert_eq!(client.block(), ["comp.lang.rust\trust talk"]);
+
+        assert!(client.send("ARTICLE 1").starts_with("412 "));
+        assert!(client.send("GROUP alt.nothing").starts_with("411 "));
+        assert_eq!(
+            client.send("GROUP comp.lang.rust"),
+            "211 1 1 1 comp.lang.rust"
+        );
+
+        assert_eq!(client.send("ARTICLE"), "220 1 <1@news.example.com>");
+        let article = client.block();
+        assert!(article.contains(&"Subject: hi".to_string()));
+        assert!(article.contains(&"Path: news.example.com!not-for-mail".to_string()));
+        assert!(article.contains(&"From: ferris <ferris@example.com>".to_string()));
+        assert!(article.contains(&"Date: Tue, 14 Nov 2023 22:13:20 +0000".to_string()));
+        assert!(client.send("HEAD 1").starts_with("221 "));
+        assert!(!client.block().contains(&"there".to_string()));
+        assert!(client
+            .send("BODY <1@news.example.com>")
+            .starts_with("222 0 "));
+        assert_eq!(client.block(), ["there", ".with a leading dot"]);
+        assert!(client.send("BODY 2").starts_with("423 "));
+        assert!(client
+            .send("ARTICLE <404@news.example.com>")
+            .starts_with("430 "));
+
+        assert!(client.send("POST").starts_with("480 "));
+        assert!(client.send("AUTHINFO PASS crab").starts_with("482 "));
+        assert!(client.send("AUTHINFO USER ferris").starts_with("381 "));
+        assert!(client.send("AUTHINFO PASS shrimp").starts_with("481 "));
+        assert!(client.send("AUTHINFO USER ferris").starts_with("381 "));
+        assert!(client.send("AUTHINFO PASS crab").starts_with("281 "));
+
+        assert!(client.send("POST").starts_with("340 "));
+        let reply = client.send(
+            "From: Ferris <ferris@example.com>\r\n\
+             Newsgroups: comp.lang.rust\r\n\
+             Subject: Re: hi\r\n\
+             References: <1@news.example.com>\r\n\
+             \r\n\
+             ..dots get stuffed\r\n\
+             .",
+        );
+        assert!(reply.starts_with("240 "), "{}", reply);
+
+        assert!(client.send("POST").starts_with("340 "));
+        let reply = client.send("Newsgroups: alt.nothing\r\nSubject: lost\r\n\r\nhello\r\n.");
+        assert!(reply.starts_with("441 "), "{}", reply);
+
+        assert!(client.send("OVER 1-").starts_with("224 "));
+        let overview = client.block();
+        assert_eq!(overview.len(), 2);
+        let fields: Vec<&str> = overview[1].split('\t').collect();
+        assert_eq!(fields[0], "2");
+        assert_eq!(fields[1], "Re: hi");
+        assert_eq!(fields[5], "<1@news.example.com>");
+        assert!(client.send("BODY 2").starts_with("222 2 "));
+        assert_eq!(client.block(), [".dots get stuffed"]);
+
+        // the comment outlives the post it answers, but not the post's subject
+        storage
+            .cancel_article(ArticleId::Post(1), "ferris", 1700000001)
+            .unwrap();
+        assert!(client.send("HEAD 1").starts_with("423 "));
+        assert!(client.send("HEAD 2").starts_with("221 "));
+        let head = client.block();
+        assert!(head.contains(&"Subject: Re:".to_string()), "{:?}", head);
+        assert!(!head.contains(&"Subject: Re: hi".to_string()));
+
+        assert!(client.send("OVER 5-9").starts_with("423 "));
+        assert!(client.send("FROB").starts_with("500 "));
+        assert!(client.send("QUIT").starts_with("205 "));
+    }
+}
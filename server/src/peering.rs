@@ -0,0 +1,701 @@
+//! article federation between NNNTP servers, in the spirit of Usenet's streaming feeds
+//! (RFC 4644).
+//!
+//! every server is named by its `--domain`, which it puts in front of the Path of each article it
+//! passes on. a server offers the articles listed since its last round to each of its peers with
+//! `/peer/check`, which answers with the message ids the peer does not have yet, and then sends
+//! those with `/peer/takethis`. articles are never offered to a server already in their Path, and
+//! never taken twice, so they cannot loop between servers.
+//!
+//! both ends of a peering list each other in their peers file, with the same key. a line names
+//! the peer, its jsontp address, the groups it is fed (and the only ones taken from it) and the
+//! key, and can end with the restricted groups the peer may feed, which are refused otherwise:
+//!
+//! ```text
+//! # name             address          groups                  key     restricted
+//! news2.example.com  127.0.0.1:8081   comp.*,!comp.lang.java  s3cret
+//! news3.example.com  127.0.0.1:8082   comp.*                  k3y     comp.announce
+//! ```
+//!
+//! posts taken into moderated groups wait for a local moderator, like posts from local users.
+
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use jsontp::client::*;
+
+use serde::de::DeserializeOwned;
+use serde_json::json;
+
+use protocol::{
+    CheckRequest, CheckResponse, ErrorBody, FeedArticle, NnntpRequest, PeerCredentials,
+    TakeThisRequest, TakeThisResponse, BODY_KEY, ERROR_KEY,
+};
+
+use crate::error::NnntpError;
+use crate::message_id;
+use crate::routes;
+use crate::settings::Settings;
+use crate::storage::{ArticleId, NewComment, NewPost, Storage};
+use crate::watch;
+use crate::wildmat::Wildmat;
+
+/// how many articles are offered to a peer at once
+const FEED_BATCH: u32 = 100;
+
+/// how long a peer gets to accept a connection, and then to answer
+const PEER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// a server articles are exchanged with
+#[derive(Debug, Clone, PartialEq)]
+pub struct Peer {
+    /// the `--domain` of the peer, as it appears in Path headers
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    /// the groups fed to the peer, and taken from it
+    pub groups: Wildmat,
+    /// the secret both ends share
+    pub key: String,
+    /// the restricted groups taken from the peer, none if this is not given
+    pub restricted: Option<Wildmat>,
+}
+
+/// reads a peers file, with one peer a line and `#` starting comments
+pub fn load(path: &Path) -> Result<Vec<Peer>, String> {
+    let file = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+
+    file.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.split('#').next().unwrap_or("").trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(number, line)| parse_peer(line).map_err(|e| format!("line {}: {}", number, e)))
+        .collect()
+}
+
+fn parse_peer(line: &str) -> Result<Peer, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+
+    let (name, address, groups, key, restricted) = match fields.as_slice() {
+        [name, address, groups, key] => (*name, *address, *groups, *key, None),
+        [name, address, groups, key, restricted] => {
+            (*name, *address, *groups, *key, Some(*restricted))
+        }
+        _ => {
+            return Err(
+                "expected a name, an address, groups, a key and maybe restricted groups"
+                    .to_string(),
+            )
+        }
+    };
+
+    let (host, port) = address
+        .rsplit_once(':')
+        .ok_or_else(|| format!("{} is not a host:port address", address))?;
+    let port = port
+        .parse()
+        .map_err(|_| format!("{} is not a port", port))?;
+
+    Ok(Peer {
+        name: name.to_string(),
+        host: host.to_string(),
+        port,
+        groups: Wildmat::parse(groups)?,
+        key: key.to_string(),
+        restricted: restricted.map(Wildmat::parse).transpose()?,
+    })
+}
+
+/// the configured peer making a request, if it sent the right key
+pub fn authenticate<'a>(
+    settings: &'a Settings,
+    credentials: &PeerCredentials,
+) -> Result<&'a Peer, NnntpError> {
+    settings
+        .peers
+        .iter()
+        .find(|peer| peer.name == credentials.name && peer.key == credentials.key)
+        .ok_or(NnntpError::Unauthorized)
+}
+
+/// the offered message ids this server does not have yet, in the order they were offered
+pub fn wanted(storage: &dyn Storage, message_ids: &[String]) -> Result<Vec<String>, NnntpError> {
+    let mut wanted: Vec<String> = vec![];
+
+    for message_id in message_ids {
+        if message_id::is_valid(message_id)
+            && !wanted.contains(message_id)
+            && storage.article_by_message_id(message_id)?.is_none()
+        {
+            wanted.push(message_id.clone());
+        }
+    }
+
+    Ok(wanted)
+}
+
+/// stores an article from `peer`, returning why it was refused if it was. authors from other
+/// servers are kept as `user@server`, so they can never pass for local users
+pub fn take(
+    storage: &dyn Storage,
+    settings: &Settings,
+    peer: &Peer,
+    article: &FeedArticle,
+) -> Result<Option<String>, NnntpError> {
+    if !message_id::is_valid(&article.message_id) {
+        return Ok(Some("invalid message id".to_string()));
+    }
+
+    // these end up in the headers of articles over NNTP, so they must not be able to add any
+    if routes::check_email(&article.author_email).is_err() {
+        return Ok(Some("invalid email".to_string()));
+    }
+    if [&article.author, &article.subject]
+        .iter()
+        .any(|field| field.chars().any(char::is_control))
+    {
+        return Ok(Some(
+            "control characters in the author or subject".to_string(),
+        ));
+    }
+    if !article.references.iter().all(|id| message_id::is_valid(id)) {
+        return Ok(Some("invalid references".to_string()));
+    }
+
+    if storage
+        .article_by_message_id(&article.message_id)?
+        .is_some()
+    {
+        return Ok(Some("already have it".to_string()));
+    }
+
+    // the peer should have put itself in front, but it is not trusted to
+    let path = match article.path.split('!').next() {
+        Some(first) if first == peer.name => article.path.clone(),
+        _ => format!("{}!{}", peer.name, article.path),
+    };
+    if path.split('!').any(|server| server == settings.domain) {
+        return Ok(Some(format!("already went through {}", settings.domain)));
+    }
+
+    if !peer.groups.matches(&article.group) {
+        return Ok(Some(format!(
+            "{} is not fed by {}",
+            article.group, peer.name
+        )));
+    }
+    let group = match storage.group(&article.group)? {
+        Some(group) => group,
+        None => return Ok(Some(format!("no such group {}", article.group))),
+    };
+    if group.restricted
+        && !peer
+            .restricted
+            .as_ref()
+            .is_some_and(|restricted| restricted.matches(&group.name))
+    {
+        return Ok(Some(format!(
+            "{} is restricted and not fed by {}",
+            group.name, peer.name
+        )));
+    }
+
+    let author = if article.author.contains('@') {
+        article.author.clone()
+    } else {
+        format!("{}@{}", article.author, peer.name)
+    };
+
+    let (post, reply_to) = match article.references.as_slice() {
+        [] => {
+            storage.insert_post(&NewPost {
+                group: &article.group,
+                subject: &article.subject,
+                body: &article.body,
+                author: &author,
+                author_email: &article.author_email,
+                created_at: article.created_at,
+                message_id: &article.message_id,
+                path: &path,
+                pending: group.moderated,
+            })?;
+            if !group.moderated {
+                watch::notify();
+            }
+
+            return Ok(None);
+        }
+        [post] => (post, None),
+        [post, .., reply_to] => (post, Some(reply_to)),
+    };
+
+    let parent = match storage.article_by_message_id(post)? {
+        Some(ArticleId::Post(id)) => storage.post(id)?,
+        _ => None,
+    };
+    let parent = match parent {
+        Some(parent) if parent.group_name == article.group && parent.is_listed() => parent,
+        _ => return Ok(Some(format!("no post {} in {}", post, article.group))),
+    };
+
+    let reply_to = match reply_to {
+        None => None,
+        Some(reply_to) => match storage.article_by_message_id(reply_to)? {
+            Some(ArticleId::Comment(id))
+                if storage
+                    .comment(id)?
+                    .is_some_and(|comment| comment.parent_id == parent.id) =>
+            {
+                Some(id)
+            }
+            _ => return Ok(Some(format!("no comment {} under {}", reply_to, post))),
+        },
+    };
+
+    storage.insert_comment(&NewComment {
+        parent_id: parent.id,
+        reply_to,
+        references: &article.references,
+        body: &article.body,
+        author: &author,
+        author_email: &article.author_email,
+        created_at: article.created_at,
+        message_id: &article.message_id,
+        path: &path,
+    })?;
+    watch::notify();
+
+    Ok(None)
+}
+
+/// an article as it is sent to peers, unless it is not listed or has been cancelled
+fn feed_article(
+    storage: &dyn Storage,
+    settings: &Settings,
+    article: ArticleId,
+) -> Result<Option<FeedArticle>, NnntpError> {
+    match article {
+        ArticleId::Post(id) => Ok(storage
+            .post(id)?
+            .filter(|post| post.is_listed() && post.cancelled_at.is_none())
+            .map(|post| FeedArticle {
+                message_id: post.message_id,
+                path: format!("{}!{}", settings.domain, post.path),
+                group: post.group_name,
+                subject: post.subject,
+                body: post.body,
+                author: post.author,
+                author_email: post.author_email,
+                created_at: post.created_at,
+                references: vec![],
+            })),
+        ArticleId::Comment(id) => {
+            let comment = match storage.comment(id)? {
+                Some(comment) if comment.cancelled_at.is_none() => comment,
+                _ => return Ok(None),
+            };
+            let post = match storage.post(comment.parent_id)? {
+                Some(post) => post,
+                None => return Ok(None),
+            };
+
+            Ok(Some(FeedArticle {
+                message_id: comment.message_id,
+                path: format!("{}!{}", settings.domain, comment.path),
+                group: post.group_name,
+                subject: String::new(),
+                body: comment.body,
+                author: comment.author,
+                author_email: comment.author_email,
+                created_at: comment.created_at,
+                references: comment.references,
+            }))
+        }
+    }
+}
+
+/// the next articles to offer `peer` after the place `after` in the feed, leaving out those in
+/// groups it is not fed and those that came through it. also returns the place in the feed the
+/// articles go up to, which is `after` once the peer has caught up
+pub fn outgoing(
+    storage: &dyn Storage,
+    settings: &Settings,
+    peer: &Peer,
+    after: i64,
+) -> Result<(Vec<FeedArticle>, i64), NnntpError> {
+    let mut articles = vec![];
+    let mut last = after;
+
+    for (seq, article) in storage.feed_after(after, FEED_BATCH)? {
+        last = seq;
+
+        if let Some(article) = feed_article(storage, settings, article)? {
+            if peer.groups.matches(&article.group)
+                && !article.path.split('!').any(|server| server == peer.name)
+            {
+                articles.push(article);
+            }
+        }
+    }
+
+    Ok((articles, last))
+}
+
+/// sends a request to `peer`, returning the payload of its response. this does what jsontp's
+/// `Request::send` does, which panics when the peer cannot be reached, with timeouts on top
+fn send<T: DeserializeOwned>(
+    peer: &Peer,
+    resource: &str,
+    request: &NnntpRequest,
+) -> Result<T, String> {
+    let request = json!({
+        "jsontp": "1.0-rc1",
+        "type": "request",
+        "method": "GET",
+        "resource": resource,
+        "headers": {},
+        "body": { "content": "", "encoding": "identity", BODY_KEY: request.to_value() },
+    });
+
+    let address = (peer.host.as_str(), peer.port)
+        .to_socket_addrs()
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or_else(|| format!("{} has no address", peer.host))?;
+    let mut stream =
+        TcpStream::connect_timeout(&address, PEER_TIMEOUT).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(PEER_TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(PEER_TIMEOUT)))
+        .map_err(|e| e.to_string())?;
+
+    stream
+        .write_all(request.to_string().as_bytes())
+        .map_err(|e| e.to_string())?;
+
+    // the server closes the connection once it has answered
+    let mut answer = vec![];
+    stream.read_to_end(&mut answer).map_err(|e| e.to_string())?;
+    let response: JsontpResponse = serde_json::from_slice(&answer)
+        .map_err(|e| format!("{} answered with invalid json: {}", resource, e))?;
+
+    if response.status.code != 200 {
+        let error = response
+            .body
+            .other
+            .get(ERROR_KEY)
+            .and_then(|error| serde_json::from_value::<ErrorBody>(error.clone()).ok());
+
+        return Err(match error {
+            Some(error) => error.message,
+            None => format!("{} failed with {}", resource, response.status.code),
+        });
+    }
+
+    match response.body.other.get(BODY_KEY) {
+        Some(payload) => serde_json::from_value(payload.clone()).map_err(|e| e.to_string()),
+        None => Err(format!("{} answered without a payload", resource)),
+    }
+}
+
+/// offers `peer` every article listed since the last round and sends it those it wants,
+/// returning how many it took. the progress is saved after each batch, so a peer that goes away
+/// halfway picks up where it left off
+pub fn feed(storage: &dyn Storage, settings: &Settings, peer: &Peer) -> Result<usize, String> {
+    let credentials = PeerCredentials {
+        name: settings.domain.clone(),
+        key: peer.key.clone(),
+    };
+    let mut taken = 0;
+
+    loop {
+        let after = storage.peer_progress(&peer.name).map_err(|e| e.0)?;
+        let (articles, last) =
+            outgoing(storage, settings, peer, after).map_err(|e| e.to_string())?;
+
+        if last == after {
+            return Ok(taken);
+        }
+
+        if !articles.is_empty() {
+            let check = NnntpRequest::Check(CheckRequest {
+                message_ids: articles.iter().map(|a| a.message_id.clone()).collect(),
+                peer: credentials.clone(),
+            });
+            let wanted: CheckResponse = send(peer, "/peer/check", &check)?;
+
+            let articles: Vec<FeedArticle> = articles
+                .into_iter()
+                .filter(|article| wanted.wanted.contains(&article.message_id))
+                .collect();
+
+            if !articles.is_empty() {
+                let takethis = NnntpRequest::TakeThis(TakeThisRequest {
+                    articles,
+                    peer: credentials.clone(),
+                });
+                let response: TakeThisResponse = send(peer, "/peer/takethis", &takethis)?;
+
+                for refused in response.refused {
+                    eprintln!(
+                        "{} refused {}: {}",
+                        peer.name, refused.message_id, refused.reason
+                    );
+                }
+                taken += response.accepted.len();
+            }
+        }
+
+        storage
+            .set_peer_progress(&peer.name, last)
+            .map_err(|e| e.0)?;
+    }
+}
+
+/// feeds every peer every `interval`, each from a thread of its own. the peers and the domain
+/// do not change when the settings are reloaded, so the threads keep the settings they started
+/// with
+pub fn start(storage: &'static dyn Storage, settings: Arc<Settings>, interval: Duration) {
+    for index in 0..settings.peers.len() {
+        let settings = settings.clone();
+
+        thread::spawn(move || loop {
+            let peer = &settings.peers[index];
+
+            match feed(storage, &settings, peer) {
+                Ok(0) => {}
+                Ok(taken) => println!("fed {} articles to {}", taken, peer.name),
+                Err(e) => eprintln!("failed to feed {}: {}", peer.name, e),
+            }
+
+            thread::sleep(interval);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::policy::Policy;
+    use crate::settings::{Registration, TEST_BCRYPT_COST};
+    use crate::storage::{MemoryStorage, LOCAL_PATH};
+
+    fn server(domain: &str, peer: &str) -> (MemoryStorage, Settings) {
+        let storage = MemoryStorage::new();
+        storage
+            .insert_group("comp.lang.rust", "", 0, "ferris")
+            .unwrap();
+        storage
+            .insert_group("comp.lang.java", "", 0, "ferris")
+            .unwrap();
+
+        let settings = Settings {
+            session_ttl: 60,
+            bcrypt_cost: TEST_BCRYPT_COST,
+            auto_create_groups: false,
+            domain: domain.to_string(),
+            peers: vec![
+                parse_peer(&format!("{} 127.0.0.1:0 comp.*,!comp.lang.java key", peer)).unwrap(),
+            ],
+            policy: Policy::default(),
+            registration: Registration::Open,
+            log_requests: false,
+            trusted_proxies: 0,
+        };
+
+        (storage, settings)
+    }
+
+    fn post(storage: &MemoryStorage, group: &str, message_id: &str) -> i32 {
+        storage
+            .insert_post(&NewPost {
+                group,
+                subject: "hello",
+                body: "world",
+                author: "ferris",
+                author_email: "f@example.com",
+                created_at: 10,
+                message_id,
+                path: LOCAL_PATH,
+                pending: false,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn reads_peers_files() {
+        let peer = parse_peer("news2.example.com 127.0.0.1:8081 comp.* s3cret").unwrap();
+        assert_eq!(peer.name, "news2.example.com");
+        assert_eq!((peer.host.as_str(), peer.port), ("127.0.0.1", 8081));
+        assert!(peer.groups.matches("comp.lang.rust"));
+        assert_eq!(peer.key, "s3cret");
+        assert_eq!(peer.restricted, None);
+
+        let peer = parse_peer("news2.example.com 127.0.0.1:8081 comp.* s3cret comp.ann*").unwrap();
+        assert!(peer.restricted.unwrap().matches("comp.announce"));
+
+        assert!(parse_peer("news2.example.com 127.0.0.1:8081 comp.*").is_err());
+        assert!(parse_peer("news2.example.com 127.0.0.1 comp.* s3cret").is_err());
+        assert!(parse_peer("news2.example.com 127.0.0.1:8081 comp.,! s3cret").is_err());
+    }
+
+    #[test]
+    fn articles_travel_once_and_never_back() {
+        let (news1, settings1) = server("news1.example.com", "news2.example.com");
+        let (news2, settings2) = server("news2.example.com", "news1.example.com");
+        let to_news2 = &settings1.peers[0];
+        let from_news1 = &settings2.peers[0];
+
+        let rust = post(&news1, "comp.lang.rust", "<1@news1.example.com>");
+        post(&news1, "comp.lang.java", "<2@news1.example.com>");
+        news1
+            .insert_comment(&NewComment {
+                parent_id: rust,
+                reply_to: None,
+                references: &["<1@news1.example.com>".to_string()],
+                body: "hi!",
+                author: "corro",
+                author_email: "c@example.com",
+                created_at: 20,
+                message_id: "<3@news1.example.com>",
+                path: LOCAL_PATH,
+            })
+            .unwrap();
+
+        // java is not fed to news2
+        let (articles, last) = outgoing(&news1, &settings1, to_news2, 0).unwrap();
+        assert_eq!(last, 3);
+        let offered: Vec<&str> = articles.iter().map(|a| a.message_id.as_str()).collect();
+        assert_eq!(offered, ["<1@news1.example.com>", "<3@news1.example.com>"]);
+        assert_eq!(articles[0].path, "news1.example.com!not-for-mail");
+
+        let ids: Vec<String> = articles.iter().map(|a| a.message_id.clone()).collect();
+        assert_eq!(wanted(&news2, &ids).unwrap(), ids);
+
+        for article in &articles {
+            assert_eq!(take(&news2, &settings2, from_news1, article).unwrap(), None);
+        }
+        assert!(wanted(&news2, &ids).unwrap().is_empty());
+        assert_eq!(
+            take(&news2, &settings2, from_news1, &articles[0]).unwrap(),
+            Some("already have it".to_string())
+        );
+
+        let copy = news2.post(1).unwrap().unwrap();
+        assert_eq!(copy.message_id, "<1@news1.example.com>");
+        assert_eq!(copy.author, "ferris@news1.example.com");
+        assert_eq!(copy.path, "news1.example.com!not-for-mail");
+        let reply = news2.comment(1).unwrap().unwrap();
+        assert_eq!(reply.parent_id, copy.id);
+        assert_eq!(reply.author, "corro@news1.example.com");
+
+        // nothing goes back to where it came from
+        let (articles, last) = outgoing(&news2, &settings2, &settings2.peers[0], 0).unwrap();
+        assert!(articles.is_empty());
+        assert_eq!(last, 2);
+
+        // not even when another server passes it along
+        let mut looped = FeedArticle {
+            message_id: "<4@news1.example.com>".to_string(),
+            path: "news3.example.com!news1.example.com!not-for-mail".to_string(),
+            ..first_post(&news1, &settings1)
+        };
+        assert_eq!(
+            take(&news1, &settings1, to_news2, &looped).unwrap(),
+            Some("already went through news1.example.com".to_string())
+        );
+
+        looped.path = "news3.example.com!not-for-mail".to_string();
+        looped.group = "comp.lang.java".to_string();
+        assert!(take(&news1, &settings1, to_news2, &looped)
+            .unwrap()
+            .unwrap()
+            .contains("not fed"));
+
+        looped.group = "comp.lang.rust".to_string();
+        looped.references = vec!["<404@news3.example.com>".to_string()];
+        assert!(take(&news1, &settings1, to_news2, &looped)
+            .unwrap()
+            .unwrap()
+            .starts_with("no post"));
+    }
+
+    #[test]
+    fn peers_cannot_skip_moderation_or_restrictions() {
+        let (news1, settings1) = server("news1.example.com", "news2.example.com");
+        let (news2, mut settings2) = server("news2.example.com", "news1.example.com");
+        post(&news1, "comp.lang.rust", "<1@news1.example.com>");
+        let article = first_post(&news1, &settings1);
+
+        news2.set_group_restricted("comp.lang.rust", true).unwrap();
+        assert!(take(&news2, &settings2, &settings2.peers[0], &article)
+            .unwrap()
+            .unwrap()
+            .contains("restricted"));
+
+        settings2.peers[0].restricted = Some(Wildmat::parse("comp.lang.*").unwrap());
+        news2.set_group_moderated("comp.lang.rust", true).unwrap();
+        assert_eq!(
+            take(&news2, &settings2, &settings2.peers[0], &article).unwrap(),
+            None
+        );
+        assert!(!news2.post(1).unwrap().unwrap().is_listed());
+    }
+
+    #[test]
+    fn articles_cannot_add_headers() {
+        let (news1, settings1) = server("news1.example.com", "news2.example.com");
+        let (news2, settings2) = server("news2.example.com", "news1.example.com");
+        post(&news1, "comp.lang.rust", "<1@news1.example.com>");
+        let from_news1 = &settings2.peers[0];
+
+        let article = FeedArticle {
+            author_email: "f@example.com>\r\nApproved: yes".to_string(),
+            ..first_post(&news1, &settings1)
+        };
+        assert_eq!(
+            take(&news2, &settings2, from_news1, &article).unwrap(),
+            Some("invalid email".to_string())
+        );
+
+        let article = FeedArticle {
+            subject: "hello\r\nApproved: yes".to_string(),
+            ..first_post(&news1, &settings1)
+        };
+        assert!(take(&news2, &settings2, from_news1, &article)
+            .unwrap()
+            .unwrap()
+            .starts_with("control characters"));
+
+        assert!(news2.post(1).unwrap().is_none());
+    }
+
+    #[test]
+    fn peers_that_are_down_are_an_error() {
+        let (storage, mut settings) = server("news1.example.com", "news2.example.com");
+        post(&storage, "comp.lang.rust", "<1@news1.example.com>");
+
+        // nothing listens on a port that was just given up
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        settings.peers[0].port = port;
+
+        assert!(feed(&storage, &settings, &settings.peers[0]).is_err());
+        assert_eq!(storage.peer_progress("news2.example.com").unwrap(), 0);
+    }
+
+    // the first post of a server, as it would be fed
+    fn first_post(storage: &MemoryStorage, settings: &Settings) -> FeedArticle {
+        feed_article(storage, settings, ArticleId::Post(1))
+            .unwrap()
+            .unwrap()
+    }
+}
@@ -0,0 +1,112 @@
+use protocol::Role;
+
+use crate::error::NnntpError;
+use crate::storage::{GroupRow, RoleRow, Storage};
+
+/// the roles of a user, and what they allow
+pub struct Permissions {
+    roles: Vec<RoleRow>,
+}
+
+impl Permissions {
+    pub fn of(storage: &dyn Storage, username: &str) -> Result<Permissions, NnntpError> {
+        Ok(Permissions {
+            roles: storage.roles_of(username)?,
+        })
+    }
+
+    /// whether the user holds `role` in `group`, either there or everywhere
+    fn has(&self, role: Role, group: &str) -> bool {
+        self.roles.iter().any(|held| {
+            held.role == role.as_str() && (held.group.is_empty() || held.group == group)
+        })
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.has(Role::Admin, "")
+    }
+
+    /// whether the user can remove anything in `group` and change its settings
+    pub fn moderates(&self, group: &str) -> bool {
+        self.is_admin() || self.has(Role::Moderator, group)
+    }
+
+    /// whether the user can post and comment in `group`. readers cannot, and restricted groups
+    /// only take posts from their posters
+    pub fn may_post(&self, group: &GroupRow) -> bool {
+        if self.moderates(&group.name) {
+            return true;
+        }
+
+        if group.restricted {
+            self.has(Role::Poster, &group.name)
+        } else {
+            !self.has(Role::Reader, &group.name)
+        }
+    }
+
+    /// whether the user can create groups, which everyone but readers can
+    pub fn may_create_groups(&self) -> bool {
+        self.is_admin() || !self.has(Role::Reader, "")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(name: &str, restricted: bool) -> GroupRow {
+        GroupRow {
+            name: name.to_string(),
+            description: String::new(),
+            created_at: 0,
+            creator: String::new(),
+            post_count: 0,
+            restricted,
+            moderated: false,
+        }
+    }
+
+    fn permissions(roles: &[(&str, &str)]) -> Permissions {
+        Permissions {
+            roles: roles
+                .iter()
+                .map(|(role, group)| RoleRow {
+                    role: role.to_string(),
+                    group: group.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn roles_hold_in_their_group_or_everywhere() {
+        let open = group("comp.lang.rust", false);
+        let announce = group("local.announce", true);
+
+        let nobody = permissions(&[]);
+        assert!(nobody.may_post(&open));
+        assert!(!nobody.may_post(&announce));
+        assert!(!nobody.moderates("comp.lang.rust"));
+
+        let poster = permissions(&[("poster", "local.announce")]);
+        assert!(poster.may_post(&announce));
+
+        let reader = permissions(&[("reader", "")]);
+        assert!(!reader.may_post(&open));
+        assert!(!reader.may_create_groups());
+
+        let muted = permissions(&[("reader", "comp.lang.rust")]);
+        assert!(!muted.may_post(&open));
+        assert!(muted.may_create_groups());
+
+        let moderator = permissions(&[("moderator", "local.announce")]);
+        assert!(moderator.may_post(&announce));
+        assert!(moderator.moderates("local.announce"));
+        assert!(!moderator.moderates("comp.lang.rust"));
+
+        let admin = permissions(&[("admin", "")]);
+        assert!(admin.moderates("comp.lang.rust"));
+        assert!(admin.may_post(&announce));
+    }
+}
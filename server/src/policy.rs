@@ -0,0 +1,199 @@
+//! the rules usernames and passwords have to follow. a request that breaks several of them is
+//! told about all of them at once, each in a sentence of its own.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::error::NnntpError;
+
+/// the passwords refused unless `--common-passwords` names another list, one a line
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+
+/// bcrypt only looks at this many bytes of a password, anything after them would not matter
+const MAX_PASSWORD_BYTES: usize = 72;
+
+pub struct Policy {
+    pub min_username_length: usize,
+    pub max_username_length: usize,
+    /// the characters usernames can have besides ASCII letters and digits, which they start with.
+    /// `@` is never allowed, since it marks the authors of articles from other servers
+    pub username_punctuation: String,
+    pub min_password_length: usize,
+    /// lowercase, passwords are compared to them whatever their case
+    pub common_passwords: HashSet<String>,
+}
+
+impl Default for Policy {
+    fn default() -> Policy {
+        Policy {
+            min_username_length: 3,
+            max_username_length: 32,
+            username_punctuation: "._-".to_string(),
+            min_password_length: 8,
+            common_passwords: parse_common_passwords(COMMON_PASSWORDS),
+        }
+    }
+}
+
+fn parse_common_passwords(list: &str) -> HashSet<String> {
+    list.lines()
+        .map(str::trim)
+        .filter(|password| !password.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// reads a list of common passwords, one a line, to refuse instead of the built in one
+pub fn load_common_passwords(path: &Path) -> Result<HashSet<String>, String> {
+    let list = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+
+    Ok(parse_common_passwords(&list))
+}
+
+/// fails with every broken rule, when there are any
+fn check(broken: Vec<String>) -> Result<(), NnntpError> {
+    if broken.is_empty() {
+        return Ok(());
+    }
+
+    Err(NnntpError::BadRequest(format!(
+        "bad request - {}",
+        broken.join(". ")
+    )))
+}
+
+impl Policy {
+    pub fn check_username(&self, username: &str) -> Result<(), NnntpError> {
+        let mut broken = vec![];
+        let length = username.chars().count();
+
+        if length < self.min_username_length || length > self.max_username_length {
+            broken.push(format!(
+                "usernames have to be {} to {} characters long",
+                self.min_username_length, self.max_username_length
+            ));
+        }
+
+        if !username.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+            broken.push("usernames have to start with a letter or a digit".to_string());
+        }
+
+        if username.contains('@') {
+            broken.push(
+                "usernames cannot have an @, which marks the authors of articles from other \
+                 servers"
+                    .to_string(),
+            );
+        }
+
+        let punctuation = &self.username_punctuation;
+        // an @ was already refused above
+        let allowed = |c: char| c == '@' || c.is_ascii_alphanumeric() || punctuation.contains(c);
+        if !username.chars().all(allowed) {
+            broken.push(format!(
+                "usernames can only have letters, digits and {}",
+                punctuation.replace('@', "")
+            ));
+        }
+
+        check(broken)
+    }
+
+    /// `username` is the user the password is for, which it must not be
+    pub fn check_password(&self, username: &str, password: &str) -> Result<(), NnntpError> {
+        let mut broken = vec![];
+
+        if password.chars().count() < self.min_password_length {
+            broken.push(format!(
+                "passwords have to be at least {} characters long",
+                self.min_password_length
+            ));
+        }
+
+        if password.len() > MAX_PASSWORD_BYTES {
+            broken.push(format!(
+                "passwords can be at most {} bytes long",
+                MAX_PASSWORD_BYTES
+            ));
+        }
+
+        let lowercase = password.to_lowercase();
+        if self.common_passwords.contains(&lowercase) {
+            broken.push("that password is too common".to_string());
+        }
+
+        if lowercase == username.to_lowercase() {
+            broken.push("passwords cannot be the username".to_string());
+        }
+
+        check(broken)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn broken(result: Result<(), NnntpError>) -> Vec<String> {
+        match result {
+            Ok(()) => vec![],
+            Err(NnntpError::BadRequest(message)) => message
+                .trim_start_matches("bad request - ")
+                .split(". ")
+                .map(str::to_string)
+                .collect(),
+            Err(e) => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn usernames_are_told_every_rule_they_break() {
+        let policy = Policy::default();
+
+        for good in ["ferris", "Ferris_the.crab-2", "abc"] {
+            assert!(broken(policy.check_username(good)).is_empty(), "{}", good);
+        }
+
+        assert_eq!(
+            broken(policy.check_username("")),
+            [
+                "usernames have to be 3 to 32 characters long",
+                "usernames have to start with a letter or a digit",
+            ]
+        );
+        assert_eq!(
+            broken(policy.check_username("ferris@news.example.com")),
+            ["usernames cannot have an @, which marks the authors of articles from other servers"]
+        );
+        assert_eq!(
+            broken(policy.check_username("ferris the\tcrab")),
+            ["usernames can only have letters, digits and ._-"]
+        );
+        assert_eq!(broken(policy.check_username(&"a".repeat(33))).len(), 1);
+        assert_eq!(broken(policy.check_username("crab™")).len(), 1);
+    }
+
+    #[test]
+    fn passwords_have_to_be_long_and_uncommon() {
+        let policy = Policy::default();
+
+        assert!(broken(policy.check_password("ferris", "correct horse battery")).is_empty());
+
+        assert_eq!(
+            broken(policy.check_password("ferris", "crab")),
+            ["passwords have to be at least 8 characters long"]
+        );
+        assert_eq!(
+            broken(policy.check_password("ferris", "PassWord")),
+            ["that password is too common"]
+        );
+        assert_eq!(
+            broken(policy.check_password("ferris123", "Ferris123")),
+            ["passwords cannot be the username"]
+        );
+        assert_eq!(
+            broken(policy.check_password("ferris", &"ä".repeat(40))),
+            ["passwords can be at most 72 bytes long"]
+        );
+    }
+}
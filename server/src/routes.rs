@@ -0,0 +1,3159 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use bcrypt::{hash, verify};
+
+use jsontp::server::*;
+
+use protocol::{
+    ApproveRequest, ApproveUserRequest, Article, Author, CancelRequest, ChangeEmailRequest,
+    ChangePasswordRequest, CheckRequest, CheckResponse, Comment, CommentCreated, CommentRequest,
+    ConfigureGroupRequest, CreateGroupRequest, CreateInvitesRequest, Credentials,
+    DeleteAccountRequest, EditRequest, Group, HeldPost, HistoryRequest, Invite, InvitesRequest,
+    InvitesResponse, ListGroupsRequest, ListRequest, ListResponse, LoginRequest, LogoutRequest,
+    MarkReadRequest, NewArticle, NewUserRequest, NnntpRequest, PendingUsersRequest,
+    PendingUsersResponse, Post, PostCreated, PostRequest, QueueRequest, RefusedArticle,
+    RejectRequest, Revision, Role, RoleRequest, SearchHit, SearchRequest, Session,
+    SubscribeRequest, Subscription, SubscriptionsRequest, TakeThisRequest, TakeThisResponse,
+    UnreadCount, UserCreated, WatchRequest, WatchResponse, BODY_KEY,
+};
+
+use crate::error::{respond, NnntpError, Reply};
+use crate::limits::{limiter, Client, Limiter};
+use crate::message_id;
+use crate::newsrc::Ranges;
+use crate::peering;
+use crate::permissions::Permissions;
+use crate::session;
+use crate::settings::{settings, Registration, Settings};
+use crate::storage::{
+    storage, ArticleId, CommentRow, GroupRow, InviteRow, NewComment, NewPost, NewUser, NewsrcRow,
+    PostQuery, PostRow, Redeemed, SearchQuery, Storage, LOCAL_PATH,
+};
+use crate::watch;
+use crate::wildmat::Wildmat;
+
+/// how many posts `/list` returns when the request does not say
+const DEFAULT_PAGE_SIZE: u32 = 100;
+
+/// the most posts `/list` returns at once, whatever the request asks for
+const MAX_PAGE_SIZE: u32 = 500;
+
+/// how many hits `/search` returns when the request does not say
+const DEFAULT_SEARCH_HITS: u32 = 20;
+
+/// the most hits `/search` returns, whatever the request asks for
+const MAX_SEARCH_HITS: u32 = 100;
+
+/// the most invites `/invites/create` makes at once
+const MAX_INVITES: u32 = 100;
+
+/// how many seconds `/watch` waits for new articles when the request does not say
+const DEFAULT_WATCH_TIMEOUT: u64 = 30;
+
+/// the most seconds `/watch` waits, whatever the request asks for
+const MAX_WATCH_TIMEOUT: u64 = 300;
+
+/// reads the `nnntp` object of a request, which has to be of type `kind`
+fn parse(req: &JsontpRequest, kind: &str) -> Result<NnntpRequest, NnntpError> {
+    let nnntp = match req.body.other.get(BODY_KEY) {
+        Some(nnntp) => nnntp.clone(),
+        None => {
+            return Err(NnntpError::BadRequest(
+                "bad request - nnntp is required".to_string(),
+            ))
+        }
+    };
+
+    let request = NnntpRequest::from_value(nnntp)
+        .map_err(|e| NnntpError::BadRequest(format!("bad request - {}", e)))?;
+
+    if request.kind() != kind {
+        return Err(NnntpError::BadRequest(format!(
+            "bad request - expected a {} request, not {}",
+            kind,
+            request.kind()
+        )));
+    }
+
+    Ok(request)
+}
+
+/// the address of the client, from the `X-Forwarded-For` header of `trusted_proxies` reverse
+/// proxies, see [`crate::limits`]. each proxy adds the address it got the request from to the
+/// end, so the client is that many entries from the end, and whatever comes before was sent by
+/// the client itself. without trusted proxies the header could only come from the client
+fn forwarded_for(req: &JsontpRequest, trusted_proxies: usize) -> Option<&str> {
+    if trusted_proxies == 0 {
+        return None;
+    }
+
+    let header = req
+        .headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("x-forwarded-for"))
+        .map(|(_, value)| value)?;
+
+    let addresses: Vec<&str> = match header {
+        Value::String(addresses) => addresses.split(',').collect(),
+        Value::Array(addresses) => addresses.iter().filter_map(Value::as_str).collect(),
+        _ => vec![],
+    };
+
+    // fewer entries than proxies means the request did not come through all of them
+    let index = addresses.len().checked_sub(trusted_proxies)?;
+
+    Some(addresses[index].trim()).filter(|address| !address.is_empty())
+}
+
+/// the handler of every route: parses a request of type `kind` and responds to it, unless the
+/// client is being throttled
+pub fn serve(req: JsontpRequest, kind: &str) -> Response {
+    // the same settings all the way through, even if they are reloaded in the meantime
+    let settings = settings();
+
+    let result = parse(&req, kind).and_then(|request| {
+        let ip = forwarded_for(&req, settings.trusted_proxies);
+
+        throttled(storage(), &settings, limiter(), ip, request)
+    });
+
+    if settings.log_requests {
+        let code = match &result {
+            Ok(_) => 200,
+            Err(e) => e.code(),
+        };
+
+        println!(
+            "{} {} {}",
+            forwarded_for(&req, settings.trusted_proxies).unwrap_or("-"),
+            kind,
+            code
+        );
+    }
+
+    respond(&req, result)
+}
+
+/// carries out a request from a client at `ip`, unless the client is being throttled
+fn throttled(
+    storage: &dyn Storage,
+    settings: &Settings,
+    limiter: &Limiter,
+    ip: Option<&str>,
+    request: NnntpRequest,
+) -> Result<Reply, NnntpError> {
+    let password_user = request.username().map(str::to_string);
+    // requests made with a session count against its user, whatever else they carry
+    let username = match (&password_user, request.token()) {
+        (Some(username), _) => Some(username.clone()),
+        (None, Some(token)) => session::user_for(storage, token)?,
+        (None, None) => None,
+    };
+    let client = Client {
+        ip,
+        username: username.as_deref(),
+        password: password_user.is_some(),
+        signup: matches!(request, NnntpRequest::New(_)),
+    };
+
+    limiter.admit(&client, Instant::now())?;
+
+    // a watch holds the connection thread until it is over, so it needs a place of its own
+    let _watching = match request {
+        NnntpRequest::Watch(_) => Some(
+            watch::start(client.ip, client.username)
+                .ok_or(NnntpError::TooManyRequests(DEFAULT_WATCH_TIMEOUT))?,
+        ),
+        _ => None,
+    };
+
+    PASSWORD_CHECKED.set(None);
+    let result = handle(storage, settings, request);
+
+    // only a password that was checked counts, right or wrong
+    if let Some(succeeded) = PASSWORD_CHECKED.take() {
+        limiter.record(&client, succeeded, Instant::now());
+    }
+
+    result
+}
+
+/// carries out a request
+pub fn handle(
+    storage: &dyn Storage,
+    settings: &Settings,
+    request: NnntpRequest,
+) -> Result<Reply, NnntpError> {
+    match request {
+        NnntpRequest::Post(request) => handle_post(storage, settings, request),
+        NnntpRequest::Comment(request) => handle_comment(storage, settings, request),
+        NnntpRequest::List(request) => handle_list(storage, request),
+        NnntpRequest::New(request) => handle_new(storage, settings, request),
+        NnntpRequest::Login(request) => handle_login(storage, settings, request),
+        NnntpRequest::Logout(request) => handle_logout(storage, request),
+        NnntpRequest::ChangePassword(request) => handle_change_password(storage, settings, request),
+        NnntpRequest::ChangeEmail(request) => handle_change_email(storage, request),
+        NnntpRequest::DeleteAccount(request) => handle_delete_account(storage, request),
+        NnntpRequest::CreateGroup(request) => handle_create_group(storage, request),
+        NnntpRequest::ListGroups(request) => handle_list_groups(storage, request),
+        NnntpRequest::Edit(request) => handle_edit(storage, request),
+        NnntpRequest::History(request) => handle_history(storage, request),
+        NnntpRequest::Cancel(request) => handle_cancel(storage, request),
+        NnntpRequest::ConfigureGroup(request) => handle_configure_group(storage, request),
+        NnntpRequest::GrantRole(request) => handle_role(storage, request, true),
+        NnntpRequest::RevokeRole(request) => handle_role(storage, request, false),
+        NnntpRequest::ModerationQueue(request) => handle_moderation_queue(storage, request),
+        NnntpRequest::Approve(request) => handle_approve(storage, request),
+        NnntpRequest::Reject(request) => handle_reject(storage, request),
+        NnntpRequest::Search(request) => handle_search(storage, request),
+        NnntpRequest::CreateInvites(request) => handle_create_invites(storage, request),
+        NnntpRequest::Invites(request) => handle_invites(storage, request),
+        NnntpRequest::PendingUsers(request) => handle_pending_users(storage, request),
+        NnntpRequest::ApproveUser(request) => handle_approve_user(storage, request),
+        NnntpRequest::Check(request) => handle_check(storage, settings, request),
+        NnntpRequest::TakeThis(request) => handle_takethis(storage, settings, request),
+        NnntpRequest::Subscribe(request) => handle_subscribe(storage, request, true),
+        NnntpRequest::Unsubscribe(request) => handle_subscribe(storage, request, false),
+        NnntpRequest::Subscriptions(request) => handle_subscriptions(storage, request),
+        NnntpRequest::MarkRead(request) => handle_mark_read(storage, request),
+        NnntpRequest::Watch(request) => handle_watch(storage, request),
+    }
+}
+
+/// comments on a post of `group`, or on one of the comments under it when `reply_to` is set.
+/// returns the id and message id of the comment
+pub fn comment_on(
+    storage: &dyn Storage,
+    settings: &Settings,
+    group: &str,
+    parent_id: i32,
+    reply_to: Option<i32>,
+    body: &str,
+    author: &Author,
+) -> Result<(i32, String), NnntpError> {
+    let username = authenticate(storage, &author.credentials)?;
+
+    let parent = match storage.post(parent_id)? {
+        Some(parent) if parent.group_name == group && parent.is_listed() => parent,
+        _ => {
+            return Err(NnntpError::NotFound(format!(
+                "post {} does not exist in {}",
+                parent_id, group
+            )))
+        }
+    };
+
+    if parent.cancelled_at.is_some() {
+        return Err(already_cancelled(Article::Post(parent_id)));
+    }
+
+    check_may_post(storage, &username, group)?;
+
+    // like Usenet's References header: the post, then every comment down to the one replied to
+    let references = match reply_to {
+        None => vec![parent.message_id],
+        Some(reply_to) => match storage.comment(reply_to)? {
+            Some(comment) if comment.cancelled_at.is_some() => {
+                return Err(already_cancelled(Article::Comment(reply_to)))
+            }
+            Some(comment) if comment.parent_id == parent_id => {
+                let mut references = comment.references;
+                references.push(comment.message_id);
+                references
+            }
+            _ => {
+                return Err(NnntpError::NotFound(format!(
+                    "comment {} does not exist under post {}",
+                    reply_to, parent_id
+                )))
+            }
+        },
+    };
+
+    let created_at = session::now();
+    let message_id = message_id::generate(&settings.domain, created_at)?;
+
+    let id = storage.insert_comment(&NewComment {
+        parent_id,
+        reply_to,
+        references: &references,
+        body,
+        author: &username,
+        author_email: &email_of(storage, &username)?,
+        created_at,
+        message_id: &message_id,
+        path: LOCAL_PATH,
+    })?;
+    watch::notify();
+
+    Ok((id, message_id))
+}
+
+/// registers a user, as far as the registration mode of the server lets them. returns whether
+/// they have to wait for an admin's approval
+fn save_new_user(
+    storage: &dyn Storage,
+    settings: &Settings,
+    request: &NewUserRequest,
+) -> Result<bool, NnntpError> {
+    let username = &request.username;
+
+    settings.policy.check_username(username)?;
+    settings
+        .policy
+        .check_password(username, &request.password)?;
+    check_email(&request.email)?;
+
+    if settings.registration == Registration::Invite && request.invite.is_none() {
+        return Err(NnntpError::Forbidden(
+            "registering needs an invite code from an admin".to_string(),
+        ));
+    }
+
+    let pending = settings.registration == Registration::Approval;
+    let hashed = hash_password(settings, &request.password)?;
+    let user = NewUser {
+        username,
+        hashed_password: &hashed,
+        email: &request.email,
+        pending,
+    };
+
+    // the invite is used in the same transaction the user is created in, so two registrations
+    // cannot both use it and a failing one does not use it up
+    let redeemed = match (settings.registration, &request.invite) {
+        (Registration::Invite, Some(code)) => storage.redeem_invite(code, session::now(), &user)?,
+        _ => match storage.insert_user(&user)? {
+            true => Redeemed::Registered,
+            false => Redeemed::UsernameTaken,
+        },
+    };
+
+    match redeemed {
+        Redeemed::Registered => Ok(pending),
+        Redeemed::InvalidInvite => Err(NnntpError::Forbidden(
+            "the invite code is not valid or has already been used".to_string(),
+        )),
+        Redeemed::UsernameTaken => Err(NnntpError::Conflict("User already exists".to_string())),
+    }
+}
+
+fn hash_password(settings: &Settings, password: &str) -> Result<String, NnntpError> {
+    hash(password, settings.bcrypt_cost).map_err(|e| NnntpError::Internal(e.to_string()))
+}
+
+/// fails unless `email` is empty or looks like `someone@example.com`. it ends up in the `From`
+/// header of articles, so anything that could break out of `name <email>` is refused
+pub fn check_email(email: &str) -> Result<(), NnntpError> {
+    let valid = email.is_empty()
+        || match email.split_once('@') {
+            Some((local, domain)) => {
+                !local.is_empty()
+                    && !domain.is_empty()
+                    && !domain.contains('@')
+                    && email.len() <= 254
+                    && email
+                        .chars()
+                        .all(|c| c.is_ascii_graphic() && !"<>(),;:\\\"[]".contains(c))
+            }
+            None => false,
+        };
+
+    if !valid {
+        return Err(NnntpError::BadRequest(format!(
+            "bad request - {} is not a valid email",
+            email
+        )));
+    }
+
+    Ok(())
+}
+
+/// fails if `subject` has control characters. it ends up in the `Subject` header of articles,
+/// where a line break would start headers of its own, or end the article over NNTP
+fn check_subject(subject: &str) -> Result<(), NnntpError> {
+    if subject.chars().any(char::is_control) {
+        return Err(NnntpError::BadRequest(
+            "bad request - subjects cannot have control characters".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// the email shown on the articles of `username`, the one stored on their account
+fn email_of(storage: &dyn Storage, username: &str) -> Result<String, NnntpError> {
+    Ok(storage.email_of(username)?.unwrap_or_default())
+}
+
+thread_local! {
+    // whether the password of the request this thread handles was right, once it was checked.
+    // that is what counts towards a lockout, and not how the request turned out, since it can
+    // fail for other reasons before or after
+    static PASSWORD_CHECKED: Cell<Option<bool>> = const { Cell::new(None) };
+}
+
+/// whether `password` is right for `username`. unknown users are simply not verified
+pub fn verify_user(
+    storage: &dyn Storage,
+    username: &str,
+    password: &str,
+) -> Result<bool, NnntpError> {
+    let verified = match storage.password_hash(username)? {
+        Some(hashed) => {
+            verify(password, &hashed).map_err(|e| NnntpError::Internal(e.to_string()))?
+        }
+        None => false,
+    };
+    PASSWORD_CHECKED.set(Some(verified));
+
+    if !verified {
+        return Ok(false);
+    }
+
+    // only someone with the password learns that the account is waiting
+    if storage.is_pending(username)? {
+        return Err(NnntpError::Forbidden(
+            "your account is waiting for an admin to approve it".to_string(),
+        ));
+    }
+
+    Ok(true)
+}
+
+/// `username` as it was registered, which is how every table but the one of users refers to them,
+/// whatever case it was typed in
+pub fn registered_username(storage: &dyn Storage, username: &str) -> Result<String, NnntpError> {
+    Ok(storage
+        .registered_username(username)?
+        .unwrap_or_else(|| username.to_string()))
+}
+
+/// works out which user is making a request, from either a session token or a password
+fn authenticate(storage: &dyn Storage, credentials: &Credentials) -> Result<String, NnntpError> {
+    match credentials {
+        Credentials::Token { token } => match session::user_for(storage, token)? {
+            Some(username) => Ok(username),
+            None => Err(NnntpError::Unauthorized),
+        },
+        Credentials::Password { username, password } => {
+            if !verify_user(storage, username, password)? {
+                return Err(NnntpError::Unauthorized);
+            }
+
+            registered_username(storage, username)
+        }
+    }
+}
+
+/// the user reading, for requests anyone can make but readers get more out of. they are
+/// authenticated before anything else, so a request with a password never fails before the
+/// password was checked
+fn authenticate_reader(
+    storage: &dyn Storage,
+    reader: Option<&Credentials>,
+) -> Result<Option<String>, NnntpError> {
+    reader
+        .map(|credentials| authenticate(storage, credentials))
+        .transpose()
+}
+
+/// whether `name` can name a group: dot separated parts of lowercase letters, digits, `+`, `-`
+/// and `_`, like `comp.lang.rust`
+pub fn valid_group_name(name: &str) -> bool {
+    name.split('.').all(|part| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "+-_".contains(c))
+    })
+}
+
+fn group_not_found(name: &str) -> NnntpError {
+    NnntpError::NotFound(format!("group {} does not exist", name))
+}
+
+fn to_group(row: GroupRow) -> Group {
+    Group {
+        name: row.name,
+        description: row.description,
+        created_at: row.created_at,
+        creator: row.creator,
+        post_count: row.post_count,
+        restricted: row.restricted,
+        moderated: row.moderated,
+    }
+}
+
+/// creates a group, making its creator the group's first moderator
+fn create_group(
+    storage: &dyn Storage,
+    name: &str,
+    description: &str,
+    restricted: bool,
+    moderated: bool,
+    creator: &str,
+) -> Result<(), NnntpError> {
+    if !Permissions::of(storage, creator)?.may_create_groups() {
+        return Err(NnntpError::Forbidden(
+            "readers cannot create groups".to_string(),
+        ));
+    }
+
+    if !valid_group_name(name) {
+        return Err(NnntpError::BadRequest(format!(
+            "bad request - {} is not a valid group name",
+            name
+        )));
+    }
+
+    if !storage.insert_group(name, description, session::now(), creator)? {
+        return Err(NnntpError::Conflict(format!(
+            "group {} already exists",
+            name
+        )));
+    }
+
+    if restricted {
+        storage.set_group_restricted(name, true)?;
+    }
+    if moderated {
+        storage.set_group_moderated(name, true)?;
+    }
+    storage.grant_role(creator, Role::Moderator.as_str(), name)?;
+
+    Ok(())
+}
+
+/// fails unless `username` can post and comment in `group`, which has to exist
+fn check_may_post(storage: &dyn Storage, username: &str, group: &str) -> Result<(), NnntpError> {
+    let group = match storage.group(group)? {
+        Some(group) => group,
+        None => return Err(group_not_found(group)),
+    };
+
+    if !Permissions::of(storage, username)?.may_post(&group) {
+        return Err(NnntpError::Forbidden(format!(
+            "you are not allowed to post to {}",
+            group.name
+        )));
+    }
+
+    Ok(())
+}
+
+/// posts to an existing group, or creates the group first when `auto_create_groups` is set.
+/// posts to moderated groups wait for a moderator, unless a moderator wrote them
+pub fn post_to_group(
+    storage: &dyn Storage,
+    settings: &Settings,
+    group: &str,
+    subject: &str,
+    body: &str,
+    author: &Author,
+) -> Result<PostCreated, NnntpError> {
+    let username = authenticate(storage, &author.credentials)?;
+
+    check_subject(subject)?;
+
+    if storage.group(group)?.is_none() {
+        if !settings.auto_create_groups {
+            return Err(group_not_found(group));
+        }
+
+        create_group(storage, group, "", false, false, &username)?;
+    }
+
+    check_may_post(storage, &username, group)?;
+
+    let pending = match storage.group(group)? {
+        Some(row) => row.moderated && !Permissions::of(storage, &username)?.moderates(group),
+        None => return Err(group_not_found(group)),
+    };
+
+    let created_at = session::now();
+    let message_id = message_id::generate(&settings.domain, created_at)?;
+
+    let id = storage.insert_post(&NewPost {
+        group,
+        subject,
+        body,
+        author: &username,
+        author_email: &email_of(storage, &username)?,
+        created_at,
+        message_id: &message_id,
+        path: LOCAL_PATH,
+        pending,
+    })?;
+    if !pending {
+        watch::notify();
+    }
+
+    Ok(PostCreated {
+        id,
+        message_id,
+        pending,
+    })
+}
+
+fn handle_post(
+    storage: &dyn Storage,
+    settings: &Settings,
+    request: PostRequest,
+) -> Result<Reply, NnntpError> {
+    let created = post_to_group(
+        storage,
+        settings,
+        &request.group,
+        &request.post.subject,
+        &request.post.body,
+        &request.author,
+    )?;
+
+    let message = if created.pending {
+        "Posted OK, waiting for a moderator"
+    } else {
+        "Posted OK"
+    };
+
+    Ok(Reply::new(message).payload(&created))
+}
+
+fn handle_comment(
+    storage: &dyn Storage,
+    settings: &Settings,
+    request: CommentRequest,
+) -> Result<Reply, NnntpError> {
+    let (id, message_id) = comment_on(
+        storage,
+        settings,
+        &request.group,
+        request.parent.id,
+        request.reply_to,
+        &request.comment.body,
+        &request.author,
+    )?;
+
+    Ok(Reply::new("Commented OK").payload(&CommentCreated { id, message_id }))
+}
+
+fn handle_new(
+    storage: &dyn Storage,
+    settings: &Settings,
+    request: NewUserRequest,
+) -> Result<Reply, NnntpError> {
+    let pending = save_new_user(storage, settings, &request)?;
+
+    let message = if pending {
+        "User created, waiting for approval"
+    } else {
+        "User created"
+    };
+
+    Ok(Reply::new(message).payload(&UserCreated { pending }))
+}
+
+/// checks a password once and hands out a session token to use instead of it
+fn handle_login(
+    storage: &dyn Storage,
+    settings: &Settings,
+    request: LoginRequest,
+) -> Result<Reply, NnntpError> {
+    let username = check_password(storage, &request.username, &request.password)?;
+
+    let (token, expires_at) = session::start(storage, &username, settings.session_ttl)?;
+
+    Ok(Reply::new("Logged in").payload(&Session { token, expires_at }))
+}
+
+fn handle_logout(storage: &dyn Storage, request: LogoutRequest) -> Result<Reply, NnntpError> {
+    session::end(storage, &request.token, request.all)?;
+
+    Ok(Reply::new("Logged out"))
+}
+
+/// fails unless `password` is the current password of `username`, which every change to an
+/// account asks for, so a stolen session token is not enough to take the account over. returns
+/// the username as it was registered
+fn check_password(
+    storage: &dyn Storage,
+    username: &str,
+    password: &str,
+) -> Result<String, NnntpError> {
+    if !verify_user(storage, username, password)? {
+        return Err(NnntpError::Unauthorized);
+    }
+
+    registered_username(storage, username)
+}
+
+/// changes the password of a user, ending all of their sessions
+fn handle_change_password(
+    storage: &dyn Storage,
+    settings: &Settings,
+    request: ChangePasswordRequest,
+) -> Result<Reply, NnntpError> {
+    let username = check_password(storage, &request.username, &request.password)?;
+    settings
+        .policy
+        .check_password(&username, &request.new_password)?;
+
+    storage.set_password_hash(&username, &hash_password(settings, &request.new_password)?)?;
+    storage.delete_sessions_of(&username)?;
+
+    Ok(Reply::new("Password changed"))
+}
+
+/// changes the email shown on the articles a user writes from now on
+fn handle_change_email(
+    storage: &dyn Storage,
+    request: ChangeEmailRequest,
+) -> Result<Reply, NnntpError> {
+    let username = check_password(storage, &request.username, &request.password)?;
+    check_email(&request.email)?;
+
+    storage.set_email(&username, &request.email)?;
+
+    Ok(Reply::new("Email changed"))
+}
+
+/// deletes a user, who can no longer log in. their articles stay, and so does their username
+fn handle_delete_account(
+    storage: &dyn Storage,
+    request: DeleteAccountRequest,
+) -> Result<Reply, NnntpError> {
+    let username = check_password(storage, &request.username, &request.password)?;
+
+    storage.delete_user(&username, session::now())?;
+
+    Ok(Reply::new("Account deleted"))
+}
+
+fn article_id(article: Article) -> ArticleId {
+    match article {
+        Article::Post(id) => ArticleId::Post(id),
+        Article::Comment(id) => ArticleId::Comment(id),
+    }
+}
+
+/// who wrote an article, where, and whether it has been cancelled
+struct ArticleInfo {
+    author: String,
+    group: String,
+    cancelled: bool,
+    /// whether the post, or the post a comment is under, made it past moderation
+    listed: bool,
+}
+
+fn describe(article: Article) -> String {
+    match article {
+        Article::Post(id) => format!("post {}", id),
+        Article::Comment(id) => format!("comment {}", id),
+    }
+}
+
+fn already_cancelled(article: Article) -> NnntpError {
+    NnntpError::Conflict(format!("{} has been cancelled", describe(article)))
+}
+
+/// looks up an article, which has to exist
+fn find_article(storage: &dyn Storage, article: Article) -> Result<ArticleInfo, NnntpError> {
+    let found = match article {
+        Article::Post(id) => storage.post(id)?.map(|post| ArticleInfo {
+            listed: post.is_listed(),
+            author: post.author,
+            group: post.group_name,
+            cancelled: post.cancelled_at.is_some(),
+        }),
+        Article::Comment(id) => match storage.comment(id)? {
+            Some(comment) => storage.post(comment.parent_id)?.map(|post| ArticleInfo {
+                listed: post.is_listed(),
+                author: comment.author,
+                group: post.group_name,
+                cancelled: comment.cancelled_at.is_some(),
+            }),
+            None => None,
+        },
+    };
+
+    found.ok_or_else(|| NnntpError::NotFound(format!("{} does not exist", describe(article))))
+}
+
+/// withdraws an article, which its author, the moderators of its group and admins can do. it
+/// stays as a tombstone, so replies to it still have their place in the thread
+fn cancel_article(
+    storage: &dyn Storage,
+    article: Article,
+    credentials: &Credentials,
+) -> Result<(), NnntpError> {
+    let username = authenticate(storage, credentials)?;
+    let info = find_article(storage, article)?;
+
+    if info.cancelled {
+        return Err(already_cancelled(article));
+    }
+
+    if info.author != username && !Permissions::of(storage, &username)?.moderates(&info.group) {
+        return Err(NnntpError::Forbidden(
+            "only the author or a moderator of the group can cancel an article".to_string(),
+        ));
+    }
+
+    storage.cancel_article(article_id(article), &username, session::now())?;
+
+    Ok(())
+}
+
+/// replaces the subject and/or body of an article, on behalf of its original author
+fn edit_article(
+    storage: &dyn Storage,
+    article: Article,
+    subject: Option<&str>,
+    body: Option<&str>,
+    credentials: &Credentials,
+) -> Result<(), NnntpError> {
+    let username = authenticate(storage, credentials)?;
+
+    let info = find_article(storage, article)?;
+
+    if info.cancelled {
+        return Err(already_cancelled(article));
+    }
+
+    if info.author != username {
+        return Err(NnntpError::Forbidden(
+            "only the author of an article can edit it".to_string(),
+        ));
+    }
+
+    if matches!(article, Article::Comment(_)) && subject.is_some() {
+        return Err(NnntpError::BadRequest(
+            "bad request - comments have no subject".to_string(),
+        ));
+    }
+
+    if subject.is_none() && body.is_none() {
+        return Err(NnntpError::BadRequest(
+            "bad request - an edit needs a new subject or body".to_string(),
+        ));
+    }
+
+    if let Some(subject) = subject {
+        check_subject(subject)?;
+    }
+
+    storage.edit_article(article_id(article), subject, body, session::now())?;
+
+    Ok(())
+}
+
+fn handle_edit(storage: &dyn Storage, request: EditRequest) -> Result<Reply, NnntpError> {
+    edit_article(
+        storage,
+        request.article,
+        request.subject.as_deref(),
+        request.body.as_deref(),
+        &request.author,
+    )?;
+
+    Ok(Reply::new("Edited OK"))
+}
+
+fn handle_cancel(storage: &dyn Storage, request: CancelRequest) -> Result<Reply, NnntpError> {
+    cancel_article(storage, request.article, &request.author)?;
+
+    Ok(Reply::new("Cancelled OK"))
+}
+
+fn handle_history(storage: &dyn Storage, request: HistoryRequest) -> Result<Reply, NnntpError> {
+    let reader = authenticate_reader(storage, request.reader.as_ref())?;
+    let info = find_article(storage, request.article)?;
+
+    if info.cancelled {
+        return Err(already_cancelled(request.article));
+    }
+
+    // held back articles do not exist as far as anyone but their authors and moderators knows
+    if !info.listed {
+        let may_see = match &reader {
+            Some(username) => {
+                *username == info.author
+                    || Permissions::of(storage, username)?.moderates(&info.group)
+            }
+            None => false,
+        };
+
+        if !may_see {
+            return Err(NnntpError::NotFound(format!(
+                "{} does not exist",
+                describe(request.article)
+            )));
+        }
+    }
+
+    let revisions: Vec<Revision> = storage
+        .revisions(article_id(request.article))?
+        .into_iter()
+        .map(|revision| Revision {
+            subject: revision.subject,
+            body: revision.body,
+            replaced_at: revision.replaced_at,
+        })
+        .collect();
+
+    Ok(Reply::new("processed OK").payload(&revisions))
+}
+
+fn handle_create_group(
+    storage: &dyn Storage,
+    request: CreateGroupRequest,
+) -> Result<Reply, NnntpError> {
+    let username = authenticate(storage, &request.creator)?;
+
+    create_group(
+        storage,
+        &request.name,
+        &request.description,
+        request.restricted,
+        request.moderated,
+        &username,
+    )?;
+
+    let group = match storage.group(&request.name)? {
+        Some(row) => to_group(row),
+        None => return Err(group_not_found(&request.name)),
+    };
+
+    Ok(Reply::new("Group created").payload(&group))
+}
+
+fn handle_configure_group(
+    storage: &dyn Storage,
+    request: ConfigureGroupRequest,
+) -> Result<Reply, NnntpError> {
+    let username = authenticate(storage, &request.author)?;
+
+    if storage.group(&request.name)?.is_none() {
+        return Err(group_not_found(&request.name));
+    }
+
+    if !Permissions::of(storage, &username)?.moderates(&request.name) {
+        return Err(NnntpError::Forbidden(format!(
+            "only moderators of {} can change its settings",
+            request.name
+        )));
+    }
+
+    if let Some(restricted) = request.restricted {
+        storage.set_group_restricted(&request.name, restricted)?;
+    }
+    if let Some(moderated) = request.moderated {
+        storage.set_group_moderated(&request.name, moderated)?;
+    }
+
+    let group = match storage.group(&request.name)? {
+        Some(row) => to_group(row),
+        None => return Err(group_not_found(&request.name)),
+    };
+
+    Ok(Reply::new("Group configured").payload(&group))
+}
+
+/// grants a role, or revokes it when `grant` is false. only admins can do either
+fn handle_role(
+    storage: &dyn Storage,
+    request: RoleRequest,
+    grant: bool,
+) -> Result<Reply, NnntpError> {
+    let username = authenticate(storage, &request.author)?;
+
+    if !Permissions::of(storage, &username)?.is_admin() {
+        return Err(NnntpError::Forbidden(
+            "only admins can grant and revoke roles".to_string(),
+        ));
+    }
+
+    // roles without a group hold everywhere
+    let group = request.group.unwrap_or_default();
+
+    if request.role == Role::Admin && !group.is_empty() {
+        return Err(NnntpError::BadRequest(
+            "bad request - admins cannot be limited to a group".to_string(),
+        ));
+    }
+
+    if !group.is_empty() && storage.group(&group)?.is_none() {
+        return Err(group_not_found(&group));
+    }
+
+    let target = match storage.registered_username(&request.username)? {
+        Some(target) => target,
+        None => {
+            return Err(NnntpError::NotFound(format!(
+                "user {} does not exist",
+                request.username
+            )))
+        }
+    };
+
+    let role = request.role.as_str();
+
+    if grant {
+        storage.grant_role(&target, role, &group)?;
+        Ok(Reply::new("Role granted"))
+    } else {
+        if !storage.revoke_role(&target, role, &group)? {
+            return Err(NnntpError::NotFound(format!(
+                "{} is not a {} there",
+                target, role
+            )));
+        }
+        Ok(Reply::new("Role revoked"))
+    }
+}
+
+/// the user the request is made as, who has to be an admin to do `what`
+fn authenticate_admin(
+    storage: &dyn Storage,
+    credentials: &Credentials,
+    what: &str,
+) -> Result<String, NnntpError> {
+    let username = authenticate(storage, credentials)?;
+
+    if !Permissions::of(storage, &username)?.is_admin() {
+        return Err(NnntpError::Forbidden(format!("only admins can {}", what)));
+    }
+
+    Ok(username)
+}
+
+fn to_invite(row: InviteRow) -> Invite {
+    Invite {
+        code: row.code,
+        created_by: row.created_by,
+        created_at: row.created_at,
+        used_by: row.used_by,
+        used_at: row.used_at,
+    }
+}
+
+fn handle_create_invites(
+    storage: &dyn Storage,
+    request: CreateInvitesRequest,
+) -> Result<Reply, NnntpError> {
+    let username = authenticate_admin(storage, &request.author, "create invites")?;
+
+    let count = request.count.unwrap_or(1);
+    if !(1..=MAX_INVITES).contains(&count) {
+        return Err(NnntpError::BadRequest(format!(
+            "bad request - between 1 and {} invites can be created at once",
+            MAX_INVITES
+        )));
+    }
+
+    let created_at = session::now();
+    let mut invites: InvitesResponse = vec![];
+    for _ in 0..count {
+        let code = session::new_token()?;
+        storage.insert_invite(&code, &username, created_at)?;
+
+        invites.push(Invite {
+            code,
+            created_by: username.clone(),
+            created_at,
+            used_by: None,
+            used_at: None,
+        });
+    }
+
+    Ok(Reply::new("Invites created").payload(&invites))
+}
+
+fn handle_invites(storage: &dyn Storage, request: InvitesRequest) -> Result<Reply, NnntpError> {
+    authenticate_admin(storage, &request.author, "list invites")?;
+
+    let invites: InvitesResponse = storage.invites()?.into_iter().map(to_invite).collect();
+
+    Ok(Reply::new("processed OK").payload(&invites))
+}
+
+fn handle_pending_users(
+    storage: &dyn Storage,
+    request: PendingUsersRequest,
+) -> Result<Reply, NnntpError> {
+    authenticate_admin(storage, &request.author, "list users waiting for approval")?;
+
+    let pending: PendingUsersResponse = storage.pending_users()?;
+
+    Ok(Reply::new("processed OK").payload(&pending))
+}
+
+fn handle_approve_user(
+    storage: &dyn Storage,
+    request: ApproveUserRequest,
+) -> Result<Reply, NnntpError> {
+    authenticate_admin(storage, &request.author, "approve users")?;
+
+    if !storage.approve_user(&request.username)? {
+        return Err(NnntpError::NotFound(format!(
+            "user {} is not waiting for approval",
+            request.username
+        )));
+    }
+
+    Ok(Reply::new("User approved"))
+}
+
+/// the posts held back from groups that the user can see: the pending posts of the groups they
+/// moderate, and their own pending and rejected posts
+fn handle_moderation_queue(
+    storage: &dyn Storage,
+    request: QueueRequest,
+) -> Result<Reply, NnntpError> {
+    let username = authenticate(storage, &request.author)?;
+    let permissions = Permissions::of(storage, &username)?;
+
+    if let Some(group) = &request.group {
+        if storage.group(group)?.is_none() {
+            return Err(group_not_found(group));
+        }
+    }
+    let wanted = |group: &str| {
+        request
+            .group
+            .as_deref()
+            .is_none_or(|wanted| wanted == group)
+    };
+
+    let moderated: Vec<String> = storage
+        .groups()?
+        .into_iter()
+        .map(|group| group.name)
+        .filter(|group| wanted(group) && permissions.moderates(group))
+        .collect();
+
+    let mut rows = storage.pending_posts(&moderated)?;
+    for row in storage.held_posts_of(&username)? {
+        if wanted(&row.group_name) && !rows.iter().any(|held| held.id == row.id) {
+            rows.push(row);
+        }
+    }
+    rows.sort_by_key(|row| row.id);
+
+    let held: Vec<HeldPost> = rows
+        .into_iter()
+        .map(|mut row| HeldPost {
+            rejection: row.rejection.take(),
+            post: to_post(row, vec![]),
+        })
+        .collect();
+
+    Ok(Reply::new("processed OK").payload(&held))
+}
+
+/// looks up a post waiting for a moderator, which `username` has to be
+fn find_pending_post(
+    storage: &dyn Storage,
+    username: &str,
+    id: i32,
+) -> Result<PostRow, NnntpError> {
+    let post = match storage.post(id)? {
+        Some(post) => post,
+        None => return Err(NnntpError::NotFound(format!("post {} does not exist", id))),
+    };
+
+    if !Permissions::of(storage, username)?.moderates(&post.group_name) {
+        return Err(NnntpError::Forbidden(format!(
+            "only moderators of {} can approve or reject its posts",
+            post.group_name
+        )));
+    }
+
+    if post.cancelled_at.is_some() {
+        return Err(already_cancelled(Article::Post(id)));
+    }
+
+    if !post.pending {
+        return Err(NnntpError::Conflict(format!(
+            "post {} is not waiting for a moderator",
+            id
+        )));
+    }
+
+    Ok(post)
+}
+
+fn handle_approve(storage: &dyn Storage, request: ApproveRequest) -> Result<Reply, NnntpError> {
+    let username = authenticate(storage, &request.author)?;
+    find_pending_post(storage, &username, request.post)?;
+
+    storage.approve_post(request.post)?;
+    watch::notify();
+
+    Ok(Reply::new("Approved OK"))
+}
+
+fn handle_reject(storage: &dyn Storage, request: RejectRequest) -> Result<Reply, NnntpError> {
+    let username = authenticate(storage, &request.author)?;
+
+    if request.reason.trim().is_empty() {
+        return Err(NnntpError::BadRequest(
+            "bad request - a rejection needs a reason".to_string(),
+        ));
+    }
+
+    find_pending_post(storage, &username, request.post)?;
+
+    storage.reject_post(request.post, &request.reason)?;
+
+    Ok(Reply::new("Rejected OK"))
+}
+
+fn parse_wildmat(wildmat: &str) -> Result<Wildmat, NnntpError> {
+    Wildmat::parse(wildmat).map_err(|e| NnntpError::BadRequest(format!("bad request - {}", e)))
+}
+
+/// the groups matching `wildmat`. a plain group name that does not exist is an error, while a
+/// pattern may match nothing at all
+fn matching_groups(storage: &dyn Storage, wildmat: &str) -> Result<Vec<GroupRow>, NnntpError> {
+    let wildmat = parse_wildmat(wildmat)?;
+
+    let groups: Vec<GroupRow> = storage
+        .groups()?
+        .into_iter()
+        .filter(|group| wildmat.matches(&group.name))
+        .collect();
+
+    match wildmat.literal() {
+        Some(name) if groups.is_empty() => Err(group_not_found(&name)),
+        _ => Ok(groups),
+    }
+}
+
+fn handle_list_groups(
+    storage: &dyn Storage,
+    request: ListGroupsRequest,
+) -> Result<Reply, NnntpError> {
+    let groups: Vec<Group> = match request.pattern {
+        Some(pattern) => matching_groups(storage, &pattern)?,
+        None => storage.groups()?,
+    }
+    .into_iter()
+    .map(to_group)
+    .collect();
+
+    Ok(Reply::new("processed OK").payload(&groups))
+}
+
+/// a post as `/list` shows it. cancelled posts are tombstones, without their content or author
+fn to_post(row: PostRow, comments: Vec<Comment>) -> Post {
+    let cancelled = row.cancelled_at.is_some();
+
+    Post {
+        id: row.id,
+        group_name: row.group_name,
+        subject: if cancelled {
+            String::new()
+        } else {
+            row.subject
+        },
+        body: if cancelled { String::new() } else { row.body },
+        author: if cancelled { String::new() } else { row.author },
+        author_email: Some(row.author_email).filter(|_| !cancelled),
+        created_at: row.created_at,
+        message_id: row.message_id,
+        edited_at: row.edited_at,
+        cancelled_at: row.cancelled_at,
+        read: None,
+        comments,
+    }
+}
+
+/// a comment as `/list` shows it, a tombstone if it was cancelled
+fn to_comment(row: CommentRow) -> Comment {
+    let cancelled = row.cancelled_at.is_some();
+
+    Comment {
+        id: row.id,
+        reply_to: row.reply_to,
+        references: row.references,
+        body: if cancelled { String::new() } else { row.body },
+        author: if cancelled { String::new() } else { row.author },
+        author_email: Some(row.author_email).filter(|_| !cancelled),
+        created_at: row.created_at,
+        message_id: row.message_id,
+        edited_at: row.edited_at,
+        cancelled_at: row.cancelled_at,
+        read: None,
+    }
+}
+
+fn handle_list(storage: &dyn Storage, request: ListRequest) -> Result<Reply, NnntpError> {
+    let reader = authenticate_reader(storage, request.reader.as_ref())?;
+    let groups: Vec<String> = matching_groups(storage, &request.group)?
+        .into_iter()
+        .map(|group| group.name)
+        .collect();
+
+    let limit = request
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .min(MAX_PAGE_SIZE);
+    if limit == 0 {
+        return Err(NnntpError::BadRequest(
+            "bad request - limit has to be at least 1".to_string(),
+        ));
+    }
+    if request.only_unread && reader.is_none() {
+        return Err(NnntpError::BadRequest(
+            "bad request - only_unread needs a reader".to_string(),
+        ));
+    }
+
+    // one post more than asked for, to know whether there is a next page
+    let query = PostQuery {
+        after_id: request.after_id,
+        since: request.since,
+        offset: request.offset.unwrap_or(0),
+        limit: Some(limit + 1),
+    };
+    let mut rows = storage.posts_in_groups(&groups, &query)?;
+
+    let more = rows.len() > limit as usize;
+    rows.truncate(limit as usize);
+
+    // the comments of the whole page in one go, instead of a query per post
+    let ids: Vec<i32> = rows.iter().map(|row| row.id).collect();
+    let mut comments: HashMap<i32, Vec<Comment>> = HashMap::new();
+    for comment in storage.comments_on(&ids)? {
+        comments
+            .entry(comment.parent_id)
+            .or_default()
+            .push(to_comment(comment));
+    }
+
+    let mut posts: Vec<Post> = rows
+        .into_iter()
+        .map(|row| {
+            let comments = comments.remove(&row.id).unwrap_or_default();
+            to_post(row, comments)
+        })
+        .collect();
+
+    // the cursor goes by what was fetched, even when only_unread leaves some of it out
+    let next = match posts.last() {
+        Some(last) if more => Some(last.id),
+        _ => None,
+    };
+
+    let mut unread = vec![];
+    if let Some(username) = &reader {
+        unread = mark_unread(storage, username, &groups, &mut posts)?;
+
+        if request.only_unread {
+            posts.retain_mut(|post| {
+                post.comments.retain(|comment| comment.read == Some(false));
+                post.read == Some(false) || !post.comments.is_empty()
+            });
+        }
+    }
+
+    Ok(Reply::new("processed OK").payload(&ListResponse {
+        posts,
+        next,
+        unread,
+    }))
+}
+
+/// the numbers of those of `articles` that are in `group`, by article
+fn numbers_of(
+    storage: &dyn Storage,
+    group: &str,
+    articles: &[ArticleId],
+) -> Result<HashMap<ArticleId, i64>, NnntpError> {
+    Ok(storage
+        .numbers_of(group, articles)?
+        .into_iter()
+        .map(|(number, article)| (article, number))
+        .collect())
+}
+
+/// how many articles of `group` are not in `read`, counted by the database rather than by
+/// loading every number of the group
+fn unread_count(storage: &dyn Storage, group: &str, read: &Ranges) -> Result<i64, NnntpError> {
+    let (count, low, high) = match storage.count_articles(group, 1, i64::MAX)? {
+        (count, Some((low, high))) => (count, low, high),
+        (_, None) => return Ok(0),
+    };
+
+    let mut unread = count;
+    for (first, last) in read.iter() {
+        // ranges outside the water marks have nothing to count
+        if last >= low && first <= high {
+            unread -= storage
+                .count_articles(group, first.max(low), last.min(high))?
+                .0;
+        }
+    }
+
+    Ok(unread)
+}
+
+/// the newsrc of `username`, by group
+fn newsrc_of(
+    storage: &dyn Storage,
+    username: &str,
+) -> Result<HashMap<String, NewsrcRow>, NnntpError> {
+    Ok(storage
+        .newsrc(username)?
+        .into_iter()
+        .map(|row| (row.group.clone(), row))
+        .collect())
+}
+
+/// marks the posts and comments `username` has read, returning the unread counts of `groups`
+fn mark_unread(
+    storage: &dyn Storage,
+    username: &str,
+    groups: &[String],
+    posts: &mut [Post],
+) -> Result<Vec<UnreadCount>, NnntpError> {
+    let newsrc = newsrc_of(storage, username)?;
+    let mut counts = vec![];
+
+    for group in groups {
+        // only the numbers of the page, however large the group is
+        let mut articles = vec![];
+        for post in posts.iter().filter(|post| &post.group_name == group) {
+            articles.push(ArticleId::Post(post.id));
+            articles.extend(post.comments.iter().map(|c| ArticleId::Comment(c.id)));
+        }
+        let numbers = numbers_of(storage, group, &articles)?;
+        let read = Ranges::parse(newsrc.get(group).map_or("", |row| row.read.as_str()));
+        let is_read = |article| numbers.get(&article).is_some_and(|n| read.contains(*n));
+
+        for post in posts.iter_mut().filter(|post| &post.group_name == group) {
+            post.read = Some(is_read(ArticleId::Post(post.id)));
+
+            for comment in &mut post.comments {
+                comment.read = Some(is_read(ArticleId::Comment(comment.id)));
+            }
+        }
+
+        counts.push(UnreadCount {
+            group: group.clone(),
+            unread: unread_count(storage, group, &read)?,
+        });
+    }
+
+    Ok(counts)
+}
+
+/// the groups `username` subscribed to that still exist, with what they have read of them
+fn subscriptions_of(
+    storage: &dyn Storage,
+    username: &str,
+) -> Result<Vec<Subscription>, NnntpError> {
+    let mut subscriptions = vec![];
+
+    for row in storage.newsrc(username)? {
+        if !row.subscribed || storage.group(&row.group)?.is_none() {
+            continue;
+        }
+
+        let read = Ranges::parse(&row.read);
+        subscriptions.push(Subscription {
+            unread: unread_count(storage, &row.group, &read)?,
+            group: row.group,
+            read: read.to_string(),
+        });
+    }
+
+    Ok(subscriptions)
+}
+
+fn handle_subscribe(
+    storage: &dyn Storage,
+    request: SubscribeRequest,
+    subscribe: bool,
+) -> Result<Reply, NnntpError> {
+    let username = authenticate(storage, &request.author)?;
+
+    let groups = matching_groups(storage, &request.group)?;
+    if groups.is_empty() {
+        return Err(NnntpError::NotFound(format!(
+            "no group matches {}",
+            request.group
+        )));
+    }
+
+    let mut newsrc = newsrc_of(storage, &username)?;
+    for group in groups {
+        let row = match newsrc.remove(&group.name) {
+            Some(row) => NewsrcRow {
+                subscribed: subscribe,
+                ..row
+            },
+            None => NewsrcRow {
+                group: group.name,
+                subscribed: subscribe,
+                read: String::new(),
+            },
+        };
+
+        storage.set_newsrc(&username, &row)?;
+    }
+
+    let message = if subscribe {
+        "Subscribed OK"
+    } else {
+        "Unsubscribed OK"
+    };
+
+    Ok(Reply::new(message).payload(&subscriptions_of(storage, &username)?))
+}
+
+fn handle_subscriptions(
+    storage: &dyn Storage,
+    request: SubscriptionsRequest,
+) -> Result<Reply, NnntpError> {
+    let username = authenticate(storage, &request.author)?;
+
+    Ok(Reply::new("processed OK").payload(&subscriptions_of(storage, &username)?))
+}
+
+fn handle_mark_read(storage: &dyn Storage, request: MarkReadRequest) -> Result<Reply, NnntpError> {
+    let username = authenticate(storage, &request.author)?;
+
+    if storage.group(&request.group)?.is_none() {
+        return Err(group_not_found(&request.group));
+    }
+
+    let articles: Vec<ArticleId> = request.articles.iter().copied().map(article_id).collect();
+    let numbers = numbers_of(storage, &request.group, &articles)?;
+    let mut row = newsrc_of(storage, &username)?
+        .remove(&request.group)
+        .unwrap_or_else(|| NewsrcRow {
+            group: request.group.clone(),
+            subscribed: false,
+            read: String::new(),
+        });
+    let mut read = Ranges::parse(&row.read);
+
+    if request.all {
+        if let (_, Some((_, last))) = storage.count_articles(&request.group, 1, i64::MAX)? {
+            read.insert(1, last);
+        }
+    }
+
+    for article in request.articles {
+        match numbers.get(&article_id(article)) {
+            Some(number) => read.insert(*number, *number),
+            None => {
+                return Err(NnntpError::NotFound(format!(
+                    "{} is not in {}",
+                    describe(article),
+                    request.group
+                )))
+            }
+        }
+    }
+
+    row.read = read.to_string();
+    storage.set_newsrc(&username, &row)?;
+
+    Ok(Reply::new("Marked OK").payload(&Subscription {
+        group: row.group,
+        unread: unread_count(storage, &request.group, &read)?,
+        read: row.read,
+    }))
+}
+
+/// splits a search query into words and the phrases in double quotes. a quote left open runs to
+/// the end of the query
+fn search_terms(query: &str) -> Vec<String> {
+    query
+        .split('"')
+        .enumerate()
+        .flat_map(|(i, part)| {
+            // every other part is inside quotes
+            if i % 2 == 1 {
+                vec![part.split_whitespace().collect::<Vec<_>>().join(" ")]
+            } else {
+                part.split_whitespace().map(str::to_string).collect()
+            }
+        })
+        .filter(|term| !term.is_empty())
+        .collect()
+}
+
+fn handle_search(storage: &dyn Storage, request: SearchRequest) -> Result<Reply, NnntpError> {
+    let terms = search_terms(&request.query);
+    if terms.is_empty() {
+        return Err(NnntpError::BadRequest(
+            "bad request - a search needs something to look for".to_string(),
+        ));
+    }
+
+    let limit = request
+        .limit
+        .unwrap_or(DEFAULT_SEARCH_HITS)
+        .min(MAX_SEARCH_HITS);
+    if limit == 0 {
+        return Err(NnntpError::BadRequest(
+            "bad request - limit has to be at least 1".to_string(),
+        ));
+    }
+
+    let groups = match &request.group {
+        Some(wildmat) => Some(
+            matching_groups(storage, wildmat)?
+                .into_iter()
+                .map(|group| group.name)
+                .collect(),
+        ),
+        None => None,
+    };
+
+    let query = SearchQuery {
+        terms,
+        groups,
+        author: request.author,
+        since: request.since,
+        until: request.until,
+        limit,
+    };
+
+    let hits: Vec<SearchHit> = storage
+        .search(&query)?
+        .into_iter()
+        .map(|row| SearchHit {
+            article: match row.article {
+                ArticleId::Post(id) => Article::Post(id),
+                ArticleId::Comment(id) => Article::Comment(id),
+            },
+            post_id: row.post_id,
+            group_name: row.group_name,
+            subject: row.subject,
+            author: row.author,
+            created_at: row.created_at,
+            snippet: row.snippet,
+        })
+        .collect();
+
+    Ok(Reply::new("processed OK").payload(&hits))
+}
+
+fn handle_check(
+    storage: &dyn Storage,
+    settings: &Settings,
+    request: CheckRequest,
+) -> Result<Reply, NnntpError> {
+    peering::authenticate(settings, &request.peer)?;
+
+    let wanted = peering::wanted(storage, &request.message_ids)?;
+
+    Ok(Reply::new("Check OK").payload(&CheckResponse { wanted }))
+}
+
+fn handle_takethis(
+    storage: &dyn Storage,
+    settings: &Settings,
+    request: TakeThisRequest,
+) -> Result<Reply, NnntpError> {
+    let peer = peering::authenticate(settings, &request.peer)?;
+
+    let mut response = TakeThisResponse {
+        accepted: vec![],
+        refused: vec![],
+    };
+
+    for article in request.articles {
+        match peering::take(storage, settings, peer, &article)? {
+            None => response.accepted.push(article.message_id),
+            Some(reason) => response.refused.push(RefusedArticle {
+                message_id: article.message_id,
+                reason,
+            }),
+        }
+    }
+
+    Ok(Reply::new("Transferred OK").payload(&response))
+}
+
+/// an article that was just listed, if it is in one of `groups` and has not been cancelled
+fn new_article(
+    storage: &dyn Storage,
+    article: ArticleId,
+    groups: &[String],
+) -> Result<Option<NewArticle>, NnntpError> {
+    match article {
+        ArticleId::Post(id) => Ok(storage
+            .post(id)?
+            .filter(|post| post.cancelled_at.is_none() && groups.contains(&post.group_name))
+            .map(|post| NewArticle::Post(to_post(post, vec![])))),
+        ArticleId::Comment(id) => {
+            let comment = match storage.comment(id)? {
+                Some(comment) if comment.cancelled_at.is_none() => comment,
+                _ => return Ok(None),
+            };
+
+            Ok(storage
+                .post(comment.parent_id)?
+                .filter(|post| groups.contains(&post.group_name))
+                .map(|post| NewArticle::Comment {
+                    group_name: post.group_name,
+                    post_id: post.id,
+                    comment: to_comment(comment),
+                }))
+        }
+    }
+}
+
+/// waits until articles are listed in the watched groups, or the timeout passes
+fn handle_watch(storage: &dyn Storage, request: WatchRequest) -> Result<Reply, NnntpError> {
+    let reader = authenticate_reader(storage, request.reader.as_ref())?;
+    let groups: Vec<String> = match (&request.group, &reader) {
+        (Some(group), _) => matching_groups(storage, group)?
+            .into_iter()
+            .map(|group| group.name)
+            .collect(),
+        (None, Some(username)) => subscriptions_of(storage, username)?
+            .into_iter()
+            .map(|subscription| subscription.group)
+            .collect(),
+        (None, None) => {
+            return Err(NnntpError::BadRequest(
+                "bad request - watch a group or the subscriptions of a reader".to_string(),
+            ))
+        }
+    };
+
+    let timeout = request
+        .timeout
+        .unwrap_or(DEFAULT_WATCH_TIMEOUT)
+        .min(MAX_WATCH_TIMEOUT);
+    let deadline = Instant::now() + Duration::from_secs(timeout);
+
+    let mut next = match request.after {
+        Some(after) => after,
+        None => storage.feed_end()?,
+    };
+    let mut articles = vec![];
+
+    loop {
+        // taken before looking, so nothing listed in between goes unnoticed
+        let generation = watch::generation();
+        let batch = storage.feed_after(next, MAX_PAGE_SIZE)?;
+
+        for (seq, article) in &batch {
+            next = *seq;
+
+            if let Some(article) = new_article(storage, *article, &groups)? {
+                articles.push(article);
+            }
+        }
+
+        if !articles.is_empty() {
+            break;
+        }
+        // everything so far was in other groups, and there may be more of it
+        if !batch.is_empty() {
+            continue;
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() || !watch::wait(generation, remaining) {
+            break;
+        }
+    }
+
+    Ok(Reply::new("processed OK").payload(&WatchResponse { articles, next }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use protocol::{CommentContent, Parent, PostContent};
+
+    use crate::limits::Limits;
+    use crate::policy::Policy;
+    use crate::settings::TEST_BCRYPT_COST;
+    use crate::storage::{MemoryStorage, SqliteStorage};
+
+    fn settings() -> Settings {
+        Settings {
+            session_ttl: 60,
+            bcrypt_cost: TEST_BCRYPT_COST,
+            auto_create_groups: false,
+            domain: "news.example.com".to_string(),
+            peers: vec![],
+            policy: Policy::default(),
+            registration: Registration::Open,
+            log_requests: false,
+            trusted_proxies: 0,
+        }
+    }
+
+    #[test]
+    fn only_the_entries_of_trusted_proxies_count() {
+        let req = |header: Value| -> JsontpRequest {
+            serde_json::from_value(serde_json::json!({
+                "jsontp": "1.0-rc1",
+                "type": "request",
+                "method": "GET",
+                "resource": "/list",
+                "headers": { "X-Forwarded-For": header },
+                "body": { "content": "", "encoding": "identity" },
+            }))
+            .unwrap()
+        };
+
+        // the client sent the first entry, the one proxy in front added the second
+        let spoofed = req(Value::from("10.0.0.1, 192.0.2.1"));
+        assert_eq!(forwarded_for(&spoofed, 0), None);
+        assert_eq!(forwarded_for(&spoofed, 1), Some("192.0.2.1"));
+        assert_eq!(forwarded_for(&spoofed, 2), Some("10.0.0.1"));
+        assert_eq!(forwarded_for(&spoofed, 3), None);
+
+        let list = req(serde_json::json!(["10.0.0.1", "192.0.2.1", "198.51.100.1"]));
+        assert_eq!(forwarded_for(&list, 2), Some("192.0.2.1"));
+    }
+
+    fn author(username: &str, password: &str) -> Author {
+        Author {
+            credentials: Credentials::Password {
+                username: username.to_string(),
+                password: password.to_string(),
+            },
+            email: "e@example.com".to_string(),
+        }
+    }
+
+    fn storage_with_ferris() -> MemoryStorage {
+        let storage = MemoryStorage::new();
+        storage
+            .insert_user(&NewUser {
+                username: "ferris",
+                hashed_password: &hash("crab", TEST_BCRYPT_COST).unwrap(),
+                ..NewUser::default()
+            })
+            .unwrap();
+        storage
+            .insert_group("comp.lang.rust", "", 0, "ferris")
+            .unwrap();
+        storage
+            .grant_role("ferris", "moderator", "comp.lang.rust")
+            .unwrap();
+        storage
+    }
+
+    fn post(group: &str, author: Author) -> NnntpRequest {
+        NnntpRequest::Post(PostRequest {
+            group: group.to_string(),
+            post: PostContent {
+                subject: "hi".to_string(),
+                body: "there".to_string(),
+            },
+            author,
+        })
+    }
+
+    fn comment(group: &str, parent: i32, author: Author) -> NnntpRequest {
+        reply(group, parent, None, author)
+    }
+
+    fn reply(group: &str, parent: i32, reply_to: Option<i32>, author: Author) -> NnntpRequest {
+        NnntpRequest::Comment(CommentRequest {
+            group: group.to_string(),
+            parent: Parent { id: parent },
+            reply_to,
+            comment: CommentContent {
+                body: "hello".to_string(),
+            },
+            author,
+        })
+    }
+
+    fn list(storage: &dyn Storage, request: ListRequest) -> Result<ListResponse, NnntpError> {
+        let reply = handle(storage, &settings(), NnntpRequest::List(request))?;
+
+        Ok(serde_json::from_value(reply.other.unwrap()[BODY_KEY].clone()).unwrap())
+    }
+
+    #[test]
+    fn unknown_users_and_wrong_passwords_are_unauthorized() {
+        let storage = storage_with_ferris();
+
+        for author in [author("nobody", "crab"), author("ferris", "lobster")] {
+            assert_eq!(
+                handle(&storage, &settings(), post("comp.lang.rust", author)).err(),
+                Some(NnntpError::Unauthorized)
+            );
+        }
+
+        let token = Credentials::Token {
+            token: "made up".to_string(),
+        };
+        assert_eq!(
+            authenticate(&storage, &token),
+            Err(NnntpError::Unauthorized)
+        );
+    }
+
+    #[test]
+    fn comments_need_an_existing_parent_in_the_same_group() {
+        let storage = storage_with_ferris();
+        let ferris = author("ferris", "crab");
+
+        handle(
+            &storage,
+            &settings(),
+            post("comp.lang.rust", ferris.clone()),
+        )
+        .unwrap();
+
+        assert!(handle(
+            &storage,
+            &settings(),
+            comment("comp.lang.rust", 1, ferris.clone())
+        )
+        .is_ok());
+
+        for request in [
+            comment("comp.lang.rust", 404, ferris.clone()),
+            comment("comp.lang.c", 1, ferris.clone()),
+        ] {
+            assert!(matches!(
+                handle(&storage, &settings(), request),
+                Err(NnntpError::NotFound(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn logging_in_hands_out_a_token_usable_as_the_author() {
+        let storage = storage_with_ferris();
+
+        let login = NnntpRequest::Login(LoginRequest {
+            username: "ferris".to_string(),
+            password: "crab".to_string(),
+        });
+        let reply = handle(&storage, &settings(), login).unwrap();
+        let session: Session =
+            serde_json::from_value(reply.other.unwrap()[BODY_KEY].clone()).unwrap();
+
+        let by_token = Author {
+            credentials: Credentials::Token {
+                token: session.token,
+            },
+            email: "e@example.com".to_string(),
+        };
+        handle(&storage, &settings(), post("comp.lang.rust", by_token)).unwrap();
+
+        assert_eq!(
+            storage
+                .posts_in_groups(&["comp.lang.rust".to_string()], &PostQuery::default())
+                .unwrap()[0]
+                .author,
+            "ferris"
+        );
+    }
+
+    #[test]
+    fn requests_failing_for_other_reasons_do_not_reset_wrong_passwords() {
+        let storage = storage_with_ferris();
+        let limiter = Limiter::new(Limits {
+            max_failures: 2,
+            lockout: Duration::from_secs(60),
+            ..Limits::default()
+        });
+        let send = |request| throttled(&storage, &settings(), &limiter, Some("192.0.2.1"), request);
+        let guess = || {
+            NnntpRequest::Login(LoginRequest {
+                username: "ferris".to_string(),
+                password: "lobster".to_string(),
+            })
+        };
+        let list = |reader: Option<Credentials>| {
+            NnntpRequest::List(ListRequest {
+                reader,
+                ..ListRequest::new("nope")
+            })
+        };
+
+        assert!(matches!(send(guess()), Err(NnntpError::Unauthorized)));
+        // the group not existing is not the password being right
+        assert!(matches!(send(list(None)), Err(NnntpError::NotFound(_))));
+        assert!(matches!(
+            send(list(Some(author("ferris", "lobster").credentials))),
+            Err(NnntpError::Unauthorized)
+        ));
+        assert!(matches!(send(guess()), Err(NnntpError::TooManyRequests(_))));
+    }
+
+    #[test]
+    fn usernames_are_the_registered_ones_whatever_case_they_are_typed_in() {
+        let storage = storage_with_ferris();
+        storage.set_group_moderated("comp.lang.rust", true).unwrap();
+
+        let login = NnntpRequest::Login(LoginRequest {
+            username: "FERRIS".to_string(),
+            password: "crab".to_string(),
+        });
+        let reply = handle(&storage, &settings(), login).unwrap();
+        let session: Session =
+            serde_json::from_value(reply.other.unwrap()[BODY_KEY].clone()).unwrap();
+        assert_eq!(
+            session::user_for(&storage, &session.token).unwrap(),
+            Some("ferris".to_string())
+        );
+
+        // ferris moderates the group, so their post goes straight through
+        let reply = handle(
+            &storage,
+            &settings(),
+            post("comp.lang.rust", author("Ferris", "crab")),
+        )
+        .unwrap();
+        let created: PostCreated =
+            serde_json::from_value(reply.other.unwrap()[BODY_KEY].clone()).unwrap();
+        assert!(!created.pending);
+        assert_eq!(storage.post(created.id).unwrap().unwrap().author, "ferris");
+    }
+
+    #[test]
+    fn accounts_need_the_current_password_to_change() {
+        let storage = storage_with_ferris();
+        let login = |password: &str| {
+            handle(
+                &storage,
+                &settings(),
+                NnntpRequest::Login(LoginRequest {
+                    username: "ferris".to_string(),
+                    password: password.to_string(),
+                }),
+            )
+        };
+        let change_email = |password: &str, email: &str| {
+            handle(
+                &storage,
+                &settings(),
+                NnntpRequest::ChangeEmail(ChangeEmailRequest {
+                    username: "ferris".to_string(),
+                    password: password.to_string(),
+                    email: email.to_string(),
+                }),
+            )
+        };
+
+        assert_eq!(
+            change_email("lobster", "ferris@example.com").err(),
+            Some(NnntpError::Unauthorized)
+        );
+        assert!(matches!(
+            change_email("crab", "ferris <ferris@example.com>"),
+            Err(NnntpError::BadRequest(_))
+        ));
+        change_email("crab", "ferris@example.com").unwrap();
+
+        // the email of the account is used, whatever the request says
+        let mut ferris = author("ferris", "crab");
+        ferris.email = "someone@else.com".to_string();
+        handle(&storage, &settings(), post("comp.lang.rust", ferris)).unwrap();
+        assert_eq!(
+            storage.post(1).unwrap().unwrap().author_email,
+            "ferris@example.com"
+        );
+
+        let reply = login("crab").unwrap();
+        let session: Session =
+            serde_json::from_value(reply.other.unwrap()[BODY_KEY].clone()).unwrap();
+
+        let change_password = NnntpRequest::ChangePassword(ChangePasswordRequest {
+            username: "ferris".to_string(),
+            password: "crab".to_string(),
+            new_password: "lobster pot".to_string(),
+        });
+        handle(&storage, &settings(), change_password).unwrap();
+        assert_eq!(login("crab").err(), Some(NnntpError::Unauthorized));
+        login("lobster pot").unwrap();
+        // sessions started with the old password end with it
+        assert_eq!(session::user_for(&storage, &session.token).unwrap(), None);
+
+        let delete = |password: &str| {
+            handle(
+                &storage,
+                &settings(),
+                NnntpRequest::DeleteAccount(DeleteAccountRequest {
+                    username: "ferris".to_string(),
+                    password: password.to_string(),
+                }),
+            )
+        };
+        assert_eq!(delete("crab").err(), Some(NnntpError::Unauthorized));
+        delete("lobster pot").unwrap();
+        assert_eq!(login("lobster pot").err(), Some(NnntpError::Unauthorized));
+        assert_eq!(storage.post(1).unwrap().unwrap().author, "ferris");
+
+        let register = NnntpRequest::New(NewUserRequest {
+            username: "Ferris".to_string(),
+            password: "crab cakes".to_string(),
+            email: String::new(),
+            invite: None,
+        });
+        assert!(matches!(
+            handle(&storage, &settings(), register),
+            Err(NnntpError::Conflict(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_groups_are_rejected_unless_auto_created() {
+        let storage = storage_with_ferris();
+        let ferris = author("ferris", "crab");
+
+        assert_eq!(
+            handle(
+                &storage,
+                &settings(),
+                post("comp.lang.rsut", ferris.clone())
+            )
+            .err(),
+            Some(group_not_found("comp.lang.rsut"))
+        );
+        assert!(storage.group("comp.lang.rsut").unwrap().is_none());
+
+        let auto_create = Settings {
+            auto_create_groups: true,
+            ..settings()
+        };
+        handle(&storage, &auto_create, post("comp.lang.c", ferris)).unwrap();
+
+        let created = storage.group("comp.lang.c").unwrap().unwrap();
+        assert_eq!(created.creator, "ferris");
+        assert_eq!(created.post_count, 1);
+    }
+
+    #[test]
+    fn groups_need_a_valid_and_unused_name() {
+        let storage = storage_with_ferris();
+
+        let create = |name: &str| {
+            NnntpRequest::CreateGroup(CreateGroupRequest {
+                name: name.to_string(),
+                description: "a group".to_string(),
+                restricted: false,
+                moderated: false,
+                creator: author("ferris", "crab").credentials,
+            })
+        };
+
+        handle(&storage, &settings(), create("comp.lang.c")).unwrap();
+
+        assert!(matches!(
+            handle(&storage, &settings(), create("comp.lang.rust")),
+            Err(NnntpError::Conflict(_))
+        ));
+
+        for name in ["", "comp..rust", "comp.lang.*", "Comp.Lang", "comp lang"] {
+            assert!(matches!(
+                handle(&storage, &settings(), create(name)),
+                Err(NnntpError::BadRequest(_))
+            ));
+        }
+
+        let names: Vec<String> = storage
+            .groups()
+            .unwrap()
+            .into_iter()
+            .map(|group| group.name)
+            .collect();
+        assert_eq!(names, ["comp.lang.c", "comp.lang.rust"]);
+    }
+
+    #[test]
+    fn listing_a_wildmat_returns_the_posts_of_every_matching_group() {
+        let storage = storage_with_ferris();
+        let ferris = author("ferris", "crab");
+
+        for group in ["comp.lang.c", "comp.lang.java", "alt.test"] {
+            storage.insert_group(group, "", 0, "ferris").unwrap();
+        }
+        for group in [
+            "comp.lang.rust",
+            "comp.lang.java",
+            "alt.test",
+            "comp.lang.c",
+        ] {
+            handle(&storage, &settings(), post(group, ferris.clone())).unwrap();
+        }
+
+        let list = |group: &str| {
+            list(&storage, ListRequest::new(group)).map(|page| {
+                page.posts
+                    .into_iter()
+                    .map(|post| post.group_name)
+                    .collect::<Vec<String>>()
+            })
+        };
+
+        assert_eq!(
+            list("comp.*,!comp.lang.java").unwrap(),
+            ["comp.lang.rust", "comp.lang.c"]
+        );
+        assert!(list("sci.*").unwrap().is_empty());
+        assert_eq!(
+            list("comp.lang.go").err(),
+            Some(group_not_found("comp.lang.go"))
+        );
+        assert!(matches!(list("comp.[lang"), Err(NnntpError::BadRequest(_))));
+    }
+
+    #[test]
+    fn listing_pages_through_posts_with_their_comments() {
+        let storage = storage_with_ferris();
+        let ferris = author("ferris", "crab");
+
+        for _ in 0..5 {
+            handle(
+                &storage,
+                &settings(),
+                post("comp.lang.rust", ferris.clone()),
+            )
+            .unwrap();
+        }
+        handle(
+            &storage,
+            &settings(),
+            comment("comp.lang.rust", 2, ferris.clone()),
+        )
+        .unwrap();
+
+        let page = |after_id| ListRequest {
+            limit: Some(2),
+            after_id,
+            ..ListRequest::new("comp.lang.rust")
+        };
+
+        let first = list(&storage, page(None)).unwrap();
+        let ids: Vec<i32> = first.posts.iter().map(|post| post.id).collect();
+        assert_eq!(ids, [1, 2]);
+        assert_eq!(first.next, Some(2));
+        assert!(first.posts[0].comments.is_empty());
+        assert_eq!(first.posts[1].comments.len(), 1);
+        assert_eq!(first.posts[1].comments[0].id, 1);
+        assert!(first.posts[1].comments[0]
+            .message_id
+            .ends_with("@news.example.com>"));
+        assert_ne!(first.posts[0].message_id, first.posts[1].message_id);
+        assert!(first.posts[0].created_at > 0);
+
+        let second = list(&storage, page(first.next)).unwrap();
+        assert_eq!(second.next, Some(4));
+
+        let last = list(&storage, page(second.next)).unwrap();
+        assert_eq!(last.posts.len(), 1);
+        assert_eq!(last.next, None);
+
+        let skipped = ListRequest {
+            offset: Some(3),
+            ..ListRequest::new("comp.lang.rust")
+        };
+        assert_eq!(list(&storage, skipped).unwrap().posts[0].id, 4);
+
+        let future = ListRequest {
+            since: Some(session::now() + 60),
+            ..ListRequest::new("comp.lang.rust")
+        };
+        assert!(list(&storage, future).unwrap().posts.is_empty());
+
+        let empty = ListRequest {
+            limit: Some(0),
+            ..ListRequest::new("comp.lang.rust")
+        };
+        assert!(matches!(
+            list(&storage, empty),
+            Err(NnntpError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn replies_to_comments_carry_the_whole_references_chain() {
+        let storage = storage_with_ferris();
+        let ferris = author("ferris", "crab");
+
+        for _ in 0..2 {
+            handle(
+                &storage,
+                &settings(),
+                post("comp.lang.rust", ferris.clone()),
+            )
+            .unwrap();
+        }
+        let answer = |parent, reply_to| {
+            let request = reply("comp.lang.rust", parent, reply_to, ferris.clone());
+            handle(&storage, &settings(), request)
+        };
+
+        answer(1, None).unwrap();
+        answer(1, Some(1)).unwrap();
+        answer(1, Some(2)).unwrap();
+        answer(2, None).unwrap();
+
+        // replies have to stay under the same post
+        for (parent, reply_to) in [(2, Some(1)), (1, Some(404))] {
+            assert!(matches!(
+                answer(parent, reply_to),
+                Err(NnntpError::NotFound(_))
+            ));
+        }
+
+        let page = list(&storage, ListRequest::new("comp.lang.rust")).unwrap();
+        let post = &page.posts[0];
+        let comments = &post.comments;
+        assert_eq!(comments.len(), 3);
+
+        assert_eq!(comments[0].reply_to, None);
+        assert_eq!(comments[0].references, [post.message_id.as_str()]);
+        assert_eq!(comments[2].reply_to, Some(2));
+        assert_eq!(
+            comments[2].references,
+            [
+                post.message_id.clone(),
+                comments[0].message_id.clone(),
+                comments[1].message_id.clone()
+            ]
+        );
+    }
+
+    #[test]
+    fn only_the_author_can_edit_and_every_edit_is_kept() {
+        let storage = storage_with_ferris();
+        storage
+            .insert_user(&NewUser {
+                username: "corro",
+                hashed_password: &hash("unsafe", TEST_BCRYPT_COST).unwrap(),
+                ..NewUser::default()
+            })
+            .unwrap();
+        let ferris = author("ferris", "crab");
+
+        handle(
+            &storage,
+            &settings(),
+            post("comp.lang.rust", ferris.clone()),
+        )
+        .unwrap();
+        handle(
+            &storage,
+            &settings(),
+            comment("comp.lang.rust", 1, ferris.clone()),
+        )
+        .unwrap();
+
+        let edit = |article, subject: Option<&str>, body: Option<&str>, author: &Author| {
+            let request = NnntpRequest::Edit(EditRequest {
+                article,
+                subject: subject.map(str::to_string),
+                body: body.map(str::to_string),
+                author: author.credentials.clone(),
+            });
+            handle(&storage, &settings(), request)
+        };
+
+        assert!(matches!(
+            edit(
+                Article::Post(1),
+                Some("mine now"),
+                None,
+                &author("corro", "unsafe")
+            ),
+            Err(NnntpError::Forbidden(_))
+        ));
+        assert!(matches!(
+            edit(Article::Comment(1), Some("subject"), None, &ferris),
+            Err(NnntpError::BadRequest(_))
+        ));
+        assert!(matches!(
+            edit(Article::Post(1), None, None, &ferris),
+            Err(NnntpError::BadRequest(_))
+        ));
+        assert!(matches!(
+            edit(Article::Comment(404), None, Some("?"), &ferris),
+            Err(NnntpError::NotFound(_))
+        ));
+
+        edit(Article::Post(1), Some("fixed"), None, &ferris).unwrap();
+        edit(Article::Comment(1), None, Some("fixed too"), &ferris).unwrap();
+
+        let page = list(&storage, ListRequest::new("comp.lang.rust")).unwrap();
+        assert_eq!(page.posts[0].subject, "fixed");
+        assert_eq!(page.posts[0].body, "there");
+        assert!(page.posts[0].edited_at.is_some());
+        assert_eq!(page.posts[0].comments[0].body, "fixed too");
+
+        let history = NnntpRequest::History(HistoryRequest {
+            article: Article::Post(1),
+            reader: None,
+        });
+        let reply = handle(&storage, &settings(), history).unwrap();
+        let revisions: Vec<Revision> =
+            serde_json::from_value(reply.other.unwrap()[BODY_KEY].clone()).unwrap();
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].subject.as_deref(), Some("hi"));
+    }
+
+    #[test]
+    fn authors_and_moderators_cancel_articles_leaving_tombstones() {
+        let storage = storage_with_ferris();
+        for (username, password) in [("corro", "unsafe"), ("root", "toor")] {
+            storage
+                .insert_user(&NewUser {
+                    username,
+                    hashed_password: &hash(password, TEST_BCRYPT_COST).unwrap(),
+                    ..NewUser::default()
+                })
+                .unwrap();
+        }
+        // corro moderates alt.test, and root is an admin
+        storage.insert_group("alt.test", "", 0, "corro").unwrap();
+        storage
+            .grant_role("corro", "moderator", "alt.test")
+            .unwrap();
+        storage.grant_role("root", "admin", "").unwrap();
+        let ferris = author("ferris", "crab");
+
+        handle(
+            &storage,
+            &settings(),
+            post("comp.lang.rust", ferris.clone()),
+        )
+        .unwrap();
+        handle(&storage, &settings(), post("alt.test", ferris.clone())).unwrap();
+        handle(
+            &storage,
+            &settings(),
+            comment("comp.lang.rust", 1, ferris.clone()),
+        )
+        .unwrap();
+
+        let cancel = |article, by: Author| {
+            let request = NnntpRequest::Cancel(CancelRequest {
+                article,
+                author: by.credentials,
+            });
+            handle(&storage, &settings(), request)
+        };
+
+        assert!(matches!(
+            cancel(Article::Post(1), author("corro", "unsafe")),
+            Err(NnntpError::Forbidden(_))
+        ));
+        cancel(Article::Post(2), author("corro", "unsafe")).unwrap();
+        cancel(Article::Comment(1), author("root", "toor")).unwrap();
+        cancel(Article::Post(1), ferris.clone()).unwrap();
+
+        assert!(matches!(
+            cancel(Article::Post(1), ferris.clone()),
+            Err(NnntpError::Conflict(_))
+        ));
+        assert!(matches!(
+            handle(
+                &storage,
+                &settings(),
+                comment("comp.lang.rust", 1, ferris.clone())
+            ),
+            Err(NnntpError::Conflict(_))
+        ));
+
+        let page = list(&storage, ListRequest::new("comp.lang.rust")).unwrap();
+        let tombstone = &page.posts[0];
+        assert!(tombstone.cancelled_at.is_some());
+        assert_eq!(
+            (tombstone.subject.as_str(), tombstone.body.as_str()),
+            ("", "")
+        );
+        assert_eq!(tombstone.author_email, None);
+        assert!(tombstone.comments[0].cancelled_at.is_some());
+        assert_eq!(tombstone.comments[0].body, "");
+
+        assert_eq!(storage.post(1).unwrap().unwrap().body, "there");
+    }
+
+    #[test]
+    fn subjects_cannot_add_headers() {
+        let storage = storage_with_ferris();
+        let ferris = author("ferris", "crab");
+        let post_with = |subject: &str| {
+            NnntpRequest::Post(PostRequest {
+                group: "comp.lang.rust".to_string(),
+                post: PostContent {
+                    subject: subject.to_string(),
+                    body: "there".to_string(),
+                },
+                author: ferris.clone(),
+            })
+        };
+
+        assert!(matches!(
+            handle(&storage, &settings(), post_with("hi\r\nApproved: yes")),
+            Err(NnntpError::BadRequest(_))
+        ));
+        handle(&storage, &settings(), post_with("hi")).unwrap();
+
+        let edit = NnntpRequest::Edit(EditRequest {
+            article: Article::Post(1),
+            subject: Some("x\r\n.\r\n".to_string()),
+            body: None,
+            author: ferris.credentials.clone(),
+        });
+        assert!(matches!(
+            handle(&storage, &settings(), edit),
+            Err(NnntpError::BadRequest(_))
+        ));
+
+        let page = list(&storage, ListRequest::new("comp.lang.rust")).unwrap();
+        assert_eq!(page.posts.len(), 1);
+        assert_eq!(page.posts[0].subject, "hi");
+    }
+
+    #[test]
+    fn roles_decide_who_posts_and_who_grants() {
+        let storage = storage_with_ferris();
+        for (username, password) in [("corro", "unsafe"), ("root", "toor")] {
+            storage
+                .insert_user(&NewUser {
+                    username,
+                    hashed_password: &hash(password, TEST_BCRYPT_COST).unwrap(),
+                    ..NewUser::default()
+                })
+                .unwrap();
+        }
+        storage.grant_role("root", "admin", "").unwrap();
+        let ferris = author("ferris", "crab");
+        let corro = author("corro", "unsafe");
+        let root = author("root", "toor");
+
+        let run = |request| handle(&storage, &settings(), request);
+        let role = |role, group: Option<&str>, by: &Author| RoleRequest {
+            username: "corro".to_string(),
+            role,
+            group: group.map(str::to_string),
+            author: by.credentials.clone(),
+        };
+
+        // the creator of a group moderates it, and can restrict it to its posters
+        run(NnntpRequest::CreateGroup(CreateGroupRequest {
+            name: "local.announce".to_string(),
+            description: String::new(),
+            restricted: true,
+            moderated: false,
+            creator: ferris.credentials.clone(),
+        }))
+        .unwrap();
+        run(post("local.announce", ferris.clone())).unwrap();
+        assert!(matches!(
+            run(post("local.announce", corro.clone())),
+            Err(NnntpError::Forbidden(_))
+        ));
+
+        assert!(matches!(
+            run(NnntpRequest::GrantRole(role(
+                Role::Poster,
+                Some("local.announce"),
+                &ferris
+            ))),
+            Err(NnntpError::Forbidden(_))
+        ));
+        assert!(matches!(
+            run(NnntpRequest::GrantRole(role(
+                Role::Admin,
+                Some("local.announce"),
+                &root
+            ))),
+            Err(NnntpError::BadRequest(_))
+        ));
+        run(NnntpRequest::GrantRole(role(
+            Role::Poster,
+            Some("local.announce"),
+            &root,
+        )))
+        .unwrap();
+        run(post("local.announce", corro.clone())).unwrap();
+
+        // readers can read everywhere but post nowhere
+        run(post("comp.lang.rust", ferris.clone())).unwrap();
+        run(NnntpRequest::GrantRole(role(Role::Reader, None, &root))).unwrap();
+        assert!(matches!(
+            run(post("comp.lang.rust", corro.clone())),
+            Err(NnntpError::Forbidden(_))
+        ));
+        assert!(matches!(
+            run(comment("comp.lang.rust", 3, corro.clone())),
+            Err(NnntpError::Forbidden(_))
+        ));
+        run(NnntpRequest::RevokeRole(role(Role::Reader, None, &root))).unwrap();
+        assert!(matches!(
+            run(NnntpRequest::RevokeRole(role(Role::Reader, None, &root))),
+            Err(NnntpError::NotFound(_))
+        ));
+        run(post("comp.lang.rust", corro.clone())).unwrap();
+
+        // only moderators open a restricted group back up
+        let configure = |by: &Author| {
+            NnntpRequest::ConfigureGroup(ConfigureGroupRequest {
+                name: "comp.lang.rust".to_string(),
+                restricted: Some(true),
+                moderated: None,
+                author: by.credentials.clone(),
+            })
+        };
+        assert!(matches!(
+            run(configure(&corro)),
+            Err(NnntpError::Forbidden(_))
+        ));
+        run(configure(&ferris)).unwrap();
+        assert!(storage.group("comp.lang.rust").unwrap().unwrap().restricted);
+    }
+
+    #[test]
+    fn posts_to_moderated_groups_wait_for_approval() {
+        let storage = storage_with_ferris();
+        storage
+            .insert_user(&NewUser {
+                username: "corro",
+                hashed_password: &hash("unsafe", TEST_BCRYPT_COST).unwrap(),
+                ..NewUser::default()
+            })
+            .unwrap();
+        let ferris = author("ferris", "crab");
+        let corro = author("corro", "unsafe");
+
+        let run = |request| handle(&storage, &settings(), request);
+        let created = |request| -> PostCreated {
+            serde_json::from_value(run(request).unwrap().other.unwrap()[BODY_KEY].clone()).unwrap()
+        };
+        let queue = |by: &Author| -> Vec<HeldPost> {
+            let request = NnntpRequest::ModerationQueue(QueueRequest {
+                group: None,
+                author: by.credentials.clone(),
+            });
+            serde_json::from_value(run(request).unwrap().other.unwrap()[BODY_KEY].clone()).unwrap()
+        };
+
+        run(NnntpRequest::ConfigureGroup(ConfigureGroupRequest {
+            name: "comp.lang.rust".to_string(),
+            restricted: None,
+            moderated: Some(true),
+            author: ferris.credentials.clone(),
+        }))
+        .unwrap();
+
+        // moderators skip their own queue
+        assert!(!created(post("comp.lang.rust", ferris.clone())).pending);
+        let first = created(post("comp.lang.rust", corro.clone()));
+        let second = created(post("comp.lang.rust", corro.clone()));
+        assert!(first.pending && second.pending);
+
+        let listed = list(&storage, ListRequest::new("comp.lang.rust")).unwrap();
+        assert_eq!(listed.posts.len(), 1);
+        assert!(matches!(
+            run(comment("comp.lang.rust", first.id, ferris.clone())),
+            Err(NnntpError::NotFound(_))
+        ));
+
+        let pending: Vec<i32> = queue(&ferris).iter().map(|held| held.post.id).collect();
+        assert_eq!(pending, [first.id, second.id]);
+        assert_eq!(queue(&corro).len(), 2);
+
+        let approve = |id, by: &Author| {
+            run(NnntpRequest::Approve(ApproveRequest {
+                post: id,
+                author: by.credentials.clone(),
+            }))
+        };
+        let reject = |id, reason: &str, by: &Author| {
+            run(NnntpRequest::Reject(RejectRequest {
+                post: id,
+                reason: reason.to_string(),
+                author: by.credentials.clone(),
+            }))
+        };
+
+        assert!(matches!(
+            approve(first.id, &corro),
+            Err(NnntpError::Forbidden(_))
+        ));
+        approve(first.id, &ferris).unwrap();
+        assert!(matches!(
+            approve(first.id, &ferris),
+            Err(NnntpError::Conflict(_))
+        ));
+        assert!(matches!(
+            reject(second.id, " ", &ferris),
+            Err(NnntpError::BadRequest(_))
+        ));
+        reject(second.id, "off topic", &ferris).unwrap();
+
+        let listed = list(&storage, ListRequest::new("comp.lang.rust")).unwrap();
+        assert_eq!(listed.posts.len(), 2);
+        assert_eq!(listed.posts[1].id, first.id);
+
+        // the author hears why, while the moderator's queue is empty again
+        assert!(queue(&ferris).is_empty());
+        let rejected = queue(&corro);
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].post.id, second.id);
+        assert_eq!(rejected[0].rejection.as_deref(), Some("off topic"));
+    }
+
+    #[test]
+    fn searches_split_words_and_quoted_phrases() {
+        assert_eq!(
+            search_terms(r#"borrow "lifetime   elision" checker "open quote"#),
+            ["borrow", "lifetime elision", "checker", "open quote"]
+        );
+        assert!(search_terms(r#"  "" "#).is_empty());
+
+        let storage = storage_with_ferris();
+        storage.insert_group("alt.test", "", 0, "ferris").unwrap();
+        let ferris = author("ferris", "crab");
+        handle(
+            &storage,
+            &settings(),
+            post("comp.lang.rust", ferris.clone()),
+        )
+        .unwrap();
+        handle(&storage, &settings(), post("alt.test", ferris.clone())).unwrap();
+        handle(
+            &storage,
+            &settings(),
+            comment("alt.test", 2, ferris.clone()),
+        )
+        .unwrap();
+
+        let search = |request: SearchRequest| -> Result<Vec<SearchHit>, NnntpError> {
+            let reply = handle(&storage, &settings(), NnntpRequest::Search(request))?;
+
+            Ok(serde_json::from_value(reply.other.unwrap()[BODY_KEY].clone()).unwrap())
+        };
+
+        assert_eq!(search(SearchRequest::new("there")).unwrap().len(), 2);
+        let hits = search(SearchRequest {
+            group: Some("alt.*".to_string()),
+            ..SearchRequest::new("hello")
+        })
+        .unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].article, Article::Comment(1));
+        assert_eq!((hits[0].post_id, hits[0].subject.as_str()), (2, "hi"));
+        assert!(search(SearchRequest {
+            author: Some("corro".to_string()),
+            ..SearchRequest::new("there")
+        })
+        .unwrap()
+        .is_empty());
+        assert!(matches!(
+            search(SearchRequest::new(" ")),
+            Err(NnntpError::BadRequest(_))
+        ));
+        assert!(matches!(
+            search(SearchRequest {
+                group: Some("alt.nothing".to_string()),
+                ..SearchRequest::new("there")
+            }),
+            Err(NnntpError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn subscriptions_track_what_each_user_has_read() {
+        let storage = storage_with_ferris();
+        storage
+            .insert_group("comp.lang.c", "", 0, "ferris")
+            .unwrap();
+        let ferris = author("ferris", "crab");
+        let credentials = ferris.credentials.clone();
+        for group in ["comp.lang.rust", "comp.lang.rust", "comp.lang.c"] {
+            handle(&storage, &settings(), post(group, ferris.clone())).unwrap();
+        }
+        handle(
+            &storage,
+            &settings(),
+            comment("comp.lang.rust", 1, ferris.clone()),
+        )
+        .unwrap();
+
+        let send = |request: NnntpRequest| -> Result<serde_json::Value, NnntpError> {
+            let reply = handle(&storage, &settings(), request)?;
+
+            Ok(reply.other.unwrap()[BODY_KEY].clone())
+        };
+        let subscribe = |group: &str, subscribe: bool| {
+            let request = SubscribeRequest {
+                group: group.to_string(),
+                author: credentials.clone(),
+            };
+            let request = if subscribe {
+                NnntpRequest::Subscribe(request)
+            } else {
+                NnntpRequest::Unsubscribe(request)
+            };
+
+            send(request).map(|value| serde_json::from_value::<Vec<Subscription>>(value).unwrap())
+        };
+        let mark_read = |articles: Vec<Article>, all: bool| {
+            send(NnntpRequest::MarkRead(MarkReadRequest {
+                group: "comp.lang.rust".to_string(),
+                articles,
+                all,
+                author: credentials.clone(),
+            }))
+            .map(|value| serde_json::from_value::<Subscription>(value).unwrap())
+        };
+
+        let subscribed = subscribe("comp.lang.*", true).unwrap();
+        assert_eq!(subscribed.len(), 2);
+        assert_eq!(
+            (subscribed[1].group.as_str(), subscribed[1].unread),
+            ("comp.lang.rust", 3)
+        );
+        assert_eq!(subscribe("comp.lang.c", false).unwrap().len(), 1);
+        assert!(matches!(
+            subscribe("sci.*", true),
+            Err(NnntpError::NotFound(_))
+        ));
+
+        // the post is number 1 and its comment number 3, after the second post
+        let marked = mark_read(vec![Article::Post(1), Article::Comment(1)], false).unwrap();
+        assert_eq!((marked.unread, marked.read.as_str()), (1, "1,3"));
+        assert!(matches!(
+            mark_read(vec![Article::Post(3)], false),
+            Err(NnntpError::NotFound(_))
+        ));
+
+        let reader = |only_unread| ListRequest {
+            reader: Some(credentials.clone()),
+            only_unread,
+            ..ListRequest::new("comp.lang.*")
+        };
+        let page = list(&storage, reader(false)).unwrap();
+        assert_eq!(page.posts.len(), 3);
+        assert_eq!(page.posts[0].read, Some(true));
+        assert_eq!(page.posts[0].comments[0].read, Some(true));
+        assert_eq!(page.posts[1].read, Some(false));
+        assert_eq!(
+            page.unread,
+            [
+                UnreadCount {
+                    group: "comp.lang.c".to_string(),
+                    unread: 1,
+                },
+                UnreadCount {
+                    group: "comp.lang.rust".to_string(),
+                    unread: 1,
+                },
+            ]
+        );
+        let unread: Vec<i32> = list(&storage, reader(true))
+            .unwrap()
+            .posts
+            .iter()
+            .map(|post| post.id)
+            .collect();
+        assert_eq!(unread, [2, 3]);
+        assert_eq!(
+            list(&storage, ListRequest::new("comp.lang.c"))
+                .unwrap()
+                .posts[0]
+                .read,
+            None
+        );
+        assert!(matches!(
+            list(
+                &storage,
+                ListRequest {
+                    only_unread: true,
+                    ..ListRequest::new("comp.lang.c")
+                }
+            ),
+            Err(NnntpError::BadRequest(_))
+        ));
+
+        assert_eq!(mark_read(vec![], true).unwrap().read, "1-3");
+        let subscriptions = send(NnntpRequest::Subscriptions(SubscriptionsRequest {
+            author: credentials.clone(),
+        }))
+        .unwrap();
+        assert_eq!(
+            serde_json::from_value::<Vec<Subscription>>(subscriptions).unwrap(),
+            [Subscription {
+                group: "comp.lang.rust".to_string(),
+                unread: 0,
+                read: "1-3".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn watching_waits_for_articles_in_the_watched_groups() {
+        let storage = storage_with_ferris();
+        storage.insert_group("alt.test", "", 0, "ferris").unwrap();
+        let ferris = author("ferris", "crab");
+        handle(
+            &storage,
+            &settings(),
+            post("comp.lang.rust", ferris.clone()),
+        )
+        .unwrap();
+
+        let watch = |after, timeout| -> Result<WatchResponse, NnntpError> {
+            let request = NnntpRequest::Watch(WatchRequest {
+                group: Some("comp.lang.*".to_string()),
+                reader: None,
+                after,
+                timeout: Some(timeout),
+            });
+            let reply = handle(&storage, &settings(), request)?;
+
+            Ok(serde_json::from_value(reply.other.unwrap()[BODY_KEY].clone()).unwrap())
+        };
+
+        let earlier = watch(Some(0), 0).unwrap();
+        assert!(matches!(earlier.articles[..], [NewArticle::Post(_)]));
+        assert_eq!(earlier.next, 1);
+        let nothing_yet = watch(None, 0).unwrap();
+        assert!(nothing_yet.articles.is_empty());
+        assert_eq!(nothing_yet.next, 1);
+
+        let arrived = std::thread::scope(|scope| {
+            let watcher = scope.spawn(|| watch(Some(1), 60));
+
+            handle(&storage, &settings(), post("alt.test", ferris.clone())).unwrap();
+            handle(
+                &storage,
+                &settings(),
+                comment("comp.lang.rust", 1, ferris.clone()),
+            )
+            .unwrap();
+
+            watcher.join().unwrap().unwrap()
+        });
+        match &arrived.articles[..] {
+            [NewArticle::Comment {
+                group_name,
+                post_id,
+                comment,
+            }] => {
+                assert_eq!((group_name.as_str(), *post_id), ("comp.lang.rust", 1));
+                assert_eq!(comment.body, "hello");
+            }
+            other => panic!("watched {:?}", other),
+        }
+        assert_eq!(arrived.next, 3);
+
+        assert!(matches!(
+            handle(
+                &storage,
+                &settings(),
+                NnntpRequest::Watch(WatchRequest {
+                    group: None,
+                    reader: None,
+                    after: None,
+                    timeout: None,
+                })
+            ),
+            Err(NnntpError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn registering_can_need_an_invite_or_an_approval() {
+        let storage = storage_with_ferris();
+        storage.grant_role("ferris", "admin", "").unwrap();
+        let ferris = author("ferris", "crab").credentials;
+        let with = |registration| Settings {
+            registration,
+            ..settings()
+        };
+        let register = |settings: &Settings, username: &str, invite: Option<&str>| {
+            let request = NnntpRequest::New(NewUserRequest {
+                username: username.to_string(),
+                password: "correct horse".to_string(),
+                email: String::new(),
+                invite: invite.map(str::to_string),
+            });
+            let reply = handle(&storage, settings, request)?;
+            let created: UserCreated =
+                serde_json::from_value(reply.other.unwrap()[BODY_KEY].clone()).unwrap();
+
+            Ok::<_, NnntpError>(created.pending)
+        };
+
+        let invites = with(Registration::Invite);
+        assert!(matches!(
+            register(&invites, "corro", None),
+            Err(NnntpError::Forbidden(_))
+        ));
+
+        let create = NnntpRequest::CreateInvites(CreateInvitesRequest {
+            count: Some(2),
+            author: ferris.clone(),
+        });
+        let reply = handle(&storage, &invites, create).unwrap();
+        let created: InvitesResponse =
+            serde_json::from_value(reply.other.unwrap()[BODY_KEY].clone()).unwrap();
+        assert_eq!(created.len(), 2);
+
+        // an invite that did not get anyone in can still be used
+        assert!(matches!(
+            register(&invites, "Ferris", Some(&created[0].code)),
+            Err(NnntpError::Conflict(_))
+        ));
+        assert!(!register(&invites, "corro", Some(&created[0].code)).unwrap());
+        assert!(matches!(
+            register(&invites, "bors", Some(&created[0].code)),
+            Err(NnntpError::Forbidden(_))
+        ));
+
+        let list = NnntpRequest::Invites(InvitesRequest {
+            author: ferris.clone(),
+        });
+        let reply = handle(&storage, &invites, list).unwrap();
+        let listed: InvitesResponse =
+            serde_json::from_value(reply.other.unwrap()[BODY_KEY].clone()).unwrap();
+        let used: Vec<_> = listed.iter().filter_map(|i| i.used_by.as_deref()).collect();
+        assert_eq!(used, ["corro"]);
+
+        // only admins handle invites
+        let corro = author("corro", "correct horse").credentials;
+        let create = NnntpRequest::CreateInvites(CreateInvitesRequest {
+            count: None,
+            author: corro,
+        });
+        assert!(matches!(
+            handle(&storage, &invites, create),
+            Err(NnntpError::Forbidden(_))
+        ));
+
+        let approval = with(Registration::Approval);
+        assert!(register(&approval, "bors", None).unwrap());
+        assert!(matches!(
+            verify_user(&storage, "bors", "correct horse"),
+            Err(NnntpError::Forbidden(_))
+        ));
+        // a wrong password does not give away that the account is waiting
+        assert_eq!(verify_user(&storage, "bors", "lobster"), Ok(false));
+
+        let pending = NnntpRequest::PendingUsers(PendingUsersRequest {
+            author: ferris.clone(),
+        });
+        let reply = handle(&storage, &approval, pending).unwrap();
+        let waiting: PendingUsersResponse =
+            serde_json::from_value(reply.other.unwrap()[BODY_KEY].clone()).unwrap();
+        assert_eq!(waiting, ["bors"]);
+
+        let approve = |username: &str| {
+            handle(
+                &storage,
+                &approval,
+                NnntpRequest::ApproveUser(ApproveUserRequest {
+                    username: username.to_string(),
+                    author: ferris.clone(),
+                }),
+            )
+        };
+        approve("bors").unwrap();
+        assert!(matches!(approve("bors"), Err(NnntpError::NotFound(_))));
+        assert_eq!(verify_user(&storage, "bors", "correct horse"), Ok(true));
+    }
+
+    #[test]
+    fn registrations_failing_in_the_database_do_not_use_up_their_invite() {
+        let path = std::env::temp_dir().join(format!("nnntp-invite-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let storage = SqliteStorage::open(&path, None).unwrap();
+        storage.insert_invite("welcome", "ferris", 1).unwrap();
+        let settings = Settings {
+            registration: Registration::Invite,
+            ..settings()
+        };
+        let register = || {
+            let request = NnntpRequest::New(NewUserRequest {
+                username: "corro".to_string(),
+                password: "correct horse".to_string(),
+                email: "corro@example.com".to_string(),
+                invite: Some("welcome".to_string()),
+            });
+            handle(&storage, &settings, request)
+        };
+
+        // another connection makes creating the user fail after the invite was taken
+        let other = rusqlite::Connection::open(&path).unwrap();
+        other
+            .execute_batch(
+                "CREATE TRIGGER full BEFORE INSERT ON users
+                    BEGIN SELECT RAISE(ABORT, 'database or disk is full'); END;",
+            )
+            .unwrap();
+        assert!(matches!(register(), Err(NnntpError::Database(_))));
+        assert_eq!(storage.invites().unwrap()[0].used_by, None);
+
+        other.execute_batch("DROP TRIGGER full;").unwrap();
+        assert!(register().is_ok());
+        assert_eq!(
+            storage.invites().unwrap()[0].used_by.as_deref(),
+            Some("corro")
+        );
+        assert_eq!(
+            storage.email_of("corro").unwrap().as_deref(),
+            Some("corro@example.com")
+        );
+
+        drop(storage);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn only_authors_and_moderators_see_the_history_of_held_back_posts() {
+        let storage = storage_with_ferris();
+        for username in ["corro", "bors"] {
+            storage
+                .insert_user(&NewUser {
+                    username,
+                    hashed_password: &hash("crab", TEST_BCRYPT_COST).unwrap(),
+                    ..NewUser::default()
+                })
+                .unwrap();
+        }
+        storage.set_group_moderated("comp.lang.rust", true).unwrap();
+
+        handle(
+            &storage,
+            &settings(),
+            post("comp.lang.rust", author("corro", "crab")),
+        )
+        .unwrap();
+        assert!(!storage.post(1).unwrap().unwrap().is_listed());
+
+        let history = |reader: Option<&str>| {
+            let request = NnntpRequest::History(HistoryRequest {
+                article: Article::Post(1),
+                reader: reader.map(|username| author(username, "crab").credentials),
+            });
+
+            handle(&storage, &settings(), request)
+        };
+
+        for reader in [None, Some("bors")] {
+            assert!(matches!(history(reader), Err(NnntpError::NotFound(_))));
+        }
+        for reader in ["corro", "ferris"] {
+            history(Some(reader)).unwrap();
+        }
+    }
+}
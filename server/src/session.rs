@@ -0,0 +1,83 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::NnntpError;
+use crate::storage::Storage;
+
+/// seconds since the unix epoch
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+/// a fresh, unguessable token for sessions and invites: 32 random bytes, hex encoded
+pub fn new_token() -> Result<String, NnntpError> {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes).map_err(|e| NnntpError::Internal(e.to_string()))?;
+
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// starts a session for an already verified user, returning the token and when it expires
+pub fn start(storage: &dyn Storage, username: &str, ttl: u64) -> Result<(String, i64), NnntpError> {
+    let token = new_token()?;
+    let expires_at = now() + ttl as i64;
+
+    storage.insert_session(&token, username, expires_at)?;
+
+    Ok((token, expires_at))
+}
+
+/// the user a token belongs to, if the session exists and has not expired
+pub fn user_for(storage: &dyn Storage, token: &str) -> Result<Option<String>, NnntpError> {
+    Ok(storage.session_user(token, now())?)
+}
+
+/// revokes a single session, or every session of its user when `all` is set
+pub fn end(storage: &dyn Storage, token: &str, all: bool) -> Result<(), NnntpError> {
+    match storage.session_user(token, now())? {
+        Some(username) if all => storage.delete_sessions_of(&username)?,
+        _ => storage.delete_session(token)?,
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[test]
+    fn sessions_can_be_ended_one_at_a_time_or_all_at_once() {
+        let storage = MemoryStorage::new();
+
+        let (first, _) = start(&storage, "ferris", 60).unwrap();
+        let (second, _) = start(&storage, "ferris", 60).unwrap();
+        let (third, _) = start(&storage, "ferris", 60).unwrap();
+        assert_ne!(first, second);
+
+        assert_eq!(
+            user_for(&storage, &first).unwrap(),
+            Some("ferris".to_string())
+        );
+
+        end(&storage, &first, false).unwrap();
+        assert_eq!(user_for(&storage, &first).unwrap(), None);
+        assert!(user_for(&storage, &second).unwrap().is_some());
+
+        end(&storage, &second, true).unwrap();
+        assert_eq!(user_for(&storage, &third).unwrap(), None);
+    }
+
+    #[test]
+    fn expired_sessions_are_rejected() {
+        let storage = MemoryStorage::new();
+
+        storage
+            .insert_session("stale", "ferris", now() - 1)
+            .unwrap();
+
+        assert_eq!(user_for(&storage, "stale").unwrap(), None);
+    }
+}
@@ -0,0 +1,80 @@
+use std::sync::{Arc, RwLock};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::peering::Peer;
+use crate::policy::Policy;
+
+/// who can register with `/new`
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Registration {
+    /// anyone
+    Open,
+    /// only someone with a code from an admin, each code letting in a single user
+    Invite,
+    /// anyone, but their account waits for an admin to approve it before they can log in
+    Approval,
+}
+
+/// the lowest cost bcrypt allows, to keep the tests fast
+#[cfg(test)]
+pub const TEST_BCRYPT_COST: u32 = 4;
+
+/// runtime options that route handlers need, taken from the config file and the command line.
+/// they are replaced as a whole when the server reloads its config
+pub struct Settings {
+    /// how long a session token stays valid, in seconds
+    pub session_ttl: u64,
+    /// how much work bcrypt puts into hashing new passwords, from 4 to 31
+    pub bcrypt_cost: u32,
+    /// whether posting to a group that does not exist creates it
+    pub auto_create_groups: bool,
+    /// the right hand side of the message ids of new articles
+    pub domain: String,
+    /// the servers articles are exchanged with
+    pub peers: Vec<Peer>,
+    /// what usernames and passwords of new users have to look like
+    pub policy: Policy,
+    pub registration: Registration,
+    /// whether every jsontp request is logged, with who sent it and how it went
+    pub log_requests: bool,
+    /// how many reverse proxies in front of the server add to `X-Forwarded-For`, see
+    /// [`crate::limits`]
+    pub trusted_proxies: usize,
+}
+
+static SETTINGS: RwLock<Option<Arc<Settings>>> = RwLock::new(None);
+
+/// installs the settings every route handler reads, this can only be done once
+pub fn init(settings: Settings) {
+    let mut current = SETTINGS.write().unwrap_or_else(|e| e.into_inner());
+
+    if current.is_some() {
+        panic!("settings have already been initialised");
+    }
+
+    *current = Some(Arc::new(settings));
+}
+
+/// replaces the settings, for the requests that come in from now on
+pub fn reload(settings: Settings) {
+    let mut current = SETTINGS.write().unwrap_or_else(|e| e.into_inner());
+
+    if current.is_none() {
+        panic!("settings have not been initialised");
+    }
+
+    *current = Some(Arc::new(settings));
+}
+
+/// the settings shared by every route handler. a request holds on to them until it is done, so
+/// a reload never changes them halfway through one
+pub fn settings() -> Arc<Settings> {
+    SETTINGS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .expect("settings have not been initialised")
+}
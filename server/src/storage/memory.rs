@@ -0,0 +1,900 @@
+use std::sync::{Mutex, MutexGuard};
+
+use super::{
+    ArticleId, CommentRow, GroupRow, InviteRow, NewComment, NewPost, NewUser, NewsrcRow, PostQuery,
+    PostRow, Redeemed, RevisionRow, RoleRow, SearchQuery, SearchRow, Storage, StorageResult,
+};
+
+struct User {
+    username: String,
+    hashed_password: String,
+    email: String,
+    deleted_at: Option<i64>,
+    pending: bool,
+}
+
+#[derive(Default)]
+struct Data {
+    users: Vec<User>,
+    // (token, username, expires at)
+    sessions: Vec<(String, String, i64)>,
+    // post counts are worked out when the groups are read
+    groups: Vec<GroupRow>,
+    posts: Vec<PostRow>,
+    comments: Vec<CommentRow>,
+    revisions: Vec<(ArticleId, RevisionRow)>,
+    // (username, role)
+    roles: Vec<(String, RoleRow)>,
+    // (group, number, article), in the order they were numbered, which is also the feed to peers
+    numbers: Vec<(String, i64, ArticleId)>,
+    // (peer, place in the feed)
+    progress: Vec<(String, i64)>,
+    // (username, line)
+    newsrc: Vec<(String, NewsrcRow)>,
+    invites: Vec<InviteRow>,
+}
+
+/// keeps everything in memory, for tests and throwaway servers. nothing survives a restart
+#[derive(Default)]
+pub struct MemoryStorage {
+    data: Mutex<Data>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+
+    fn data(&self) -> MutexGuard<'_, Data> {
+        self.data.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Data {
+    /// adds `user`, unless the username is taken whatever its case
+    fn insert_user(&mut self, user: &NewUser) -> bool {
+        if self
+            .users
+            .iter()
+            .any(|taken| taken.username.eq_ignore_ascii_case(user.username))
+        {
+            return false;
+        }
+
+        self.users.push(User {
+            username: user.username.to_string(),
+            hashed_password: user.hashed_password.to_string(),
+            email: user.email.to_string(),
+            deleted_at: None,
+            pending: user.pending,
+        });
+
+        true
+    }
+
+    /// gives an article that just became listed the next number in `group`
+    fn number(&mut self, group: &str, article: ArticleId) {
+        let last = self
+            .numbers
+            .iter()
+            .filter(|(numbered, _, _)| numbered == group)
+            .map(|(_, number, _)| *number)
+            .max()
+            .unwrap_or(0);
+
+        self.numbers.push((group.to_string(), last + 1, article));
+    }
+
+    fn with_post_count(&self, group: &GroupRow) -> GroupRow {
+        GroupRow {
+            post_count: self
+                .posts
+                .iter()
+                .filter(|post| post.group_name == group.name && post.is_listed())
+                .count() as i64,
+            ..group.clone()
+        }
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn registered_username(&self, username: &str) -> StorageResult<Option<String>> {
+        Ok(self
+            .data()
+            .users
+            .iter()
+            .find(|user| user.username.eq_ignore_ascii_case(username))
+            .map(|user| user.username.clone()))
+    }
+
+    fn insert_user(&self, user: &NewUser) -> StorageResult<bool> {
+        Ok(self.data().insert_user(user))
+    }
+
+    fn is_pending(&self, username: &str) -> StorageResult<bool> {
+        Ok(self
+            .data()
+            .users
+            .iter()
+            .any(|user| user.username.eq_ignore_ascii_case(username) && user.pending))
+    }
+
+    fn pending_users(&self) -> StorageResult<Vec<String>> {
+        let mut pending: Vec<String> = self
+            .data()
+            .users
+            .iter()
+            .filter(|user| user.pending && user.deleted_at.is_none())
+            .map(|user| user.username.clone())
+            .collect();
+        pending.sort();
+
+        Ok(pending)
+    }
+
+    fn approve_user(&self, username: &str) -> StorageResult<bool> {
+        let mut data = self.data();
+
+        match data
+            .users
+            .iter_mut()
+            .find(|user| user.username.eq_ignore_ascii_case(username) && user.pending)
+        {
+            Some(user) => {
+                user.pending = false;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn insert_invite(&self, code: &str, created_by: &str, created_at: i64) -> StorageResult<()> {
+        self.data().invites.push(InviteRow {
+            code: code.to_string(),
+            created_by: created_by.to_string(),
+            created_at,
+            used_by: None,
+            used_at: None,
+        });
+
+        Ok(())
+    }
+
+    fn invites(&self) -> StorageResult<Vec<InviteRow>> {
+        // newest first, and of those made at once the last one first, like the database
+        let mut invites: Vec<InviteRow> = self.data().invites.iter().rev().cloned().collect();
+        invites.sort_by_key(|invite| std::cmp::Reverse(invite.created_at));
+
+        Ok(invites)
+    }
+
+    fn redeem_invite(&self, code: &str, used_at: i64, user: &NewUser) -> StorageResult<Redeemed> {
+        let mut data = self.data();
+
+        let invite = match data
+            .invites
+            .iter()
+            .position(|invite| invite.code == code && invite.used_by.is_none())
+        {
+            Some(invite) => invite,
+            None => return Ok(Redeemed::InvalidInvite),
+        };
+        if !data.insert_user(user) {
+            return Ok(Redeemed::UsernameTaken);
+        }
+
+        data.invites[invite].used_by = Some(user.username.to_string());
+        data.invites[invite].used_at = Some(used_at);
+
+        Ok(Redeemed::Registered)
+    }
+
+    fn password_hash(&self, username: &str) -> StorageResult<Option<String>> {
+        Ok(self
+            .data()
+            .users
+            .iter()
+            .find(|user| user.username.eq_ignore_ascii_case(username) && user.deleted_at.is_none())
+            .map(|user| user.hashed_password.clone()))
+    }
+
+    fn set_password_hash(&self, username: &str, hashed_password: &str) -> StorageResult<()> {
+        for user in self.data().users.iter_mut() {
+            if user.username.eq_ignore_ascii_case(username) {
+                user.hashed_password = hashed_password.to_string();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn email_of(&self, username: &str) -> StorageResult<Option<String>> {
+        Ok(self
+            .data()
+            .users
+            .iter()
+            .find(|user| user.username.eq_ignore_ascii_case(username) && user.deleted_at.is_none())
+            .map(|user| user.email.clone()))
+    }
+
+    fn set_email(&self, username: &str, email: &str) -> StorageResult<()> {
+        for user in self.data().users.iter_mut() {
+            if user.username.eq_ignore_ascii_case(username) {
+                user.email = email.to_string();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn delete_user(&self, username: &str, deleted_at: i64) -> StorageResult<()> {
+        // the other tables have the username as it was registered
+        let username = match self.registered_username(username)? {
+            Some(username) => username,
+            None => return Ok(()),
+        };
+        let mut data = self.data();
+
+        for user in data.users.iter_mut() {
+            if user.username == username {
+                user.hashed_password.clear();
+                user.email.clear();
+                user.deleted_at = Some(deleted_at);
+            }
+        }
+        data.sessions.retain(|(_, u, _)| *u != username);
+        data.roles.retain(|(u, _)| *u != username);
+        data.newsrc.retain(|(u, _)| *u != username);
+
+        Ok(())
+    }
+
+    fn insert_session(&self, token: &str, username: &str, expires_at: i64) -> StorageResult<()> {
+        self.data()
+            .sessions
+            .push((token.to_string(), username.to_string(), expires_at));
+
+        Ok(())
+    }
+
+    fn session_user(&self, token: &str, now: i64) -> StorageResult<Option<String>> {
+        Ok(self
+            .data()
+            .sessions
+            .iter()
+            .find(|(t, _, expires_at)| t == token && *expires_at > now)
+            .map(|(_, username, _)| username.clone()))
+    }
+
+    fn delete_session(&self, token: &str) -> StorageResult<()> {
+        self.data().sessions.retain(|(t, _, _)| t != token);
+
+        Ok(())
+    }
+
+    fn delete_sessions_of(&self, username: &str) -> StorageResult<()> {
+        self.data().sessions.retain(|(_, u, _)| u != username);
+
+        Ok(())
+    }
+
+    fn insert_group(
+        &self,
+        name: &str,
+        description: &str,
+        created_at: i64,
+        creator: &str,
+    ) -> StorageResult<bool> {
+        let mut data = self.data();
+
+        if data.groups.iter().any(|group| group.name == name) {
+            return Ok(false);
+        }
+
+        data.groups.push(GroupRow {
+            name: name.to_string(),
+            description: description.to_string(),
+            created_at,
+            creator: creator.to_string(),
+            post_count: 0,
+            restricted: false,
+            moderated: false,
+        });
+
+        Ok(true)
+    }
+
+    fn group(&self, name: &str) -> StorageResult<Option<GroupRow>> {
+        let data = self.data();
+
+        Ok(data
+            .groups
+            .iter()
+            .find(|group| group.name == name)
+            .map(|group| data.with_post_count(group)))
+    }
+
+    fn groups(&self) -> StorageResult<Vec<GroupRow>> {
+        let data = self.data();
+
+        let mut groups: Vec<GroupRow> = data
+            .groups
+            .iter()
+            .map(|group| data.with_post_count(group))
+            .collect();
+        groups.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(groups)
+    }
+
+    fn set_group_restricted(&self, name: &str, restricted: bool) -> StorageResult<()> {
+        if let Some(group) = self
+            .data()
+            .groups
+            .iter_mut()
+            .find(|group| group.name == name)
+        {
+            group.restricted = restricted;
+        }
+
+        Ok(())
+    }
+
+    fn set_group_moderated(&self, name: &str, moderated: bool) -> StorageResult<()> {
+        if let Some(group) = self
+            .data()
+            .groups
+            .iter_mut()
+            .find(|group| group.name == name)
+        {
+            group.moderated = moderated;
+        }
+
+        Ok(())
+    }
+
+    fn grant_role(&self, username: &str, role: &str, group: &str) -> StorageResult<bool> {
+        let mut data = self.data();
+        let granted = (
+            username.to_string(),
+            RoleRow {
+                role: role.to_string(),
+                group: group.to_string(),
+            },
+        );
+
+        if data.roles.contains(&granted) {
+            return Ok(false);
+        }
+
+        data.roles.push(granted);
+
+        Ok(true)
+    }
+
+    fn revoke_role(&self, username: &str, role: &str, group: &str) -> StorageResult<bool> {
+        let mut data = self.data();
+        let before = data.roles.len();
+
+        data.roles
+            .retain(|(user, held)| !(user == username && held.role == role && held.group == group));
+
+        Ok(data.roles.len() < before)
+    }
+
+    fn roles_of(&self, username: &str) -> StorageResult<Vec<RoleRow>> {
+        let mut roles: Vec<RoleRow> = self
+            .data()
+            .roles
+            .iter()
+            .filter(|(user, _)| user == username)
+            .map(|(_, role)| role.clone())
+            .collect();
+        roles.sort_by(|a, b| (&a.role, &a.group).cmp(&(&b.role, &b.group)));
+
+        Ok(roles)
+    }
+
+    fn insert_post(&self, post: &NewPost) -> StorageResult<i32> {
+        let mut data = self.data();
+        let id = data.posts.last().map_or(1, |post| post.id + 1);
+
+        data.posts.push(PostRow {
+            id,
+            group_name: post.group.to_string(),
+            subject: post.subject.to_string(),
+            body: post.body.to_string(),
+            author: post.author.to_string(),
+            author_email: post.author_email.to_string(),
+            created_at: post.created_at,
+            message_id: post.message_id.to_string(),
+            path: post.path.to_string(),
+            edited_at: None,
+            cancelled_at: None,
+            cancelled_by: None,
+            pending: post.pending,
+            rejection: None,
+        });
+        if !post.pending {
+            data.number(post.group, ArticleId::Post(id));
+        }
+
+        Ok(id)
+    }
+
+    fn insert_comment(&self, comment: &NewComment) -> StorageResult<i32> {
+        let mut data = self.data();
+        let id = data.comments.last().map_or(1, |comment| comment.id + 1);
+
+        data.comments.push(CommentRow {
+            id,
+            parent_id: comment.parent_id,
+            reply_to: comment.reply_to,
+            references: comment.references.to_vec(),
+            body: comment.body.to_string(),
+            author: comment.author.to_string(),
+            author_email: comment.author_email.to_string(),
+            created_at: comment.created_at,
+            message_id: comment.message_id.to_string(),
+            path: comment.path.to_string(),
+            edited_at: None,
+            cancelled_at: None,
+            cancelled_by: None,
+        });
+
+        let group = data
+            .posts
+            .iter()
+            .find(|post| post.id == comment.parent_id)
+            .map(|post| post.group_name.clone());
+        if let Some(group) = group {
+            data.number(&group, ArticleId::Comment(id));
+        }
+
+        Ok(id)
+    }
+
+    fn comment(&self, id: i32) -> StorageResult<Option<CommentRow>> {
+        Ok(self
+            .data()
+            .comments
+            .iter()
+            .find(|comment| comment.id == id)
+            .cloned())
+    }
+
+    fn edit_article(
+        &self,
+        article: ArticleId,
+        subject: Option<&str>,
+        body: Option<&str>,
+        edited_at: i64,
+    ) -> StorageResult<()> {
+        let mut data = self.data();
+
+        let revision = match article {
+            ArticleId::Post(id) => match data.posts.iter_mut().find(|post| post.id == id) {
+                Some(post) => {
+                    let revision = RevisionRow {
+                        subject: Some(post.subject.clone()),
+                        body: post.body.clone(),
+                        replaced_at: edited_at,
+                    };
+                    if let Some(subject) = subject {
+                        post.subject = subject.to_string();
+                    }
+                    if let Some(body) = body {
+                        post.body = body.to_string();
+                    }
+                    post.edited_at = Some(edited_at);
+                    revision
+                }
+                None => return Ok(()),
+            },
+            ArticleId::Comment(id) => {
+                match data.comments.iter_mut().find(|comment| comment.id == id) {
+                    Some(comment) => {
+                        let revision = RevisionRow {
+                            subject: None,
+                            body: comment.body.clone(),
+                            replaced_at: edited_at,
+                        };
+                        if let Some(body) = body {
+                            comment.body = body.to_string();
+                        }
+                        comment.edited_at = Some(edited_at);
+                        revision
+                    }
+                    None => return Ok(()),
+                }
+            }
+        };
+
+        data.revisions.push((article, revision));
+
+        Ok(())
+    }
+
+    fn revisions(&self, article: ArticleId) -> StorageResult<Vec<RevisionRow>> {
+        Ok(self
+            .data()
+            .revisions
+            .iter()
+            .filter(|(of, _)| *of == article)
+            .map(|(_, revision)| revision.clone())
+            .collect())
+    }
+
+    fn cancel_article(&self, article: ArticleId, by: &str, at: i64) -> StorageResult<()> {
+        let mut data = self.data();
+
+        let (cancelled_at, cancelled_by) = match article {
+            ArticleId::Post(id) => match data.posts.iter_mut().find(|post| post.id == id) {
+                Some(post) => (&mut post.cancelled_at, &mut post.cancelled_by),
+                None => return Ok(()),
+            },
+            ArticleId::Comment(id) => {
+                match data.comments.iter_mut().find(|comment| comment.id == id) {
+                    Some(comment) => (&mut comment.cancelled_at, &mut comment.cancelled_by),
+                    None => return Ok(()),
+                }
+            }
+        };
+
+        *cancelled_at = Some(at);
+        *cancelled_by = Some(by.to_string());
+
+        Ok(())
+    }
+
+    fn purge_cancelled(&self) -> StorageResult<usize> {
+        let mut data = self.data();
+        let data = &mut *data;
+        let mut purged = vec![];
+
+        // articles that were purged before have no author left
+        for post in data.posts.iter_mut() {
+            if post.cancelled_at.is_some() && !post.author.is_empty() {
+                post.subject.clear();
+                post.body.clear();
+                post.author.clear();
+                post.author_email.clear();
+                purged.push(ArticleId::Post(post.id));
+            }
+        }
+
+        for comment in data.comments.iter_mut() {
+            if comment.cancelled_at.is_some() && !comment.author.is_empty() {
+                comment.body.clear();
+                comment.author.clear();
+                comment.author_email.clear();
+                purged.push(ArticleId::Comment(comment.id));
+            }
+        }
+
+        data.revisions
+            .retain(|(article, _)| !purged.contains(article));
+
+        Ok(purged.len())
+    }
+
+    fn post(&self, id: i32) -> StorageResult<Option<PostRow>> {
+        Ok(self.data().posts.iter().find(|post| post.id == id).cloned())
+    }
+
+    fn approve_post(&self, id: i32) -> StorageResult<()> {
+        let mut data = self.data();
+
+        if let Some(post) = data.posts.iter_mut().find(|post| post.id == id) {
+            post.pending = false;
+            let group = post.group_name.clone();
+            data.number(&group, ArticleId::Post(id));
+        }
+
+        Ok(())
+    }
+
+    fn reject_post(&self, id: i32, reason: &str) -> StorageResult<()> {
+        if let Some(post) = self.data().posts.iter_mut().find(|post| post.id == id) {
+            post.pending = false;
+            post.rejection = Some(reason.to_string());
+        }
+
+        Ok(())
+    }
+
+    fn pending_posts(&self, groups: &[String]) -> StorageResult<Vec<PostRow>> {
+        Ok(self
+            .data()
+            .posts
+            .iter()
+            .filter(|post| post.pending && groups.contains(&post.group_name))
+            .cloned()
+            .collect())
+    }
+
+    fn held_posts_of(&self, author: &str) -> StorageResult<Vec<PostRow>> {
+        Ok(self
+            .data()
+            .posts
+            .iter()
+            .filter(|post| post.author == author && !post.is_listed())
+            .cloned()
+            .collect())
+    }
+
+    fn posts_in_groups(&self, groups: &[String], query: &PostQuery) -> StorageResult<Vec<PostRow>> {
+        Ok(self
+            .data()
+            .posts
+            .iter()
+            .filter(|post| groups.contains(&post.group_name) && post.is_listed())
+            .filter(|post| query.after_id.is_none_or(|after_id| post.id > after_id))
+            .filter(|post| query.since.is_none_or(|since| post.created_at >= since))
+            .skip(query.offset as usize)
+            .take(query.limit.map_or(usize::MAX, |limit| limit as usize))
+            .cloned()
+            .collect())
+    }
+
+    fn comments_on(&self, parent_ids: &[i32]) -> StorageResult<Vec<CommentRow>> {
+        Ok(self
+            .data()
+            .comments
+            .iter()
+            .filter(|comment| parent_ids.contains(&comment.parent_id))
+            .cloned()
+            .collect())
+    }
+
+    fn search(&self, query: &SearchQuery) -> StorageResult<Vec<SearchRow>> {
+        let data = self.data();
+
+        let wanted = |group: &str, author: &str, created_at: i64| {
+            query
+                .groups
+                .as_ref()
+                .is_none_or(|groups| groups.iter().any(|g| g == group))
+                && query.author.as_ref().is_none_or(|a| a == author)
+                && query.since.is_none_or(|since| created_at >= since)
+                && query.until.is_none_or(|until| created_at < until)
+        };
+
+        // (matches, row), ranked by how often the terms turn up
+        let mut hits: Vec<(usize, SearchRow)> = vec![];
+
+        for post in data.posts.iter() {
+            if !post.is_listed() {
+                continue;
+            }
+            // comments stay searchable under a cancelled post, which has no subject left
+            let cancelled = post.cancelled_at.is_some();
+
+            let hit = |article, author: &str, created_at, fields: &[&str]| {
+                if !wanted(&post.group_name, author, created_at) {
+                    return None;
+                }
+                let (matches, snippet) = highlight(fields, &query.terms)?;
+
+                Some((
+                    matches,
+                    SearchRow {
+                        article,
+                        post_id: post.id,
+                        group_name: post.group_name.clone(),
+                        subject: if cancelled {
+                            String::new()
+                        } else {
+                            post.subject.clone()
+                        },
+                        author: author.to_string(),
+                        created_at,
+                        snippet,
+                    },
+                ))
+            };
+
+            if !cancelled {
+                hits.extend(hit(
+                    ArticleId::Post(post.id),
+                    &post.author,
+                    post.created_at,
+                    &[&post.subject, &post.body],
+                ));
+            }
+
+            for comment in data.comments.iter() {
+                if comment.parent_id == post.id && comment.cancelled_at.is_none() {
+                    hits.extend(hit(
+                        ArticleId::Comment(comment.id),
+                        &comment.author,
+                        comment.created_at,
+                        &[&comment.body],
+                    ));
+                }
+            }
+        }
+
+        hits.sort_by_key(|(matches, _)| std::cmp::Reverse(*matches));
+
+        Ok(hits
+            .into_iter()
+            .take(query.limit as usize)
+            .map(|(_, row)| row)
+            .collect())
+    }
+
+    fn article_numbers(
+        &self,
+        group: &str,
+        from: i64,
+        to: i64,
+    ) -> StorageResult<Vec<(i64, ArticleId)>> {
+        Ok(self
+            .data()
+            .numbers
+            .iter()
+            .filter(|(numbered, number, _)| numbered == group && (from..=to).contains(number))
+            .map(|(_, number, article)| (*number, *article))
+            .collect())
+    }
+
+    fn count_articles(
+        &self,
+        group: &str,
+        from: i64,
+        to: i64,
+    ) -> StorageResult<(i64, Option<(i64, i64)>)> {
+        let numbers: Vec<i64> = self
+            .article_numbers(group, from, to)?
+            .into_iter()
+            .map(|(number, _)| number)
+            .collect();
+
+        Ok((
+            numbers.len() as i64,
+            numbers.first().copied().zip(numbers.last().copied()),
+        ))
+    }
+
+    fn numbers_of(
+        &self,
+        group: &str,
+        articles: &[ArticleId],
+    ) -> StorageResult<Vec<(i64, ArticleId)>> {
+        Ok(self
+            .data()
+            .numbers
+            .iter()
+            .filter(|(numbered, _, article)| numbered == group && articles.contains(article))
+            .map(|(_, number, article)| (*number, *article))
+            .collect())
+    }
+
+    fn article_by_message_id(&self, message_id: &str) -> StorageResult<Option<ArticleId>> {
+        let data = self.data();
+
+        let post = data
+            .posts
+            .iter()
+            .find(|post| post.message_id == message_id)
+            .map(|post| ArticleId::Post(post.id));
+        let comment = || {
+            data.comments
+                .iter()
+                .find(|comment| comment.message_id == message_id)
+                .map(|comment| ArticleId::Comment(comment.id))
+        };
+
+        Ok(post.or_else(comment))
+    }
+
+    fn feed_after(&self, after: i64, limit: u32) -> StorageResult<Vec<(i64, ArticleId)>> {
+        Ok(self
+            .data()
+            .numbers
+            .iter()
+            .enumerate()
+            .map(|(index, (_, _, article))| (index as i64 + 1, *article))
+            .filter(|(seq, _)| *seq > after)
+            .take(limit as usize)
+            .collect())
+    }
+
+    fn feed_end(&self) -> StorageResult<i64> {
+        Ok(self.data().numbers.len() as i64)
+    }
+
+    fn peer_progress(&self, peer: &str) -> StorageResult<i64> {
+        Ok(self
+            .data()
+            .progress
+            .iter()
+            .find(|(name, _)| name == peer)
+            .map_or(0, |(_, progress)| *progress))
+    }
+
+    fn set_peer_progress(&self, peer: &str, progress: i64) -> StorageResult<()> {
+        let mut data = self.data();
+
+        data.progress.retain(|(name, _)| name != peer);
+        data.progress.push((peer.to_string(), progress));
+
+        Ok(())
+    }
+
+    fn newsrc(&self, username: &str) -> StorageResult<Vec<NewsrcRow>> {
+        let mut rows: Vec<NewsrcRow> = self
+            .data()
+            .newsrc
+            .iter()
+            .filter(|(name, _)| name == username)
+            .map(|(_, row)| row.clone())
+            .collect();
+        rows.sort_by(|a, b| a.group.cmp(&b.group));
+
+        Ok(rows)
+    }
+
+    fn set_newsrc(&self, username: &str, row: &NewsrcRow) -> StorageResult<()> {
+        let mut data = self.data();
+
+        data.newsrc
+            .retain(|(name, line)| name != username || line.group != row.group);
+        data.newsrc.push((username.to_string(), row.clone()));
+
+        Ok(())
+    }
+}
+
+/// how often `terms` turn up in `fields`, ignoring case, and the field with the most of them
+/// with each one in brackets. none unless every term turns up somewhere
+fn highlight(fields: &[&str], terms: &[String]) -> Option<(usize, String)> {
+    let mut total = 0;
+    let mut best = (0, String::new());
+
+    for field in fields {
+        let lowered = field.to_ascii_lowercase();
+        let mut found: Vec<(usize, usize)> = vec![];
+
+        for term in terms {
+            let term = term.to_ascii_lowercase();
+            found.extend(
+                lowered
+                    .match_indices(&term)
+                    .map(|(start, _)| (start, start + term.len())),
+            );
+        }
+        found.sort();
+
+        let mut snippet = String::new();
+        let mut copied = 0;
+        for (start, end) in &found {
+            if *start < copied {
+                continue;
+            }
+            snippet.push_str(&field[copied..*start]);
+            snippet.push_str(&format!("[{}]", &field[*start..*end]));
+            copied = *end;
+        }
+        snippet.push_str(&field[copied..]);
+
+        total += found.len();
+        if found.len() > best.0 {
+            best = (found.len(), snippet);
+        }
+    }
+
+    let every_term = terms.iter().all(|term| {
+        let term = term.to_ascii_lowercase();
+        fields
+            .iter()
+            .any(|field| field.to_ascii_lowercase().contains(&term))
+    });
+
+    if every_term && total > 0 {
+        Some((total, best.1))
+    } else {
+        None
+    }
+}
@@ -0,0 +1,1068 @@
+use std::fmt;
+use std::sync::OnceLock;
+
+mod memory;
+mod sqlite;
+
+pub use memory::MemoryStorage;
+pub use sqlite::SqliteStorage;
+
+/// the name of the database file created inside `--data-dir`
+pub const DATABASE_FILE: &str = "nnntp.db";
+
+/// the path of articles written on this server, which peers add their names in front of
+pub const LOCAL_PATH: &str = "not-for-mail";
+
+static STORAGE: OnceLock<Box<dyn Storage>> = OnceLock::new();
+
+/// installs the storage every route handler uses, this can only be done once
+pub fn init(storage: Box<dyn Storage>) {
+    if STORAGE.set(storage).is_err() {
+        panic!("storage has already been initialised");
+    }
+}
+
+/// the storage shared by every route handler
+pub fn storage() -> &'static dyn Storage {
+    STORAGE
+        .get()
+        .expect("storage has not been initialised")
+        .as_ref()
+}
+
+/// a failure inside a storage backend
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageError(pub String);
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> StorageError {
+        StorageError(e.to_string())
+    }
+}
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+#[derive(Debug, Clone)]
+pub struct CommentRow {
+    pub id: i32,
+    /// the post at the root of the thread
+    pub parent_id: i32,
+    /// the comment this one replies to, none when it replies to the post itself
+    pub reply_to: Option<i32>,
+    /// the message ids of the post and every comment above this one, oldest first
+    pub references: Vec<String>,
+    pub body: String,
+    pub author: String,
+    pub author_email: String,
+    pub created_at: i64,
+    pub message_id: String,
+    /// the servers the comment came through, newest first, `not-for-mail` when it was written here
+    pub path: String,
+    /// when the comment was last edited, if ever
+    pub edited_at: Option<i64>,
+    /// when, and by whom, the comment was cancelled. its content stays until it is purged
+    pub cancelled_at: Option<i64>,
+    pub cancelled_by: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GroupRow {
+    pub name: String,
+    pub description: String,
+    pub created_at: i64,
+    pub creator: String,
+    pub post_count: i64,
+    /// only posters of the group, its moderators and admins can post to it
+    pub restricted: bool,
+    /// posts wait for a moderator to approve them before they are listed
+    pub moderated: bool,
+}
+
+/// a role held by a user, in one group or, when `group` is empty, in all of them
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoleRow {
+    pub role: String,
+    pub group: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct PostRow {
+    pub id: i32,
+    pub group_name: String,
+    pub subject: String,
+    pub body: String,
+    pub author: String,
+    pub author_email: String,
+    /// seconds since the unix epoch, 0 for posts from before this was recorded
+    pub created_at: i64,
+    pub message_id: String,
+    /// the servers the post came through, newest first, `not-for-mail` when it was written here
+    pub path: String,
+    /// when the post was last edited, if ever
+    pub edited_at: Option<i64>,
+    /// when, and by whom, the post was cancelled. its content stays until it is purged
+    pub cancelled_at: Option<i64>,
+    pub cancelled_by: Option<String>,
+    /// whether the post waits for a moderator of its moderated group
+    pub pending: bool,
+    /// why a moderator turned the post down, if they did
+    pub rejection: Option<String>,
+}
+
+impl PostRow {
+    /// whether the post shows up in its group, which held posts do not
+    pub fn is_listed(&self) -> bool {
+        !self.pending && self.rejection.is_none()
+    }
+}
+
+/// a post or a comment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ArticleId {
+    Post(i32),
+    Comment(i32),
+}
+
+/// what a user has read of a group, and whether they subscribed to it, a line of their newsrc
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewsrcRow {
+    pub group: String,
+    pub subscribed: bool,
+    /// the numbers of the articles read, as ranges like `1-5,7`
+    pub read: String,
+}
+
+/// a single-use code that lets someone register on an invite-only server
+#[derive(Debug, Clone, PartialEq)]
+pub struct InviteRow {
+    pub code: String,
+    pub created_by: String,
+    pub created_at: i64,
+    /// who registered with the code, none while it is unused
+    pub used_by: Option<String>,
+    pub used_at: Option<i64>,
+}
+
+/// an earlier version of an article, replaced by an edit
+#[derive(Debug, Clone, PartialEq)]
+pub struct RevisionRow {
+    /// none for comments, which have no subject
+    pub subject: Option<String>,
+    pub body: String,
+    /// when the edit replacing this version was made
+    pub replaced_at: i64,
+}
+
+/// a user to register
+#[derive(Debug, Clone, Default)]
+pub struct NewUser<'a> {
+    pub username: &'a str,
+    pub hashed_password: &'a str,
+    /// empty if they gave none
+    pub email: &'a str,
+    /// waits for an admin to approve them
+    pub pending: bool,
+}
+
+/// what came of registering with an invite
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Redeemed {
+    Registered,
+    /// there is no such unused invite
+    InvalidInvite,
+    /// someone has the username already, whatever its case
+    UsernameTaken,
+}
+
+/// a post to insert, the storage picks its id
+#[derive(Debug, Clone)]
+pub struct NewPost<'a> {
+    pub group: &'a str,
+    pub subject: &'a str,
+    pub body: &'a str,
+    pub author: &'a str,
+    pub author_email: &'a str,
+    pub created_at: i64,
+    pub message_id: &'a str,
+    /// [`LOCAL_PATH`] for posts written here, and the Path of posts from peers
+    pub path: &'a str,
+    /// holds the post back until a moderator approves it
+    pub pending: bool,
+}
+
+/// a comment to insert, the storage picks its id
+#[derive(Debug, Clone)]
+pub struct NewComment<'a> {
+    pub parent_id: i32,
+    pub reply_to: Option<i32>,
+    pub references: &'a [String],
+    pub body: &'a str,
+    pub author: &'a str,
+    pub author_email: &'a str,
+    pub created_at: i64,
+    pub message_id: &'a str,
+    /// [`LOCAL_PATH`] for comments written here, and the Path of comments from peers
+    pub path: &'a str,
+}
+
+/// which of the posts of some groups to fetch, for paging through them
+#[derive(Debug, Clone, Default)]
+pub struct PostQuery {
+    /// only posts with a higher id than this
+    pub after_id: Option<i32>,
+    /// only posts created at or after this time
+    pub since: Option<i64>,
+    /// how many of the remaining posts to skip
+    pub offset: u32,
+    /// at most this many posts, or all of them
+    pub limit: Option<u32>,
+}
+
+/// what to search for: every one of `terms`, each a word or a phrase, in the subject or body of
+/// listed, uncancelled articles
+#[derive(Debug, Clone, Default)]
+pub struct SearchQuery {
+    pub terms: Vec<String>,
+    /// only articles in these groups, or in any group
+    pub groups: Option<Vec<String>>,
+    pub author: Option<String>,
+    /// only articles created at or after this time
+    pub since: Option<i64>,
+    /// only articles created before this time
+    pub until: Option<i64>,
+    pub limit: u32,
+}
+
+/// an article that matched a search
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchRow {
+    pub article: ArticleId,
+    /// the post itself, or the post a comment is under
+    pub post_id: i32,
+    pub group_name: String,
+    /// the subject of the post, for comments too
+    pub subject: String,
+    pub author: String,
+    pub created_at: i64,
+    /// the part of the article that matched best, with the matches in `[` and `]`
+    pub snippet: String,
+}
+
+/// everything the server persists: users, and the posts and comments of each group.
+/// implementations only store and fetch, checking passwords and permissions is left to the caller
+pub trait Storage: Send + Sync {
+    /// `username` as it was registered, if there is such a user. usernames are unique whatever
+    /// their case, so the methods about the account itself, from here to `delete_user`, ignore
+    /// its case. sessions, roles, newsrcs and articles have the username as it was registered,
+    /// and their methods only find it that way, so callers look it up here first
+    fn registered_username(&self, username: &str) -> StorageResult<Option<String>>;
+
+    /// creates a user, returning false if the username is taken, whatever its case
+    fn insert_user(&self, user: &NewUser) -> StorageResult<bool>;
+
+    fn is_pending(&self, username: &str) -> StorageResult<bool>;
+
+    /// every user waiting for approval, by name
+    fn pending_users(&self) -> StorageResult<Vec<String>>;
+
+    /// lets a pending user in, returning false if they were not pending
+    fn approve_user(&self, username: &str) -> StorageResult<bool>;
+
+    fn insert_invite(&self, code: &str, created_by: &str, created_at: i64) -> StorageResult<()>;
+
+    /// every invite, newest first
+    fn invites(&self) -> StorageResult<Vec<InviteRow>>;
+
+    /// marks the invite `code` used by `user` and creates them, both or neither, so the invite
+    /// stays unused when registering fails
+    fn redeem_invite(&self, code: &str, used_at: i64, user: &NewUser) -> StorageResult<Redeemed>;
+
+    /// the bcrypt hash stored for `username`, if the user exists and was not deleted
+    fn password_hash(&self, username: &str) -> StorageResult<Option<String>>;
+
+    fn set_password_hash(&self, username: &str, hashed_password: &str) -> StorageResult<()>;
+
+    /// the email stored for `username`, empty if they never gave one, or None for unknown and
+    /// deleted users
+    fn email_of(&self, username: &str) -> StorageResult<Option<String>>;
+
+    fn set_email(&self, username: &str, email: &str) -> StorageResult<()>;
+
+    /// deletes the account of `username`, with its sessions, roles and newsrc. the username stays
+    /// taken, so nobody can register it again and pass for the author of its articles
+    fn delete_user(&self, username: &str, deleted_at: i64) -> StorageResult<()>;
+
+    fn insert_session(&self, token: &str, username: &str, expires_at: i64) -> StorageResult<()>;
+
+    /// the user owning the session `token`, unless it has expired by `now`
+    fn session_user(&self, token: &str, now: i64) -> StorageResult<Option<String>>;
+
+    fn delete_session(&self, token: &str) -> StorageResult<()>;
+
+    fn delete_sessions_of(&self, username: &str) -> StorageResult<()>;
+
+    /// creates a group, returning false if one with that name already exists
+    fn insert_group(
+        &self,
+        name: &str,
+        description: &str,
+        created_at: i64,
+        creator: &str,
+    ) -> StorageResult<bool>;
+
+    fn group(&self, name: &str) -> StorageResult<Option<GroupRow>>;
+
+    /// every group, by name
+    fn groups(&self) -> StorageResult<Vec<GroupRow>>;
+
+    fn set_group_restricted(&self, name: &str, restricted: bool) -> StorageResult<()>;
+
+    fn set_group_moderated(&self, name: &str, moderated: bool) -> StorageResult<()>;
+
+    /// gives a user a role, returning false if they already had it. `group` is empty for roles
+    /// that hold everywhere
+    fn grant_role(&self, username: &str, role: &str, group: &str) -> StorageResult<bool>;
+
+    /// takes a role away, returning false if the user did not have it
+    fn revoke_role(&self, username: &str, role: &str, group: &str) -> StorageResult<bool>;
+
+    fn roles_of(&self, username: &str) -> StorageResult<Vec<RoleRow>>;
+
+    /// inserts a post and returns its id
+    fn insert_post(&self, post: &NewPost) -> StorageResult<i32>;
+
+    /// inserts a comment and returns its id
+    fn insert_comment(&self, comment: &NewComment) -> StorageResult<i32>;
+
+    fn comment(&self, id: i32) -> StorageResult<Option<CommentRow>>;
+
+    /// replaces the subject and/or body of an article, keeping the current version as a
+    /// revision. the subject of a comment is ignored
+    fn edit_article(
+        &self,
+        article: ArticleId,
+        subject: Option<&str>,
+        body: Option<&str>,
+        edited_at: i64,
+    ) -> StorageResult<()>;
+
+    /// the earlier versions of an article, oldest first
+    fn revisions(&self, article: ArticleId) -> StorageResult<Vec<RevisionRow>>;
+
+    /// marks an article as cancelled, leaving a tombstone in its place
+    fn cancel_article(&self, article: ArticleId, by: &str, at: i64) -> StorageResult<()>;
+
+    /// erases the content, authors and revisions of every cancelled article, returning how many
+    /// were erased. the rows themselves stay, so threads and message ids stay intact
+    fn purge_cancelled(&self) -> StorageResult<usize>;
+
+    fn post(&self, id: i32) -> StorageResult<Option<PostRow>>;
+
+    /// lets a pending post into its group
+    fn approve_post(&self, id: i32) -> StorageResult<()>;
+
+    /// turns a pending post down for good, keeping it, out of every listing, for its author
+    fn reject_post(&self, id: i32, reason: &str) -> StorageResult<()>;
+
+    /// the posts of all of `groups` still waiting for a moderator, oldest first
+    fn pending_posts(&self, groups: &[String]) -> StorageResult<Vec<PostRow>>;
+
+    /// the posts of `author` that are pending or were rejected, oldest first
+    fn held_posts_of(&self, author: &str) -> StorageResult<Vec<PostRow>>;
+
+    /// the listed posts of all of `groups` picked out by `query`, oldest first
+    fn posts_in_groups(&self, groups: &[String], query: &PostQuery) -> StorageResult<Vec<PostRow>>;
+
+    /// the comments on all of the posts `parent_ids`, oldest first
+    fn comments_on(&self, parent_ids: &[i32]) -> StorageResult<Vec<CommentRow>>;
+
+    /// the articles matching `query`, best match first
+    fn search(&self, query: &SearchQuery) -> StorageResult<Vec<SearchRow>>;
+
+    /// the articles of `group` numbered `from` to `to`, both included, by number. posts and
+    /// comments are numbered in each group as they are listed, starting from 1, and keep their
+    /// number when they are cancelled
+    fn article_numbers(
+        &self,
+        group: &str,
+        from: i64,
+        to: i64,
+    ) -> StorageResult<Vec<(i64, ArticleId)>>;
+
+    /// how many articles of `group` are numbered `from` to `to`, both included, and the lowest
+    /// and highest of their numbers if there are any
+    fn count_articles(
+        &self,
+        group: &str,
+        from: i64,
+        to: i64,
+    ) -> StorageResult<(i64, Option<(i64, i64)>)>;
+
+    /// the numbers of those of `articles` that are numbered in `group`, in any order
+    fn numbers_of(
+        &self,
+        group: &str,
+        articles: &[ArticleId],
+    ) -> StorageResult<Vec<(i64, ArticleId)>>;
+
+    /// the article with the message id `message_id`, whether or not it is listed
+    fn article_by_message_id(&self, message_id: &str) -> StorageResult<Option<ArticleId>>;
+
+    /// at most `limit` of the articles that were listed after the one at `after` in the feed
+    /// to peers, in the order they were listed, each with its place in the feed
+    fn feed_after(&self, after: i64, limit: u32) -> StorageResult<Vec<(i64, ArticleId)>>;
+
+    /// the place in the feed of the article listed last, 0 when nothing was
+    fn feed_end(&self) -> StorageResult<i64>;
+
+    /// the place in the feed up to which articles were offered to `peer`, 0 for new peers
+    fn peer_progress(&self, peer: &str) -> StorageResult<i64>;
+
+    fn set_peer_progress(&self, peer: &str, progress: i64) -> StorageResult<()>;
+
+    /// every group the user subscribed to or read something in, by group
+    fn newsrc(&self, username: &str) -> StorageResult<Vec<NewsrcRow>>;
+
+    /// replaces what the user has read of `row.group`, and whether they subscribed to it
+    fn set_newsrc(&self, username: &str, row: &NewsrcRow) -> StorageResult<()>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every backend has to behave the same, so they all run through the same checks
+    fn exercise(storage: &dyn Storage) {
+        assert_eq!(storage.registered_username("ferris").unwrap(), None);
+        assert_eq!(storage.password_hash("ferris").unwrap(), None);
+
+        let user = |username, pending| NewUser {
+            username,
+            hashed_password: "hash",
+            pending,
+            ..NewUser::default()
+        };
+        assert!(storage.insert_user(&user("ferris", false)).unwrap());
+        assert!(!storage.insert_user(&user("Ferris", false)).unwrap());
+        assert!(storage.insert_user(&user("corro", true)).unwrap());
+        assert!(!storage.is_pending("ferris").unwrap());
+        assert!(storage.is_pending("corro").unwrap());
+        assert_eq!(storage.pending_users().unwrap(), ["corro"]);
+        assert!(storage.approve_user("corro").unwrap());
+        assert!(!storage.approve_user("corro").unwrap());
+        assert!(storage.pending_users().unwrap().is_empty());
+
+        storage.insert_invite("first", "ferris", 1).unwrap();
+        storage.insert_invite("second", "ferris", 2).unwrap();
+        let invited = NewUser {
+            email: "bors@example.com",
+            ..user("bors", false)
+        };
+        assert_eq!(
+            storage
+                .redeem_invite("first", 3, &user("CORRO", false))
+                .unwrap(),
+            Redeemed::UsernameTaken
+        );
+        assert_eq!(
+            storage.redeem_invite("missing", 3, &invited).unwrap(),
+            Redeemed::InvalidInvite
+        );
+        assert_eq!(storage.registered_username("bors").unwrap(), None);
+        assert_eq!(
+            storage.redeem_invite("first", 3, &invited).unwrap(),
+            Redeemed::Registered
+        );
+        assert_eq!(
+            storage.email_of("bors").unwrap().as_deref(),
+            Some("bors@example.com")
+        );
+        assert_eq!(
+            storage
+                .redeem_invite("first", 4, &user("ferris2", false))
+                .unwrap(),
+            Redeemed::InvalidInvite
+        );
+        assert_eq!(
+            storage.invites().unwrap(),
+            [
+                InviteRow {
+                    code: "second".to_string(),
+                    created_by: "ferris".to_string(),
+                    created_at: 2,
+                    used_by: None,
+                    used_at: None,
+                },
+                InviteRow {
+                    code: "first".to_string(),
+                    created_by: "ferris".to_string(),
+                    created_at: 1,
+                    used_by: Some("bors".to_string()),
+                    used_at: Some(3),
+                },
+            ]
+        );
+
+        assert!(storage.registered_username("ferris").unwrap().is_some());
+        assert_eq!(
+            storage.password_hash("ferris").unwrap(),
+            Some("hash".to_string())
+        );
+
+        // users are found whatever the case of their name
+        assert!(storage.registered_username("FERRIS").unwrap().is_some());
+        assert_eq!(
+            storage.registered_username("FeRRis").unwrap(),
+            Some("ferris".to_string())
+        );
+        assert_eq!(storage.registered_username("nobody").unwrap(), None);
+        assert_eq!(
+            storage.password_hash("Ferris").unwrap(),
+            Some("hash".to_string())
+        );
+        assert!(!storage.is_pending("CORRO").unwrap());
+        storage.set_email("FERRIS", "shouting@example.com").unwrap();
+        assert_eq!(
+            storage.email_of("ferris").unwrap(),
+            Some("shouting@example.com".to_string())
+        );
+        storage.set_email("ferris", "").unwrap();
+        assert_eq!(storage.email_of("ferris").unwrap(), Some(String::new()));
+        assert_eq!(storage.email_of("nobody").unwrap(), None);
+
+        storage.set_password_hash("ferris", "rehash").unwrap();
+        storage.set_email("ferris", "ferris@example.com").unwrap();
+        assert_eq!(
+            storage.password_hash("ferris").unwrap(),
+            Some("rehash".to_string())
+        );
+        assert_eq!(
+            storage.email_of("ferris").unwrap(),
+            Some("ferris@example.com".to_string())
+        );
+
+        storage.insert_session("live", "ferris", 100).unwrap();
+        storage.insert_session("stale", "ferris", 10).unwrap();
+        assert_eq!(
+            storage.session_user("live", 50).unwrap(),
+            Some("ferris".to_string())
+        );
+        assert_eq!(storage.session_user("stale", 50).unwrap(), None);
+        assert_eq!(storage.session_user("missing", 50).unwrap(), None);
+
+        storage.delete_session("live").unwrap();
+        assert_eq!(storage.session_user("live", 50).unwrap(), None);
+
+        storage.insert_session("live", "ferris", 100).unwrap();
+        storage.delete_sessions_of("ferris").unwrap();
+        assert_eq!(storage.session_user("live", 50).unwrap(), None);
+
+        assert!(storage
+            .insert_group("comp.lang.rust", "rust talk", 5, "ferris")
+            .unwrap());
+        assert!(!storage
+            .insert_group("comp.lang.rust", "again", 6, "ferris")
+            .unwrap());
+        assert!(storage
+            .insert_group("comp.lang.c", "", 7, "ferris")
+            .unwrap());
+        assert!(storage.group("alt.nothing").unwrap().is_none());
+
+        storage.set_group_restricted("comp.lang.c", true).unwrap();
+        assert!(storage.group("comp.lang.c").unwrap().unwrap().restricted);
+
+        assert!(storage.grant_role("ferris", "admin", "").unwrap());
+        assert!(!storage.grant_role("ferris", "admin", "").unwrap());
+        assert!(storage
+            .grant_role("ferris", "poster", "comp.lang.c")
+            .unwrap());
+        assert_eq!(
+            storage.roles_of("ferris").unwrap(),
+            [
+                RoleRow {
+                    role: "admin".to_string(),
+                    group: String::new(),
+                },
+                RoleRow {
+                    role: "poster".to_string(),
+                    group: "comp.lang.c".to_string(),
+                },
+            ]
+        );
+        assert!(storage.revoke_role("ferris", "admin", "").unwrap());
+        assert!(!storage.revoke_role("ferris", "admin", "").unwrap());
+        assert_eq!(storage.roles_of("ferris").unwrap().len(), 1);
+        assert!(storage.roles_of("nobody").unwrap().is_empty());
+
+        let post = |group, created_at, message_id| NewPost {
+            group,
+            subject: "hello",
+            body: "world",
+            author: "ferris",
+            author_email: "f@example.com",
+            created_at,
+            message_id,
+            path: LOCAL_PATH,
+            pending: false,
+        };
+        let first = storage
+            .insert_post(&post("comp.lang.rust", 10, "<1@example.com>"))
+            .unwrap();
+        let second = storage
+            .insert_post(&post("comp.lang.c", 20, "<2@example.com>"))
+            .unwrap();
+        assert_ne!(first, second);
+
+        let references = ["<1@example.com>".to_string()];
+        let comment = storage
+            .insert_comment(&NewComment {
+                parent_id: first,
+                reply_to: None,
+                references: &references,
+                body: "hi!",
+                author: "ferris",
+                author_email: "f@example.com",
+                created_at: 30,
+                message_id: "<3@example.com>",
+                path: LOCAL_PATH,
+            })
+            .unwrap();
+
+        let all = PostQuery::default();
+        let posts = storage
+            .posts_in_groups(&["comp.lang.rust".to_string()], &all)
+            .unwrap();
+        assert_eq!(posts.len(), 1);
+        assert_eq!(posts[0].id, first);
+        assert_eq!(posts[0].subject, "hello");
+        assert_eq!(posts[0].created_at, 10);
+        assert_eq!(posts[0].message_id, "<1@example.com>");
+
+        let both = ["comp.lang.c".to_string(), "comp.lang.rust".to_string()];
+        let ids = |query: PostQuery| -> Vec<i32> {
+            storage
+                .posts_in_groups(&both, &query)
+                .unwrap()
+                .iter()
+                .map(|post| post.id)
+                .collect()
+        };
+        assert_eq!(ids(PostQuery::default()), [first, second]);
+        let page = |limit| PostQuery {
+            limit: Some(limit),
+            ..PostQuery::default()
+        };
+        assert_eq!(ids(page(1)), [first]);
+        assert_eq!(
+            ids(PostQuery {
+                offset: 1,
+                ..page(1)
+            }),
+            [second]
+        );
+        assert_eq!(
+            ids(PostQuery {
+                after_id: Some(first),
+                ..PostQuery::default()
+            }),
+            [second]
+        );
+        assert_eq!(
+            ids(PostQuery {
+                since: Some(11),
+                ..PostQuery::default()
+            }),
+            [second]
+        );
+
+        let comments = storage.comments_on(&[first, second]).unwrap();
+        assert_eq!(comments.len(), 1);
+        assert_eq!(comments[0].id, comment);
+        assert_eq!(comments[0].parent_id, first);
+        assert_eq!(comments[0].body, "hi!");
+        assert_eq!(comments[0].created_at, 30);
+        assert_eq!(comments[0].message_id, "<3@example.com>");
+        assert_eq!(comments[0].references, references);
+
+        let chain = ["<1@example.com>".to_string(), "<3@example.com>".to_string()];
+        let reply = storage
+            .insert_comment(&NewComment {
+                parent_id: first,
+                reply_to: Some(comment),
+                references: &chain,
+                body: "hello back",
+                author: "ferris",
+                author_email: "f@example.com",
+                created_at: 40,
+                message_id: "<4@example.com>",
+                path: LOCAL_PATH,
+            })
+            .unwrap();
+        let reply = storage.comment(reply).unwrap().unwrap();
+        assert_eq!(reply.reply_to, Some(comment));
+        assert_eq!(reply.references, chain);
+        assert!(storage.comment(reply.id + 1).unwrap().is_none());
+
+        storage
+            .edit_article(ArticleId::Post(first), Some("hi"), None, 50)
+            .unwrap();
+        storage
+            .edit_article(ArticleId::Post(first), None, Some("everyone"), 60)
+            .unwrap();
+        let edited = storage.post(first).unwrap().unwrap();
+        assert_eq!(
+            (edited.subject.as_str(), edited.body.as_str()),
+            ("hi", "everyone")
+        );
+        assert_eq!(edited.edited_at, Some(60));
+        assert_eq!(
+            storage.revisions(ArticleId::Post(first)).unwrap(),
+            [
+                RevisionRow {
+                    subject: Some("hello".to_string()),
+                    body: "world".to_string(),
+                    replaced_at: 50,
+                },
+                RevisionRow {
+                    subject: Some("hi".to_string()),
+                    body: "world".to_string(),
+                    replaced_at: 60,
+                },
+            ]
+        );
+
+        storage
+            .edit_article(ArticleId::Comment(comment), None, Some("hey!"), 70)
+            .unwrap();
+        assert_eq!(storage.comment(comment).unwrap().unwrap().body, "hey!");
+        assert_eq!(
+            storage.revisions(ArticleId::Comment(comment)).unwrap()[0].body,
+            "hi!"
+        );
+        assert!(storage
+            .revisions(ArticleId::Post(second))
+            .unwrap()
+            .is_empty());
+
+        storage
+            .cancel_article(ArticleId::Post(first), "admin", 80)
+            .unwrap();
+        let cancelled = storage.post(first).unwrap().unwrap();
+        assert_eq!(cancelled.cancelled_at, Some(80));
+        assert_eq!(cancelled.cancelled_by.as_deref(), Some("admin"));
+        assert_eq!(cancelled.body, "everyone");
+
+        assert_eq!(storage.purge_cancelled().unwrap(), 1);
+        let purged = storage.post(first).unwrap().unwrap();
+        assert_eq!((purged.subject.as_str(), purged.body.as_str()), ("", ""));
+        assert_eq!(purged.author, "");
+        assert_eq!(purged.message_id, "<1@example.com>");
+        assert!(storage
+            .revisions(ArticleId::Post(first))
+            .unwrap()
+            .is_empty());
+        assert_eq!(storage.comment(comment).unwrap().unwrap().body, "hey!");
+        assert_eq!(storage.purge_cancelled().unwrap(), 0);
+
+        assert_eq!(
+            storage.post(second).unwrap().unwrap().group_name,
+            "comp.lang.c"
+        );
+        assert!(storage.post(second + 1).unwrap().is_none());
+
+        assert!(storage.comments_on(&[second]).unwrap().is_empty());
+        assert!(storage.comments_on(&[]).unwrap().is_empty());
+
+        let rust = storage.group("comp.lang.rust").unwrap().unwrap();
+        assert_eq!(rust.description, "rust talk");
+        assert_eq!(rust.created_at, 5);
+        assert_eq!(rust.post_count, 1);
+
+        let names: Vec<String> = storage
+            .groups()
+            .unwrap()
+            .into_iter()
+            .map(|group| group.name)
+            .collect();
+        assert_eq!(names, ["comp.lang.c", "comp.lang.rust"]);
+        assert!(storage
+            .posts_in_groups(&["alt.nothing".to_string()], &all)
+            .unwrap()
+            .is_empty());
+        assert!(storage.posts_in_groups(&[], &all).unwrap().is_empty());
+
+        storage.set_group_moderated("comp.lang.rust", true).unwrap();
+        assert!(storage.group("comp.lang.rust").unwrap().unwrap().moderated);
+
+        let rust = ["comp.lang.rust".to_string()];
+        let held = |message_id| NewPost {
+            pending: true,
+            ..post("comp.lang.rust", 90, message_id)
+        };
+        let approved = storage.insert_post(&held("<5@example.com>")).unwrap();
+        let rejected = storage.insert_post(&held("<6@example.com>")).unwrap();
+        assert_eq!(storage.posts_in_groups(&rust, &all).unwrap().len(), 1);
+        assert_eq!(
+            storage.group("comp.lang.rust").unwrap().unwrap().post_count,
+            1
+        );
+        let pending: Vec<i32> = storage
+            .pending_posts(&rust)
+            .unwrap()
+            .iter()
+            .map(|post| post.id)
+            .collect();
+        assert_eq!(pending, [approved, rejected]);
+        assert!(storage.pending_posts(&[]).unwrap().is_empty());
+
+        storage.approve_post(approved).unwrap();
+        storage.reject_post(rejected, "off topic").unwrap();
+        assert!(storage.pending_posts(&rust).unwrap().is_empty());
+        assert_eq!(
+            storage
+                .posts_in_groups(&rust, &all)
+                .unwrap()
+                .last()
+                .unwrap()
+                .id,
+            approved
+        );
+        let turned_down = storage.held_posts_of("ferris").unwrap();
+        assert_eq!(turned_down.len(), 1);
+        assert_eq!(turned_down[0].id, rejected);
+        assert_eq!(turned_down[0].rejection.as_deref(), Some("off topic"));
+        assert!(!turned_down[0].pending);
+        assert!(storage.held_posts_of("nobody").unwrap().is_empty());
+
+        let search = |terms: &[&str], tweak: &dyn Fn(&mut SearchQuery)| -> Vec<SearchRow> {
+            let mut query = SearchQuery {
+                terms: terms.iter().map(|term| term.to_string()).collect(),
+                limit: 10,
+                ..SearchQuery::default()
+            };
+            tweak(&mut query);
+            storage.search(&query).unwrap()
+        };
+        let articles = |hits: Vec<SearchRow>| -> Vec<ArticleId> {
+            let mut articles: Vec<ArticleId> = hits.iter().map(|hit| hit.article).collect();
+            articles.sort_by_key(|article| format!("{:?}", article));
+            articles
+        };
+        let anything = |_: &mut SearchQuery| {};
+
+        // neither the cancelled, the rejected nor anything pending turns up
+        assert_eq!(
+            articles(search(&["world"], &anything)),
+            [ArticleId::Post(second), ArticleId::Post(approved)]
+        );
+
+        let strict = storage
+            .insert_post(&NewPost {
+                body: "The borrow checker is strict",
+                author: "corro@news2.example.com",
+                path: "news2.example.com!not-for-mail",
+                ..post("comp.lang.rust", 100, "<7@example.com>")
+            })
+            .unwrap();
+        let love = storage
+            .insert_comment(&NewComment {
+                parent_id: strict,
+                reply_to: None,
+                references: &[],
+                body: "I love the Borrow Checker",
+                author: "ferris",
+                author_email: "f@example.com",
+                created_at: 110,
+                message_id: "<8@example.com>",
+                path: LOCAL_PATH,
+            })
+            .unwrap();
+
+        let hits = search(&["borrow checker"], &anything);
+        assert_eq!(hits.len(), 2);
+        let by_ferris = search(&["borrow checker"], &|q| {
+            q.author = Some("ferris".to_string())
+        });
+        assert_eq!(by_ferris.len(), 1);
+        assert_eq!(by_ferris[0].article, ArticleId::Comment(love));
+        assert_eq!(by_ferris[0].post_id, strict);
+        assert_eq!(by_ferris[0].subject, "hello");
+        assert_eq!(by_ferris[0].group_name, "comp.lang.rust");
+        assert!(by_ferris[0].snippet.contains('['));
+        assert!(search(&["checker borrow"], &anything).is_empty());
+        assert_eq!(search(&["checker", "borrow"], &anything).len(), 2);
+        assert!(search(&["borrow checker"], &|q| q.groups =
+            Some(vec!["comp.lang.c".to_string()]))
+        .is_empty());
+        assert_eq!(
+            articles(search(&["borrow"], &|q| q.since = Some(105))),
+            [ArticleId::Comment(love)]
+        );
+        assert_eq!(
+            articles(search(&["borrow"], &|q| q.until = Some(105))),
+            [ArticleId::Post(strict)]
+        );
+
+        storage
+            .edit_article(ArticleId::Comment(love), None, Some("never mind"), 120)
+            .unwrap();
+        assert_eq!(search(&["borrow"], &anything).len(), 1);
+        assert_eq!(search(&["never mind"], &anything).len(), 1);
+        storage
+            .cancel_article(ArticleId::Post(strict), "corro", 130)
+            .unwrap();
+        assert!(search(&["borrow"], &anything).is_empty());
+        assert_eq!(search(&["never mind"], &anything)[0].subject, "");
+
+        // pending posts get their number once approved, rejected ones never
+        assert_eq!(
+            storage
+                .article_numbers("comp.lang.rust", 1, i64::MAX)
+                .unwrap(),
+            [
+                (1, ArticleId::Post(first)),
+                (2, ArticleId::Comment(comment)),
+                (3, ArticleId::Comment(reply.id)),
+                (4, ArticleId::Post(approved)),
+                (5, ArticleId::Post(strict)),
+                (6, ArticleId::Comment(love)),
+            ]
+        );
+        assert_eq!(
+            storage.article_numbers("comp.lang.rust", 2, 3).unwrap(),
+            [
+                (2, ArticleId::Comment(comment)),
+                (3, ArticleId::Comment(reply.id))
+            ]
+        );
+        assert_eq!(
+            storage.article_numbers("comp.lang.c", 1, 10).unwrap(),
+            [(1, ArticleId::Post(second))]
+        );
+        assert!(storage
+            .article_numbers("alt.nothing", 1, 10)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            storage.count_articles("comp.lang.rust", 2, 10).unwrap(),
+            (5, Some((2, 6)))
+        );
+        assert_eq!(
+            storage.count_articles("alt.nothing", 1, i64::MAX).unwrap(),
+            (0, None)
+        );
+        let mut numbers = storage
+            .numbers_of(
+                "comp.lang.rust",
+                &[
+                    ArticleId::Comment(love),
+                    ArticleId::Post(first),
+                    ArticleId::Post(rejected),
+                    ArticleId::Post(second),
+                ],
+            )
+            .unwrap();
+        numbers.sort_by_key(|(number, _)| *number);
+        assert_eq!(
+            numbers,
+            [(1, ArticleId::Post(first)), (6, ArticleId::Comment(love))]
+        );
+        assert!(storage
+            .numbers_of("comp.lang.rust", &[])
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(
+            storage.article_by_message_id("<6@example.com>").unwrap(),
+            Some(ArticleId::Post(rejected))
+        );
+        assert_eq!(
+            storage.article_by_message_id("<8@example.com>").unwrap(),
+            Some(ArticleId::Comment(love))
+        );
+        assert_eq!(
+            storage.article_by_message_id("<404@example.com>").unwrap(),
+            None
+        );
+
+        // the feed to peers runs across groups, in the order articles were listed
+        let feed = storage.feed_after(0, 100).unwrap();
+        assert_eq!(feed.len(), 7);
+        assert_eq!(
+            feed[..3],
+            [
+                (1, ArticleId::Post(first)),
+                (2, ArticleId::Post(second)),
+                (3, ArticleId::Comment(comment)),
+            ]
+        );
+        assert_eq!(
+            storage.feed_after(2, 1).unwrap(),
+            [(3, ArticleId::Comment(comment))]
+        );
+        assert!(storage.feed_after(7, 100).unwrap().is_empty());
+        assert_eq!(storage.feed_end().unwrap(), 7);
+        assert_eq!(storage.post(first).unwrap().unwrap().path, LOCAL_PATH);
+        assert_eq!(
+            storage.post(strict).unwrap().unwrap().path,
+            "news2.example.com!not-for-mail"
+        );
+
+        assert_eq!(storage.peer_progress("news2.example.com").unwrap(), 0);
+        storage.set_peer_progress("news2.example.com", 3).unwrap();
+        storage.set_peer_progress("news2.example.com", 5).unwrap();
+        assert_eq!(storage.peer_progress("news2.example.com").unwrap(), 5);
+        assert_eq!(storage.peer_progress("news3.example.com").unwrap(), 0);
+
+        let line = |group: &str, subscribed, read: &str| NewsrcRow {
+            group: group.to_string(),
+            subscribed,
+            read: read.to_string(),
+        };
+        assert!(storage.newsrc("ferris").unwrap().is_empty());
+        storage
+            .set_newsrc("ferris", &line("comp.lang.rust", true, ""))
+            .unwrap();
+        storage
+            .set_newsrc("ferris", &line("comp.lang.c", false, "1"))
+            .unwrap();
+        storage
+            .set_newsrc("ferris", &line("comp.lang.rust", true, "1-3"))
+            .unwrap();
+        assert_eq!(
+            storage.newsrc("ferris").unwrap(),
+            [
+                line("comp.lang.c", false, "1"),
+                line("comp.lang.rust", true, "1-3"),
+            ]
+        );
+        assert!(storage.newsrc("corro").unwrap().is_empty());
+
+        storage.insert_session("live", "ferris", 100).unwrap();
+        storage.delete_user("Ferris", 60).unwrap();
+        assert!(storage.registered_username("ferris").unwrap().is_some());
+        assert_eq!(storage.password_hash("ferris").unwrap(), None);
+        assert_eq!(storage.email_of("ferris").unwrap(), None);
+        assert_eq!(storage.session_user("live", 50).unwrap(), None);
+        assert!(storage.roles_of("ferris").unwrap().is_empty());
+        assert!(storage.newsrc("ferris").unwrap().is_empty());
+        // their articles stay
+        assert!(storage.post(1).unwrap().is_some());
+    }
+
+    #[test]
+    fn sqlite_storage() {
+        exercise(&SqliteStorage::open(":memory:", None).unwrap());
+    }
+
+    #[test]
+    fn memory_storage() {
+        exercise(&MemoryStorage::new());
+    }
+}
@@ -0,0 +1,1013 @@
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Row};
+
+use super::{
+    ArticleId, CommentRow, GroupRow, InviteRow, NewComment, NewPost, NewUser, NewsrcRow, PostQuery,
+    PostRow, Redeemed, RevisionRow, RoleRow, SearchQuery, SearchRow, Storage, StorageResult,
+};
+use crate::migrations;
+
+/// the single database connection of the server, owning the whole schema
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    /// opens (or creates) the database at `path`, bringing its schema up to date. servers used
+    /// to keep users and articles in files of their own in their data directory, which are
+    /// imported from `legacy_dir` into a database that is new
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        legacy_dir: Option<&Path>,
+    ) -> Result<SqliteStorage, String> {
+        let mut conn = Connection::open(&path).map_err(|e| e.to_string())?;
+
+        migrations::upgrade(&mut conn, legacy_dir, migrations::latest_version())?;
+
+        Ok(SqliteStorage {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        // a panic while holding the lock cannot leave sqlite in a bad state, so carry on
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn insert_user(conn: &Connection, user: &NewUser) -> rusqlite::Result<bool> {
+    // the unique index on usernames ignores case, so a taken name is not inserted at all
+    let inserted = conn.execute(
+        "INSERT OR IGNORE INTO users (username, password, email, pending)
+            VALUES (?1, ?2, ?3, ?4)",
+        params![
+            user.username,
+            user.hashed_password,
+            user.email,
+            user.pending
+        ],
+    )?;
+
+    Ok(inserted == 1)
+}
+
+fn post_row(row: &Row) -> rusqlite::Result<PostRow> {
+    Ok(PostRow {
+        id: row.get(0)?,
+        group_name: row.get(1)?,
+        subject: row.get(2)?,
+        body: row.get(3)?,
+        author: row.get(4)?,
+        author_email: row.get(5)?,
+        created_at: row.get(6)?,
+        message_id: row.get(7)?,
+        edited_at: row.get(8)?,
+        cancelled_at: row.get(9)?,
+        cancelled_by: row.get(10)?,
+        pending: row.get(11)?,
+        rejection: row.get(12)?,
+        path: row.get(13)?,
+    })
+}
+
+const POST_COLUMNS: &str =
+    "id, group_name, subject, body, author, author_email, created_at, message_id, edited_at,
+    cancelled_at, cancelled_by, pending, rejection, path";
+
+/// the condition on posts that shows up in their groups
+const LISTED: &str = "pending = 0 AND rejection IS NULL";
+
+const COMMENT_COLUMNS: &str = "id, parent_id, reply_to, refs, body, author, author_email,
+    created_at, message_id, edited_at, cancelled_at, cancelled_by, path";
+
+fn comment_row(row: &Row) -> rusqlite::Result<CommentRow> {
+    let references: String = row.get(3)?;
+
+    Ok(CommentRow {
+        id: row.get(0)?,
+        parent_id: row.get(1)?,
+        reply_to: row.get(2)?,
+        // message ids cannot contain spaces, so they are stored space separated
+        references: references.split_whitespace().map(str::to_string).collect(),
+        body: row.get(4)?,
+        author: row.get(5)?,
+        author_email: row.get(6)?,
+        created_at: row.get(7)?,
+        message_id: row.get(8)?,
+        edited_at: row.get(9)?,
+        cancelled_at: row.get(10)?,
+        cancelled_by: row.get(11)?,
+        path: row.get(12)?,
+    })
+}
+
+/// how the revisions, search and numbering tables refer to an article
+fn kind_and_id(article: ArticleId) -> (&'static str, i32) {
+    match article {
+        ArticleId::Post(id) => ("post", id),
+        ArticleId::Comment(id) => ("comment", id),
+    }
+}
+
+/// the other way around from [`kind_and_id`]
+fn article_of_kind(kind: &str, id: i32) -> ArticleId {
+    match kind {
+        "post" => ArticleId::Post(id),
+        _ => ArticleId::Comment(id),
+    }
+}
+
+/// gives an article that just became listed the next number in its group, and queues it for
+/// peers
+fn number_article(conn: &Connection, group: &str, kind: &str, id: i32) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO article_numbers (group_name, number, kind, article_id)
+            SELECT ?1, COALESCE(MAX(number), 0) + 1, ?2, ?3 FROM article_numbers
+                WHERE group_name = ?1",
+        params![group, kind, id],
+    )?;
+    conn.execute(
+        "INSERT INTO feed (kind, article_id) VALUES (?1, ?2)",
+        params![kind, id],
+    )?;
+
+    Ok(())
+}
+
+/// numbers a post that just became listed, and adds it to the search index
+fn publish_post(conn: &Connection, id: i32) -> rusqlite::Result<()> {
+    let group: String =
+        conn.query_row("SELECT group_name FROM posts WHERE id = ?1", [id], |row| {
+            row.get(0)
+        })?;
+    number_article(conn, &group, "post", id)?;
+
+    conn.execute(
+        "INSERT INTO search_index
+            (subject, body, kind, article_id, post_id, group_name, author, created_at)
+            SELECT subject, body, 'post', id, id, group_name, author, created_at FROM posts
+                WHERE id = ?1",
+        [id],
+    )?;
+
+    Ok(())
+}
+
+fn publish_comment(conn: &Connection, id: i32) -> rusqlite::Result<()> {
+    let group: String = conn.query_row(
+        "SELECT posts.group_name FROM comments JOIN posts ON posts.id = comments.parent_id
+            WHERE comments.id = ?1",
+        [id],
+        |row| row.get(0),
+    )?;
+    number_article(conn, &group, "comment", id)?;
+
+    conn.execute(
+        "INSERT INTO search_index
+            (subject, body, kind, article_id, post_id, group_name, author, created_at)
+            SELECT '', comments.body, 'comment', comments.id, posts.id, posts.group_name,
+                comments.author, comments.created_at
+                FROM comments JOIN posts ON posts.id = comments.parent_id
+                WHERE comments.id = ?1",
+        [id],
+    )?;
+
+    Ok(())
+}
+
+/// the columns [`group_row`] reads, counting only the posts that are listed
+fn group_columns() -> String {
+    format!(
+        "name, description, created_at, creator,
+            (SELECT COUNT(*) FROM posts WHERE posts.group_name = groups.name AND {}),
+            restricted, moderated",
+        LISTED
+    )
+}
+
+fn group_row(row: &Row) -> rusqlite::Result<GroupRow> {
+    Ok(GroupRow {
+        name: row.get(0)?,
+        description: row.get(1)?,
+        created_at: row.get(2)?,
+        creator: row.get(3)?,
+        post_count: row.get(4)?,
+        restricted: row.get(5)?,
+        moderated: row.get(6)?,
+    })
+}
+
+impl Storage for SqliteStorage {
+    fn registered_username(&self, username: &str) -> StorageResult<Option<String>> {
+        Ok(self
+            .conn()
+            .query_row(
+                "SELECT username FROM users WHERE username = ?1 COLLATE NOCASE",
+                [username],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn insert_user(&self, user: &NewUser) -> StorageResult<bool> {
+        Ok(insert_user(&self.conn(), user)?)
+    }
+
+    fn is_pending(&self, username: &str) -> StorageResult<bool> {
+        let conn = self.conn();
+        let mut stmt =
+            conn.prepare("SELECT 1 FROM users WHERE username = ?1 COLLATE NOCASE AND pending = 1")?;
+
+        Ok(stmt.exists([username])?)
+    }
+
+    fn pending_users(&self) -> StorageResult<Vec<String>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT username FROM users WHERE pending = 1 AND deleted_at IS NULL
+                ORDER BY username",
+        )?;
+
+        let users = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+
+        Ok(users)
+    }
+
+    fn approve_user(&self, username: &str) -> StorageResult<bool> {
+        let approved = self.conn().execute(
+            "UPDATE users SET pending = 0 WHERE username = ?1 COLLATE NOCASE AND pending = 1",
+            [username],
+        )?;
+
+        Ok(approved == 1)
+    }
+
+    fn insert_invite(&self, code: &str, created_by: &str, created_at: i64) -> StorageResult<()> {
+        self.conn().execute(
+            "INSERT INTO invites (code, created_by, created_at) VALUES (?1, ?2, ?3)",
+            params![code, created_by, created_at],
+        )?;
+
+        Ok(())
+    }
+
+    fn invites(&self) -> StorageResult<Vec<InviteRow>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT code, created_by, created_at, used_by, used_at FROM invites
+                ORDER BY created_at DESC, rowid DESC",
+        )?;
+
+        let invites = stmt
+            .query_map([], |row| {
+                Ok(InviteRow {
+                    code: row.get(0)?,
+                    created_by: row.get(1)?,
+                    created_at: row.get(2)?,
+                    used_by: row.get(3)?,
+                    used_at: row.get(4)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+
+        Ok(invites)
+    }
+
+    fn redeem_invite(&self, code: &str, used_at: i64, user: &NewUser) -> StorageResult<Redeemed> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+
+        // returning early drops the transaction, which rolls it back
+        let used = tx.execute(
+            "UPDATE invites SET used_by = ?2, used_at = ?3 WHERE code = ?1 AND used_by IS NULL",
+            params![code, user.username, used_at],
+        )?;
+        if used == 0 {
+            return Ok(Redeemed::InvalidInvite);
+        }
+        if !insert_user(&tx, user)? {
+            return Ok(Redeemed::UsernameTaken);
+        }
+
+        tx.commit()?;
+
+        Ok(Redeemed::Registered)
+    }
+
+    fn password_hash(&self, username: &str) -> StorageResult<Option<String>> {
+        Ok(self
+            .conn()
+            .query_row(
+                "SELECT password FROM users
+                    WHERE username = ?1 COLLATE NOCASE AND deleted_at IS NULL",
+                [username],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn set_password_hash(&self, username: &str, hashed_password: &str) -> StorageResult<()> {
+        self.conn().execute(
+            "UPDATE users SET password = ?2 WHERE username = ?1 COLLATE NOCASE",
+            [username, hashed_password],
+        )?;
+
+        Ok(())
+    }
+
+    fn email_of(&self, username: &str) -> StorageResult<Option<String>> {
+        Ok(self
+            .conn()
+            .query_row(
+                "SELECT email FROM users WHERE username = ?1 COLLATE NOCASE AND deleted_at IS NULL",
+                [username],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn set_email(&self, username: &str, email: &str) -> StorageResult<()> {
+        self.conn().execute(
+            "UPDATE users SET email = ?2 WHERE username = ?1 COLLATE NOCASE",
+            [username, email],
+        )?;
+
+        Ok(())
+    }
+
+    fn delete_user(&self, username: &str, deleted_at: i64) -> StorageResult<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+
+        // the other tables have the username as it was registered
+        let username: Option<String> = tx
+            .query_row(
+                "SELECT username FROM users WHERE username = ?1 COLLATE NOCASE",
+                [username],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let username = match username {
+            Some(username) => username,
+            None => return Ok(()),
+        };
+
+        tx.execute(
+            "UPDATE users SET password = '', email = '', deleted_at = ?2 WHERE username = ?1",
+            params![username, deleted_at],
+        )?;
+        tx.execute("DELETE FROM sessions WHERE username = ?1", [&username])?;
+        tx.execute("DELETE FROM roles WHERE username = ?1", [&username])?;
+        tx.execute("DELETE FROM newsrc WHERE username = ?1", [&username])?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn insert_session(&self, token: &str, username: &str, expires_at: i64) -> StorageResult<()> {
+        self.conn().execute(
+            "INSERT INTO sessions (token, username, expires_at) VALUES (?1, ?2, ?3)",
+            params![token, username, expires_at],
+        )?;
+
+        Ok(())
+    }
+
+    fn session_user(&self, token: &str, now: i64) -> StorageResult<Option<String>> {
+        Ok(self
+            .conn()
+            .query_row(
+                "SELECT username FROM sessions WHERE token = ?1 AND expires_at > ?2",
+                params![token, now],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn delete_session(&self, token: &str) -> StorageResult<()> {
+        self.conn()
+            .execute("DELETE FROM sessions WHERE token = ?1", [token])?;
+
+        Ok(())
+    }
+
+    fn delete_sessions_of(&self, username: &str) -> StorageResult<()> {
+        self.conn()
+            .execute("DELETE FROM sessions WHERE username = ?1", [username])?;
+
+        Ok(())
+    }
+
+    fn insert_group(
+        &self,
+        name: &str,
+        description: &str,
+        created_at: i64,
+        creator: &str,
+    ) -> StorageResult<bool> {
+        let inserted = self.conn().execute(
+            "INSERT OR IGNORE INTO groups (name, description, created_at, creator)
+                VALUES (?1, ?2, ?3, ?4)",
+            params![name, description, created_at, creator],
+        )?;
+
+        Ok(inserted == 1)
+    }
+
+    fn group(&self, name: &str) -> StorageResult<Option<GroupRow>> {
+        Ok(self
+            .conn()
+            .query_row(
+                &format!("SELECT {} FROM groups WHERE name = ?1", group_columns()),
+                [name],
+                group_row,
+            )
+            .optional()?)
+    }
+
+    fn groups(&self) -> StorageResult<Vec<GroupRow>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM groups ORDER BY name",
+            group_columns()
+        ))?;
+
+        let rows = stmt.query_map([], group_row)?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn set_group_restricted(&self, name: &str, restricted: bool) -> StorageResult<()> {
+        self.conn().execute(
+            "UPDATE groups SET restricted = ?1 WHERE name = ?2",
+            params![restricted, name],
+        )?;
+
+        Ok(())
+    }
+
+    fn set_group_moderated(&self, name: &str, moderated: bool) -> StorageResult<()> {
+        self.conn().execute(
+            "UPDATE groups SET moderated = ?1 WHERE name = ?2",
+            params![moderated, name],
+        )?;
+
+        Ok(())
+    }
+
+    fn grant_role(&self, username: &str, role: &str, group: &str) -> StorageResult<bool> {
+        let granted = self.conn().execute(
+            "INSERT OR IGNORE INTO roles (username, role, group_name) VALUES (?1, ?2, ?3)",
+            [username, role, group],
+        )?;
+
+        Ok(granted == 1)
+    }
+
+    fn revoke_role(&self, username: &str, role: &str, group: &str) -> StorageResult<bool> {
+        let revoked = self.conn().execute(
+            "DELETE FROM roles WHERE username = ?1 AND role = ?2 AND group_name = ?3",
+            [username, role, group],
+        )?;
+
+        Ok(revoked == 1)
+    }
+
+    fn roles_of(&self, username: &str) -> StorageResult<Vec<RoleRow>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT role, group_name FROM roles WHERE username = ?1 ORDER BY role, group_name",
+        )?;
+
+        let rows = stmt.query_map([username], |row| {
+            Ok(RoleRow {
+                role: row.get(0)?,
+                group: row.get(1)?,
+            })
+        })?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn insert_post(&self, post: &NewPost) -> StorageResult<i32> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO posts
+                (group_name, subject, body, author, author_email, created_at, message_id,
+                path, pending)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                post.group,
+                post.subject,
+                post.body,
+                post.author,
+                post.author_email,
+                post.created_at,
+                post.message_id,
+                post.path,
+                post.pending
+            ],
+        )?;
+        let id = tx.last_insert_rowid() as i32;
+
+        // pending posts are published once they are approved
+        if !post.pending {
+            publish_post(&tx, id)?;
+        }
+        tx.commit()?;
+
+        Ok(id)
+    }
+
+    fn insert_comment(&self, comment: &NewComment) -> StorageResult<i32> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO comments
+                (parent_id, reply_to, refs, body, author, author_email, created_at, message_id,
+                path)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                comment.parent_id,
+                comment.reply_to,
+                comment.references.join(" "),
+                comment.body,
+                comment.author,
+                comment.author_email,
+                comment.created_at,
+                comment.message_id,
+                comment.path
+            ],
+        )?;
+        let id = tx.last_insert_rowid() as i32;
+
+        publish_comment(&tx, id)?;
+        tx.commit()?;
+
+        Ok(id)
+    }
+
+    fn comment(&self, id: i32) -> StorageResult<Option<CommentRow>> {
+        Ok(self
+            .conn()
+            .query_row(
+                &format!("SELECT {} FROM comments WHERE id = ?1", COMMENT_COLUMNS),
+                [id],
+                comment_row,
+            )
+            .optional()?)
+    }
+
+    fn edit_article(
+        &self,
+        article: ArticleId,
+        subject: Option<&str>,
+        body: Option<&str>,
+        edited_at: i64,
+    ) -> StorageResult<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+
+        match article {
+            ArticleId::Post(id) => {
+                tx.execute(
+                    "INSERT INTO revisions (kind, article_id, subject, body, replaced_at)
+                        SELECT 'post', id, subject, body, ?1 FROM posts WHERE id = ?2",
+                    params![edited_at, id],
+                )?;
+                tx.execute(
+                    "UPDATE posts SET subject = COALESCE(?1, subject), body = COALESCE(?2, body),
+                        edited_at = ?3 WHERE id = ?4",
+                    params![subject, body, edited_at, id],
+                )?;
+                tx.execute(
+                    "UPDATE search_index SET subject = COALESCE(?1, subject),
+                        body = COALESCE(?2, body) WHERE kind = 'post' AND article_id = ?3",
+                    params![subject, body, id],
+                )?;
+            }
+            ArticleId::Comment(id) => {
+                tx.execute(
+                    "INSERT INTO revisions (kind, article_id, subject, body, replaced_at)
+                        SELECT 'comment', id, NULL, body, ?1 FROM comments WHERE id = ?2",
+                    params![edited_at, id],
+                )?;
+                tx.execute(
+                    "UPDATE comments SET body = COALESCE(?1, body), edited_at = ?2 WHERE id = ?3",
+                    params![body, edited_at, id],
+                )?;
+                tx.execute(
+                    "UPDATE search_index SET body = COALESCE(?1, body)
+                        WHERE kind = 'comment' AND article_id = ?2",
+                    params![body, id],
+                )?;
+            }
+        }
+
+        Ok(tx.commit()?)
+    }
+
+    fn revisions(&self, article: ArticleId) -> StorageResult<Vec<RevisionRow>> {
+        let (kind, id) = kind_and_id(article);
+
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT subject, body, replaced_at FROM revisions
+                    WHERE kind = ?1 AND article_id = ?2 ORDER BY id",
+        )?;
+
+        let rows = stmt.query_map(params![kind, id], |row| {
+            Ok(RevisionRow {
+                subject: row.get(0)?,
+                body: row.get(1)?,
+                replaced_at: row.get(2)?,
+            })
+        })?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn cancel_article(&self, article: ArticleId, by: &str, at: i64) -> StorageResult<()> {
+        let sql = match article {
+            ArticleId::Post(_) => {
+                "UPDATE posts SET cancelled_at = ?1, cancelled_by = ?2 WHERE id = ?3"
+            }
+            ArticleId::Comment(_) => {
+                "UPDATE comments SET cancelled_at = ?1, cancelled_by = ?2 WHERE id = ?3"
+            }
+        };
+        let (kind, id) = kind_and_id(article);
+
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        tx.execute(sql, params![at, by, id])?;
+        // cancelled articles leave no trace in searches, long before they are purged
+        tx.execute(
+            "DELETE FROM search_index WHERE kind = ?1 AND article_id = ?2",
+            params![kind, id],
+        )?;
+
+        Ok(tx.commit()?)
+    }
+
+    fn purge_cancelled(&self) -> StorageResult<usize> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+
+        // articles that were purged before have no author left
+        tx.execute_batch(
+            "DELETE FROM revisions WHERE
+                (kind = 'post' AND article_id IN
+                    (SELECT id FROM posts WHERE cancelled_at IS NOT NULL))
+                OR (kind = 'comment' AND article_id IN
+                    (SELECT id FROM comments WHERE cancelled_at IS NOT NULL));",
+        )?;
+        let posts = tx.execute(
+            "UPDATE posts SET subject = '', body = '', author = '', author_email = ''
+                WHERE cancelled_at IS NOT NULL AND author != ''",
+            [],
+        )?;
+        let comments = tx.execute(
+            "UPDATE comments SET body = '', author = '', author_email = ''
+                WHERE cancelled_at IS NOT NULL AND author != ''",
+            [],
+        )?;
+
+        tx.commit()?;
+
+        Ok(posts + comments)
+    }
+
+    fn post(&self, id: i32) -> StorageResult<Option<PostRow>> {
+        Ok(self
+            .conn()
+            .query_row(
+                &format!("SELECT {} FROM posts WHERE id = ?1", POST_COLUMNS),
+                [id],
+                post_row,
+            )
+            .optional()?)
+    }
+
+    fn approve_post(&self, id: i32) -> StorageResult<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+
+        tx.execute("UPDATE posts SET pending = 0 WHERE id = ?1", [id])?;
+        publish_post(&tx, id)?;
+
+        Ok(tx.commit()?)
+    }
+
+    fn reject_post(&self, id: i32, reason: &str) -> StorageResult<()> {
+        self.conn().execute(
+            "UPDATE posts SET pending = 0, rejection = ?1 WHERE id = ?2",
+            params![reason, id],
+        )?;
+
+        Ok(())
+    }
+
+    fn pending_posts(&self, groups: &[String]) -> StorageResult<Vec<PostRow>> {
+        let placeholders = vec!["?"; groups.len()].join(", ");
+
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM posts WHERE pending = 1 AND group_name IN ({}) ORDER BY id",
+            POST_COLUMNS, placeholders
+        ))?;
+
+        let rows = stmt.query_map(params_from_iter(groups), post_row)?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn held_posts_of(&self, author: &str) -> StorageResult<Vec<PostRow>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM posts WHERE author = ?1 AND NOT ({}) ORDER BY id",
+            POST_COLUMNS, LISTED
+        ))?;
+
+        let rows = stmt.query_map([author], post_row)?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn posts_in_groups(&self, groups: &[String], query: &PostQuery) -> StorageResult<Vec<PostRow>> {
+        let placeholders = vec!["?"; groups.len()].join(", ");
+
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM posts
+                    WHERE group_name IN ({}) AND {} AND id > ? AND created_at >= ?
+                    ORDER BY id LIMIT ? OFFSET ?",
+            POST_COLUMNS, placeholders, LISTED
+        ))?;
+
+        let mut values: Vec<Value> = groups.iter().cloned().map(Value::Text).collect();
+        values.push(Value::Integer(query.after_id.unwrap_or(0).into()));
+        values.push(Value::Integer(query.since.unwrap_or(i64::MIN)));
+        // a negative limit means no limit to sqlite
+        values.push(Value::Integer(query.limit.map_or(-1, i64::from)));
+        values.push(Value::Integer(query.offset.into()));
+
+        let rows = stmt.query_map(params_from_iter(values), post_row)?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn comments_on(&self, parent_ids: &[i32]) -> StorageResult<Vec<CommentRow>> {
+        let placeholders = vec!["?"; parent_ids.len()].join(", ");
+
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM comments WHERE parent_id IN ({}) ORDER BY id",
+            COMMENT_COLUMNS, placeholders
+        ))?;
+
+        let rows = stmt.query_map(params_from_iter(parent_ids), comment_row)?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn search(&self, query: &SearchQuery) -> StorageResult<Vec<SearchRow>> {
+        // each term is quoted, making it a phrase to fts5 rather than query syntax
+        let terms: Vec<String> = query
+            .terms
+            .iter()
+            .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+            .collect();
+
+        let mut conditions = vec!["search_index MATCH ?".to_string()];
+        let mut values = vec![Value::Text(terms.join(" "))];
+
+        if let Some(groups) = &query.groups {
+            let placeholders = vec!["?"; groups.len()].join(", ");
+            conditions.push(format!("search_index.group_name IN ({})", placeholders));
+            values.extend(groups.iter().cloned().map(Value::Text));
+        }
+        if let Some(author) = &query.author {
+            conditions.push("search_index.author = ?".to_string());
+            values.push(Value::Text(author.clone()));
+        }
+        if let Some(since) = query.since {
+            conditions.push("search_index.created_at >= ?".to_string());
+            values.push(Value::Integer(since));
+        }
+        if let Some(until) = query.until {
+            conditions.push("search_index.created_at < ?".to_string());
+            values.push(Value::Integer(until));
+        }
+        values.push(Value::Integer(query.limit.into()));
+
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT search_index.kind, search_index.article_id, search_index.post_id,
+                    search_index.group_name,
+                    CASE WHEN posts.cancelled_at IS NULL THEN posts.subject ELSE '' END,
+                    search_index.author,
+                    search_index.created_at,
+                    snippet(search_index, -1, '[', ']', '...', 16)
+                FROM search_index JOIN posts ON posts.id = search_index.post_id
+                WHERE {} ORDER BY rank LIMIT ?",
+            conditions.join(" AND ")
+        ))?;
+
+        let rows = stmt.query_map(params_from_iter(values), |row| {
+            let kind: String = row.get(0)?;
+            let id: i32 = row.get(1)?;
+
+            Ok(SearchRow {
+                article: article_of_kind(&kind, id),
+                post_id: row.get(2)?,
+                group_name: row.get(3)?,
+                subject: row.get(4)?,
+                author: row.get(5)?,
+                created_at: row.get(6)?,
+                snippet: row.get(7)?,
+            })
+        })?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn article_numbers(
+        &self,
+        group: &str,
+        from: i64,
+        to: i64,
+    ) -> StorageResult<Vec<(i64, ArticleId)>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT number, kind, article_id FROM article_numbers
+                WHERE group_name = ?1 AND number BETWEEN ?2 AND ?3 ORDER BY number",
+        )?;
+
+        let rows = stmt.query_map(params![group, from, to], |row| {
+            let kind: String = row.get(1)?;
+            let id: i32 = row.get(2)?;
+
+            Ok((row.get(0)?, article_of_kind(&kind, id)))
+        })?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn count_articles(
+        &self,
+        group: &str,
+        from: i64,
+        to: i64,
+    ) -> StorageResult<(i64, Option<(i64, i64)>)> {
+        let conn = self.conn();
+        let (count, low, high): (i64, Option<i64>, Option<i64>) = conn.query_row(
+            "SELECT COUNT(*), MIN(number), MAX(number) FROM article_numbers
+                WHERE group_name = ?1 AND number BETWEEN ?2 AND ?3",
+            params![group, from, to],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+
+        Ok((count, low.zip(high)))
+    }
+
+    fn numbers_of(
+        &self,
+        group: &str,
+        articles: &[ArticleId],
+    ) -> StorageResult<Vec<(i64, ArticleId)>> {
+        if articles.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let placeholders = vec!["(?, ?)"; articles.len()].join(", ");
+        let mut values = vec![Value::Text(group.to_string())];
+        for article in articles {
+            let (kind, id) = kind_and_id(*article);
+            values.push(Value::Text(kind.to_string()));
+            values.push(Value::Integer(id.into()));
+        }
+
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT number, kind, article_id FROM article_numbers
+                WHERE group_name = ? AND (kind, article_id) IN (VALUES {})",
+            placeholders
+        ))?;
+
+        let rows = stmt.query_map(params_from_iter(values), |row| {
+            let kind: String = row.get(1)?;
+            let id: i32 = row.get(2)?;
+
+            Ok((row.get(0)?, article_of_kind(&kind, id)))
+        })?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn article_by_message_id(&self, message_id: &str) -> StorageResult<Option<ArticleId>> {
+        let conn = self.conn();
+
+        let post = conn
+            .query_row(
+                "SELECT id FROM posts WHERE message_id = ?1",
+                [message_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(id) = post {
+            return Ok(Some(ArticleId::Post(id)));
+        }
+
+        let comment = conn
+            .query_row(
+                "SELECT id FROM comments WHERE message_id = ?1",
+                [message_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(comment.map(ArticleId::Comment))
+    }
+
+    fn feed_after(&self, after: i64, limit: u32) -> StorageResult<Vec<(i64, ArticleId)>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT seq, kind, article_id FROM feed WHERE seq > ?1 ORDER BY seq LIMIT ?2",
+        )?;
+
+        let rows = stmt.query_map(params![after, limit], |row| {
+            let kind: String = row.get(1)?;
+            let id: i32 = row.get(2)?;
+
+            Ok((row.get(0)?, article_of_kind(&kind, id)))
+        })?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn feed_end(&self) -> StorageResult<i64> {
+        Ok(self
+            .conn()
+            .query_row("SELECT COALESCE(MAX(seq), 0) FROM feed", [], |row| {
+                row.get(0)
+            })?)
+    }
+
+    fn peer_progress(&self, peer: &str) -> StorageResult<i64> {
+        Ok(self
+            .conn()
+            .query_row(
+                "SELECT seq FROM peer_progress WHERE peer = ?1",
+                [peer],
+                |row| row.get(0),
+            )
+            .optional()?
+            .unwrap_or(0))
+    }
+
+    fn set_peer_progress(&self, peer: &str, progress: i64) -> StorageResult<()> {
+        self.conn().execute(
+            "INSERT INTO peer_progress (peer, seq) VALUES (?1, ?2)
+                ON CONFLICT (peer) DO UPDATE SET seq = excluded.seq",
+            params![peer, progress],
+        )?;
+
+        Ok(())
+    }
+
+    fn newsrc(&self, username: &str) -> StorageResult<Vec<NewsrcRow>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT group_name, subscribed, read FROM newsrc WHERE username = ?1
+                ORDER BY group_name",
+        )?;
+
+        let rows = stmt.query_map([username], |row| {
+            Ok(NewsrcRow {
+                group: row.get(0)?,
+                subscribed: row.get(1)?,
+                read: row.get(2)?,
+            })
+        })?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn set_newsrc(&self, username: &str, row: &NewsrcRow) -> StorageResult<()> {
+        self.conn().execute(
+            "INSERT INTO newsrc (username, group_name, subscribed, read) VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT (username, group_name)
+                DO UPDATE SET subscribed = excluded.subscribed, read = excluded.read",
+            params![username, row.group, row.subscribed, row.read],
+        )?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+//! wakes up `/watch` requests when new articles are listed. watchers only learn that something
+//! changed and look in the storage for what, so a missed or a spurious wake up costs nothing.
+//!
+//! every watcher holds a connection thread until it wakes up or times out, so only
+//! [`MAX_WATCHERS`] can wait at once, and [`MAX_WATCHERS_PER_CLIENT`] of them for the same address
+//! or the same user.
+
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+struct Changes {
+    // bumped every time articles are listed
+    generation: Mutex<u64>,
+    changed: Condvar,
+}
+
+static CHANGES: Changes = Changes {
+    generation: Mutex::new(0),
+    changed: Condvar::new(),
+};
+
+/// how many watchers can wait at once, whoever they are
+pub const MAX_WATCHERS: usize = 256;
+
+/// how many watchers the same address, or the same user, can have waiting at once
+pub const MAX_WATCHERS_PER_CLIENT: usize = 4;
+
+// the address and the user of every watcher waiting, as far as they are known
+static WATCHERS: Mutex<Vec<(Option<String>, Option<String>)>> = Mutex::new(Vec::new());
+
+fn watchers() -> MutexGuard<'static, Vec<(Option<String>, Option<String>)>> {
+    WATCHERS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// a place among the watchers, given up when it is dropped
+pub struct Watching {
+    watcher: (Option<String>, Option<String>),
+}
+
+impl Drop for Watching {
+    fn drop(&mut self) {
+        let mut watchers = watchers();
+
+        if let Some(index) = watchers.iter().position(|watcher| *watcher == self.watcher) {
+            watchers.swap_remove(index);
+        }
+    }
+}
+
+/// takes a place among the watchers for a client at `ip`, sent by `username`, or returns None if
+/// every place, or every one the client can have, is taken
+pub fn start(ip: Option<&str>, username: Option<&str>) -> Option<Watching> {
+    let mut watchers = watchers();
+    // usernames are the same whatever their case
+    let watcher = (ip.map(str::to_string), username.map(str::to_lowercase));
+
+    let same_ip = watchers
+        .iter()
+        .filter(|(other, _)| other.is_some() && *other == watcher.0)
+        .count();
+    let same_user = watchers
+        .iter()
+        .filter(|(_, other)| other.is_some() && *other == watcher.1)
+        .count();
+    let full = watchers.len() >= MAX_WATCHERS
+        || same_ip >= MAX_WATCHERS_PER_CLIENT
+        || same_user >= MAX_WATCHERS_PER_CLIENT;
+
+    if full {
+        return None;
+    }
+
+    watchers.push(watcher.clone());
+
+    Some(Watching { watcher })
+}
+
+fn lock() -> MutexGuard<'static, u64> {
+    CHANGES.generation.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// how many times articles were listed so far, to [`wait`] for the next time
+pub fn generation() -> u64 {
+    *lock()
+}
+
+/// wakes every watcher, after articles were listed
+pub fn notify() {
+    *lock() += 1;
+
+    CHANGES.changed.notify_all();
+}
+
+/// waits until articles are listed after `generation`, returning false if `timeout` passed first
+pub fn wait(generation: u64, timeout: Duration) -> bool {
+    let (current, _) = CHANGES
+        .changed
+        .wait_timeout_while(lock(), timeout, |current| *current == generation)
+        .unwrap_or_else(|e| e.into_inner());
+
+    *current != generation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn watchers_wake_up_on_changes() {
+        let before = generation();
+        let notifier = thread::spawn(|| {
+            thread::sleep(Duration::from_millis(50));
+            notify();
+        });
+
+        assert!(wait(before, Duration::from_secs(10)));
+        // a change the watcher did not see yet does not make it wait at all
+        assert!(wait(before, Duration::ZERO));
+
+        notifier.join().unwrap();
+    }
+
+    #[test]
+    fn clients_only_get_a_few_watchers_each() {
+        let mine: Vec<Watching> = (0..MAX_WATCHERS_PER_CLIENT)
+            .map(|_| start(Some("192.0.2.1"), None).unwrap())
+            .collect();
+        assert!(start(Some("192.0.2.1"), Some("ferris")).is_none());
+
+        let ferris: Vec<Watching> = (0..MAX_WATCHERS_PER_CLIENT)
+            .map(|i| start(Some(&format!("192.0.2.{}", i + 2)), Some("ferris")).unwrap())
+            .collect();
+        assert!(start(Some("192.0.2.99"), Some("FERRIS")).is_none());
+
+        drop(mine);
+        assert!(start(Some("192.0.2.1"), None).is_some());
+        drop(ferris);
+        assert!(start(None, Some("ferris")).is_some());
+    }
+}
@@ -0,0 +1,205 @@
+//! wildmats, the group patterns of NNTP (RFC 3977, section 4), like `comp.lang.*` or
+//! `comp.*,!comp.lang.java`.
+//!
+//! a wildmat is a comma separated list of patterns, each optionally negated with `!`. a name
+//! matches if the last pattern that matches it is not negated. within a pattern `*` matches any
+//! run of characters, `?` any single character, `[...]` any character of a set (or, as `[^...]`,
+//! any character not in it) and `\` takes the next character literally.
+
+/// a parsed wildmat
+#[derive(Debug, Clone, PartialEq)]
+pub struct Wildmat {
+    patterns: Vec<(bool, Vec<Token>)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(char),
+    Any,
+    Star,
+    /// whether the set is negated, and its ranges (a single character is a range of one)
+    Set(bool, Vec<(char, char)>),
+}
+
+impl Wildmat {
+    pub fn parse(wildmat: &str) -> Result<Wildmat, String> {
+        let mut patterns = vec![];
+
+        for pattern in wildmat.split(',') {
+            let (negated, pattern) = match pattern.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, pattern),
+            };
+
+            if pattern.is_empty() {
+                return Err(format!("{} has an empty pattern", wildmat));
+            }
+
+            patterns.push((negated, tokenize(pattern)?));
+        }
+
+        Ok(Wildmat { patterns })
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        let name: Vec<char> = name.chars().collect();
+
+        self.patterns
+            .iter()
+            .rev()
+            .find(|(_, tokens)| match_tokens(tokens, &name))
+            .is_some_and(|(negated, _)| !negated)
+    }
+
+    /// the single group the wildmat names, if it is a plain group name without any wildcards
+    pub fn literal(&self) -> Option<String> {
+        match self.patterns.as_slice() {
+            [(false, tokens)] => tokens
+                .iter()
+                .map(|token| match token {
+                    Token::Literal(c) => Some(*c),
+                    _ => None,
+                })
+                .collect(),
+            _ => None,
+        }
+    }
+}
+
+fn tokenize(pattern: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = pattern.chars();
+
+    while let Some(c) = chars.next() {
+        tokens.push(match c {
+            '*' => Token::Star,
+            '?' => Token::Any,
+            '\\' => match chars.next() {
+                Some(c) => Token::Literal(c),
+                None => return Err(format!("{} ends with a lone \\", pattern)),
+            },
+            '[' => {
+                let negated = chars.as_str().starts_with('^');
+                if negated {
+                    chars.next();
+                }
+
+                let mut ranges = vec![];
+                // a `]` straight after the opening bracket is part of the set
+                let mut first = true;
+
+                loop {
+                    let start = match chars.next() {
+                        Some(']') if !first => break,
+                        Some(c) => c,
+                        None => return Err(format!("{} has an unclosed [", pattern)),
+                    };
+                    first = false;
+
+                    let rest = chars.as_str();
+                    match (rest.strip_prefix('-'), rest.chars().nth(1)) {
+                        (Some(_), Some(end)) if end != ']' => {
+                            chars.next();
+                            chars.next();
+                            ranges.push((start, end));
+                        }
+                        _ => ranges.push((start, start)),
+                    }
+                }
+
+                Token::Set(negated, ranges)
+            }
+            c => Token::Literal(c),
+        });
+    }
+
+    Ok(tokens)
+}
+
+fn match_tokens(tokens: &[Token], name: &[char]) -> bool {
+    match tokens.split_first() {
+        None => name.is_empty(),
+        Some((Token::Star, rest)) => (0..=name.len()).any(|skip| match_tokens(rest, &name[skip..])),
+        Some((token, rest)) => match name.split_first() {
+            Some((c, name)) => match_char(token, *c) && match_tokens(rest, name),
+            None => false,
+        },
+    }
+}
+
+fn match_char(token: &Token, c: char) -> bool {
+    match token {
+        Token::Literal(literal) => *literal == c,
+        Token::Any => true,
+        Token::Set(negated, ranges) => {
+            ranges
+                .iter()
+                .any(|(start, end)| (*start..=*end).contains(&c))
+                != *negated
+        }
+        Token::Star => unreachable!("stars are matched by match_tokens"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(wildmat: &str, name: &str) -> bool {
+        Wildmat::parse(wildmat).unwrap().matches(name)
+    }
+
+    #[test]
+    fn matches_wildcards_and_sets() {
+        assert!(matches("comp.lang.rust", "comp.lang.rust"));
+        assert!(!matches("comp.lang.rust", "comp.lang.rusty"));
+
+        assert!(matches("comp.lang.*", "comp.lang.rust"));
+        assert!(matches("comp.*", "comp.lang.rust"));
+        assert!(!matches("comp.lang.*", "comp.lang"));
+        assert!(matches("*", "alt.test"));
+
+        assert!(matches("comp.lang.?", "comp.lang.c"));
+        assert!(!matches("comp.lang.?", "comp.lang.go"));
+
+        assert!(matches("comp.lang.[cd]", "comp.lang.d"));
+        assert!(matches("alt.[a-c]*", "alt.binaries"));
+        assert!(!matches("alt.[^a-c]*", "alt.binaries"));
+        assert!(matches("comp.lang.\\*", "comp.lang.*"));
+        assert!(!matches("comp.lang.\\*", "comp.lang.c"));
+    }
+
+    #[test]
+    fn the_last_matching_pattern_wins() {
+        assert!(matches("comp.*,!comp.lang.java", "comp.lang.rust"));
+        assert!(!matches("comp.*,!comp.lang.java", "comp.lang.java"));
+        assert!(matches(
+            "comp.*,!comp.lang.*,comp.lang.rust",
+            "comp.lang.rust"
+        ));
+        assert!(!matches(
+            "comp.*,!comp.lang.*,comp.lang.rust",
+            "comp.lang.c"
+        ));
+        assert!(!matches("!comp.lang.java", "comp.lang.rust"));
+    }
+
+    #[test]
+    fn only_plain_names_are_literal() {
+        let literal = |wildmat: &str| Wildmat::parse(wildmat).unwrap().literal();
+
+        assert_eq!(
+            literal("comp.lang.rust"),
+            Some("comp.lang.rust".to_string())
+        );
+        assert_eq!(literal("comp.lang.*"), None);
+        assert_eq!(literal("comp.lang.c,comp.lang.rust"), None);
+    }
+
+    #[test]
+    fn rejects_malformed_wildmats() {
+        for wildmat in ["", "comp.*,", "comp.lang.[ab", "comp.lang\\", "!"] {
+            assert!(Wildmat::parse(wildmat).is_err(), "{}", wildmat);
+        }
+    }
+}